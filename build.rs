@@ -0,0 +1,53 @@
+use std::{fs, path::Path, process::Command};
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_COMMIT={commit}");
+
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    for (package, key) in [
+        ("twee-v3", "TWEE_V3_VERSION"),
+        ("serenity", "SERENITY_VERSION"),
+    ] {
+        let version = locked_version(&lock, package).unwrap_or_else(|| "unknown".to_string());
+        println!("cargo:rustc-env={key}={version}");
+    }
+
+    // `HEAD` only changes when switching branches, a commit updates the ref it points to, which
+    // can also be packed. Missing files would rerun the script on every build.
+    let head_ref = fs::read_to_string(".git/HEAD").ok().and_then(|head| {
+        head.trim()
+            .strip_prefix("ref: ")
+            .map(|path| format!(".git/{path}"))
+    });
+    for path in [
+        Some(".git/HEAD".to_string()),
+        head_ref,
+        Some(".git/packed-refs".to_string()),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|path| Path::new(path).exists())
+    {
+        println!("cargo:rerun-if-changed={path}");
+    }
+    println!("cargo:rerun-if-changed=Cargo.lock");
+}
+
+/// Find the version of a package in the `[[package]]` entries of the lock file.
+fn locked_version(lock: &str, package: &str) -> Option<String> {
+    let name = format!("name = \"{package}\"");
+    let mut lines = lock.lines();
+    lines.find(|line| line.trim() == name)?;
+    lines
+        .next()
+        .and_then(|line| line.trim().strip_prefix("version = "))
+        .map(|version| version.trim_matches('"').to_string())
+}
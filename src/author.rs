@@ -0,0 +1,677 @@
+use anyhow::Result;
+use serenity::{
+    model::prelude::{
+        interaction::{
+            application_command::ApplicationCommandInteraction, InteractionResponseType,
+        },
+        AttachmentType, GuildId, UserId,
+    },
+    prelude::Context,
+};
+
+use crate::{
+    error::UserError,
+    interaction::{
+        bool_option, error_interaction, integer_option, string_option, text_interaction,
+    },
+    persistance::{now, SaveStory},
+    play::{choose_start_passage, guild_emojis, preview_passage, start_passage},
+    utils::{
+        ending_passages, incoming_links, outgoing_links, passages_info, path_mismatch,
+        story_to_dot, story_to_mermaid, validate_story, Grade,
+    },
+    Handler,
+};
+
+/// How many days `/analytics` looks back at when not told otherwise.
+const DEFAULT_ANALYTICS_DAYS: i64 = 30;
+
+/// How many passages `/visits` lists, for the most and the least visited.
+const MAX_LISTED_VISITS: usize = 5;
+
+/// How many problems `/validatestory` lists.
+const MAX_LISTED_FINDINGS: usize = 30;
+
+/// How many links `/links` lists, of each direction.
+const MAX_LISTED_LINKS: usize = 15;
+
+/// How many passages `/dropoffs` lists.
+const MAX_LISTED_DROPOFFS: usize = 10;
+
+/// The id of the story named in the `story` option of the command.
+async fn story_option(handler: &Handler, command: &ApplicationCommandInteraction) -> Result<i64> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let name = string_option(command, "story").ok_or(UserError::UnknownStory)?;
+
+    let storage = handler.storage.lock().await;
+    Ok(storage
+        .find_guild_story(&guild_id, name)?
+        .ok_or(UserError::UnknownStory)?)
+}
+
+pub async fn endings_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = endings_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn endings_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let story_id = story_option(handler, command).await?;
+    let content = handler.storage.lock().await.get_story_content(story_id)?;
+
+    let endings = ending_passages(&content);
+    let text = if endings.is_empty() {
+        "This story has no ending, every passage links to another one.".to_string()
+    } else {
+        endings
+            .iter()
+            .map(|(title, tagged)| match tagged {
+                true => format!("✅ `{title}`"),
+                false => format!("⚠️ `{title}`, not tagged `ending`, is it a dead end?"),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    text_interaction(text, ctx, command).await;
+
+    Ok(())
+}
+
+pub async fn story_map_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = story_map_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn story_map_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let story_id = story_option(handler, command).await?;
+    let content = handler.storage.lock().await.get_story_content(story_id)?;
+
+    // Maps of big stories don't fit in a message, so they are sent as a file.
+    let (map, filename) = match string_option(command, "format") {
+        Some("mermaid") => (story_to_mermaid(&content), "story.mmd"),
+        _ => (story_to_dot(&content), "story.dot"),
+    };
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| {
+                    data.add_file(AttachmentType::Bytes {
+                        data: map.into_bytes().into(),
+                        filename: filename.to_string(),
+                    })
+                    .ephemeral(true)
+                })
+        })
+        .await?;
+
+    Ok(())
+}
+
+pub async fn set_start_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = set_start_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn set_start_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let story_id = story_option(handler, command).await?;
+    let passage = string_option(command, "passage").map(str::trim);
+
+    let mut storage = handler.storage.lock().await;
+    let story = storage.get_story(story_id)?;
+    let text = match passage {
+        Some(passage) => {
+            choose_start_passage(&storage, story_id, &story, passage)?;
+            format!("New sessions of this story now start from `{passage}`")
+        }
+        None => {
+            let start = start_passage(&storage, story_id, &story)?;
+            format!("Sessions of this story start from `{start}`")
+        }
+    };
+    drop(storage);
+
+    text_interaction(text, ctx, command).await;
+
+    Ok(())
+}
+
+pub async fn set_free_navigation_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = set_free_navigation_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn set_free_navigation_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let story_id = story_option(handler, command).await?;
+
+    let storage = handler.storage.lock().await;
+    let free_navigation = match bool_option(command, "enabled") {
+        Some(free_navigation) => {
+            storage.set_free_navigation(story_id, free_navigation)?;
+            free_navigation
+        }
+        None => storage.get_free_navigation(story_id)?,
+    };
+    drop(storage);
+
+    let text = if free_navigation {
+        "Players of this story can jump to any passage with `/jumpto`"
+    } else {
+        "Players of this story can only follow its links"
+    };
+    text_interaction(text, ctx, command).await;
+
+    Ok(())
+}
+
+pub async fn set_series_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = set_series_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn set_series_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let story_id = story_option(handler, command).await?;
+    let series = string_option(command, "series")
+        .map(|series| series.trim().to_string())
+        .filter(|series| !series.is_empty())
+        .ok_or(UserError::UnknownSeries)?;
+    let position = integer_option(command, "position");
+
+    handler
+        .storage
+        .lock()
+        .await
+        .set_series_position(&guild_id, &series, story_id, position)?;
+
+    let text = match position {
+        Some(position) => format!("Story put at position {position} of the series `{series}`"),
+        None => format!("Story removed from the series `{series}`"),
+    };
+    text_interaction(text, ctx, command).await;
+
+    Ok(())
+}
+
+pub async fn set_path_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = set_path_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+/// Record the passages the administrator went through in their session of the story, from its
+/// start or the passage they went to with `/goto`, up to the current one.
+async fn set_path_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let player_id = command.user.id.to_string();
+    let story_id = story_option(handler, command).await?;
+
+    let mut storage = handler.storage.lock().await;
+    let game_state = storage
+        .retrieve_game_state(&player_id, &guild_id)
+        .ok()
+        .filter(|game_state| game_state.story_id == story_id)
+        .ok_or(UserError::NotPlayingStory)?;
+    let mut path = game_state.history;
+    path.push(game_state.current_chapter);
+    let story = storage.get_story(story_id)?;
+    if let Some(reason) = path_mismatch(&story, &path) {
+        return Err(UserError::PathMismatch(reason).into());
+    }
+    storage.set_canonical_path(story_id, &path)?;
+    drop(storage);
+
+    text_interaction(
+        format!(
+            "Recorded a path of {} passages, from `{}` to `{}`",
+            path.len(),
+            path[0],
+            path[path.len() - 1]
+        ),
+        ctx,
+        command,
+    )
+    .await;
+
+    Ok(())
+}
+
+pub async fn transfer_story_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = transfer_story_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn transfer_story_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let story_id = story_option(handler, command).await?;
+    let target_guild_id = string_option(command, "server")
+        .and_then(|id| id.trim().parse::<u64>().ok())
+        .map(GuildId)
+        .ok_or(UserError::NotGuildAdmin)?;
+    let replace = bool_option(command, "replace").unwrap_or(false);
+
+    if !is_guild_admin(ctx, target_guild_id, command.user.id).await {
+        return Err(UserError::NotGuildAdmin.into());
+    }
+
+    let saved = handler.storage.lock().await.copy_story_to_guild(
+        story_id,
+        &target_guild_id.to_string(),
+        replace,
+    )?;
+    let text = match saved {
+        SaveStory::New => "Story copied to the server",
+        SaveStory::Update => "Story copied to the server, replacing the story of the same name",
+    };
+
+    text_interaction(text, ctx, command).await;
+
+    Ok(())
+}
+
+/// Whether the user is an administrator of the guild. False when the bot isn't on the guild, as
+/// it can't tell.
+async fn is_guild_admin(ctx: &Context, guild_id: GuildId, user_id: UserId) -> bool {
+    let Ok(guild) = ctx.http.get_guild(guild_id.0).await else {
+        return false;
+    };
+    guild
+        .member_permissions(ctx, user_id)
+        .await
+        .is_ok_and(|permissions| permissions.administrator())
+}
+
+pub async fn bug_reports_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = bug_reports_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn bug_reports_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let story_id = story_option(handler, command).await?;
+    let reports = handler
+        .storage
+        .lock()
+        .await
+        .list_bug_reports(&guild_id, story_id)?;
+
+    let text = if reports.is_empty() {
+        "No problem was reported about this story.".to_string()
+    } else {
+        reports
+            .iter()
+            .map(|report| {
+                let mut line = format!(
+                    "<t:{}:R> by <@{}> on `{}`",
+                    report.created_at, report.player_id, report.passage
+                );
+                if let Some(broken_link) = &report.broken_link {
+                    line.push_str(&format!(", links to the missing `{broken_link}`"));
+                }
+                if let Some(description) = &report.description {
+                    line.push_str(&format!(": {description}"));
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    text_interaction(text, ctx, command).await;
+
+    Ok(())
+}
+
+pub async fn visits_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = visits_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn visits_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let story_id = story_option(handler, command).await?;
+    let (mut counts, content) = {
+        let mut storage = handler.storage.lock().await;
+        (
+            storage.passage_visit_counts(&guild_id, story_id)?,
+            storage.get_story_content(story_id)?,
+        )
+    };
+
+    // Passages nobody reached are where players drop off the most.
+    for passage in passages_info(&content) {
+        if !counts.iter().any(|(title, _)| *title == passage.title) {
+            counts.push((passage.title, 0));
+        }
+    }
+
+    let list = |counts: &[(String, i64)]| {
+        counts
+            .iter()
+            .map(|(title, count)| format!("`{title}`: {count}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let text = if counts.iter().all(|(_, count)| *count == 0) {
+        "Nobody played this story yet.".to_string()
+    } else {
+        let listed = counts.len().min(MAX_LISTED_VISITS);
+        let least_visited: Vec<_> = counts.iter().rev().take(listed).cloned().collect();
+        format!(
+            "Most visited passages:\n{}\n\nLeast visited passages:\n{}",
+            list(&counts[..listed]),
+            list(&least_visited)
+        )
+    };
+
+    text_interaction(text, ctx, command).await;
+    Ok(())
+}
+
+pub async fn dropoffs_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = dropoffs_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn dropoffs_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let story_id = story_option(handler, command).await?;
+    let dropoffs = handler.storage.lock().await.dropoff_report(story_id)?;
+
+    let text = if dropoffs.is_empty() {
+        "Nobody stopped playing this story before its end.".to_string()
+    } else {
+        dropoffs
+            .iter()
+            .take(MAX_LISTED_DROPOFFS)
+            .map(|dropoff| {
+                let players = dropoff.stopped + dropoff.idle;
+                let players = match players {
+                    1 => "1 player".to_string(),
+                    players => format!("{players} players"),
+                };
+                format!(
+                    "{players} stopped at passage `{}` ({} with `/stop` or `/clearsession`, {} idle for a week).",
+                    dropoff.passage, dropoff.stopped, dropoff.idle
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    text_interaction(text, ctx, command).await;
+    Ok(())
+}
+
+pub async fn preview_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = preview_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn preview_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let story_id = story_option(handler, command).await?;
+    let passage = string_option(command, "passage").unwrap_or_default().trim();
+    let emojis = guild_emojis(ctx, &guild_id);
+    let (embed, components, file) = preview_passage(
+        &mut *handler.storage.lock().await,
+        &guild_id,
+        story_id,
+        passage,
+        emojis,
+    )?;
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| {
+                    if let Some(file) = file {
+                        data.add_file(file);
+                    }
+                    data.set_embed(embed)
+                        .set_components(components)
+                        .ephemeral(true)
+                })
+        })
+        .await?;
+
+    Ok(())
+}
+
+pub async fn validate_story_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = validate_story_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn validate_story_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let story_id = story_option(handler, command).await?;
+    let content = handler.storage.lock().await.get_story_content(story_id)?;
+
+    let report = validate_story(&content);
+    let text = match report.grade() {
+        Grade::Pass => "Grade: pass, no problem found.".to_string(),
+        grade => format!("Grade: {grade}\n{}", report.summary(MAX_LISTED_FINDINGS)),
+    };
+
+    text_interaction(text, ctx, command).await;
+    Ok(())
+}
+
+pub async fn links_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = links_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn links_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let story_id = story_option(handler, command).await?;
+    let passage = string_option(command, "passage").unwrap_or_default().trim();
+    let content = handler.storage.lock().await.get_story_content(story_id)?;
+
+    let outgoing = outgoing_links(&content, passage)
+        .ok_or_else(|| UserError::UnknownPassage(passage.to_string()))?;
+    let titles: Vec<String> = passages_info(&content)
+        .into_iter()
+        .map(|info| info.title)
+        .collect();
+    let incoming = incoming_links(&content, passage);
+
+    let outgoing = match outgoing.is_empty() {
+        true => "None, the story ends here.".to_string(),
+        false => listed_lines(
+            outgoing.iter().map(|(text, target)| {
+                let missing = if titles.contains(target) {
+                    ""
+                } else {
+                    ", missing"
+                };
+                format!("`{text}` → `{target}`{missing}")
+            }),
+            MAX_LISTED_LINKS,
+        ),
+    };
+    let incoming = match incoming.is_empty() {
+        true => "None, players can't reach it by following links.".to_string(),
+        false => listed_lines(
+            incoming
+                .iter()
+                .map(|(from, text)| format!("`{from}`, by `{text}`")),
+            MAX_LISTED_LINKS,
+        ),
+    };
+
+    text_interaction(
+        format!("**Links from `{passage}`**\n{outgoing}\n\n**Links to `{passage}`**\n{incoming}"),
+        ctx,
+        command,
+    )
+    .await;
+    Ok(())
+}
+
+/// The lines, one per entry, the ones past `max` summed up in a last line.
+fn listed_lines(lines: impl ExactSizeIterator<Item = String>, max: usize) -> String {
+    let count = lines.len();
+    let mut listed: Vec<String> = lines.take(max).collect();
+    if count > max {
+        listed.push(format!("And {} more", count - max));
+    }
+    listed.join("\n")
+}
+
+pub async fn analytics_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = analytics_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn analytics_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let days = integer_option(command, "days").unwrap_or(DEFAULT_ANALYTICS_DAYS);
+    let since = now() - days * 24 * 60 * 60;
+    let csv = handler
+        .storage
+        .lock()
+        .await
+        .analytics_csv(&guild_id, since)?;
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| {
+                    data.content(format!("The stories played in the last {days} days"))
+                        .add_file(AttachmentType::Bytes {
+                            data: csv.into_bytes().into(),
+                            filename: "analytics.csv".to_string(),
+                        })
+                        .ephemeral(true)
+                })
+        })
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use std::{future::Future, pin::Pin, time::Duration};
+
+/// The waits of the bot, like the delay before showing the next passage.
+pub trait Clock: Send + Sync {
+    /// Wait for the duration.
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+/// Real time, from the tokio timer.
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
@@ -12,7 +12,7 @@ struct Access<K> {
 }
 impl<K> PartialOrd for Access<K> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.instant.cmp(&other.instant).reverse())
+        Some(self.cmp(other))
     }
 }
 impl<K> Ord for Access<K> {
@@ -67,9 +67,10 @@ impl<K: Eq + Hash + Clone, V> ExpiringHashMap<K, V> {
     }
 
     fn cleanup(&mut self) {
-        let deadline = Instant::now()
-            .checked_sub(self.duration)
-            .expect("We use duration in minutes");
+        // Nothing can have expired yet when the clock started less than `duration` ago.
+        let Some(deadline) = Instant::now().checked_sub(self.duration) else {
+            return;
+        };
         while let Some(Access { instant, .. }) = self.access_log.peek() {
             if *instant > deadline {
                 return;
@@ -105,6 +106,13 @@ impl<K: Eq + Hash + Clone, V> ExpiringHashMap<K, V> {
         }
     }
 
+    /// Like [`ExpiringHashMap::get`], but without updating the last access of the entry:
+    /// peeking doesn't keep an entry alive, and it expires as if it was never read.
+    pub fn peek(&mut self, k: &K) -> Option<&V> {
+        self.cleanup();
+        self.map.get(k).map(|Value { value, .. }| value)
+    }
+
     pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
     where
         K: Borrow<Q>,
@@ -112,4 +120,80 @@ impl<K: Eq + Hash + Clone, V> ExpiringHashMap<K, V> {
     {
         self.map.remove(k).map(|Value { value, .. }| value)
     }
+
+    /// The number of entries that are not expired yet.
+    pub fn len(&mut self) -> usize {
+        self.cleanup();
+        self.map.len()
+    }
+
+    /// Remove every entry, returning how many were still alive.
+    /// The access log is emptied too, so no stale key is left behind.
+    pub fn clear(&mut self) -> usize {
+        let count = self.len();
+        self.map.clear();
+        self.access_log.clear();
+        count
+    }
+
+    /// Remove every entry that is not expired yet, returning them.
+    pub fn drain(&mut self) -> Vec<(K, V)> {
+        self.cleanup();
+        self.access_log.clear();
+        self.map
+            .drain()
+            .map(|(key, Value { value, .. })| (key, value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn len_excludes_expired_entries() {
+        let mut map = ExpiringHashMap::new(TTL);
+        map.insert("old", 1);
+        sleep(TTL * 2);
+        map.insert("new", 2);
+
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&"old"), None);
+        assert_eq!(map.get(&"new"), Some(&2));
+    }
+
+    #[test]
+    fn clear_returns_the_live_entries() {
+        let mut map = ExpiringHashMap::new(TTL);
+        map.insert("old", 1);
+        sleep(TTL * 2);
+        map.insert("first", 2);
+        map.insert("second", 3);
+
+        assert_eq!(map.clear(), 2);
+        assert_eq!(map.len(), 0);
+        assert!(map.access_log.is_empty());
+        assert_eq!(map.clear(), 0);
+    }
+
+    #[test]
+    fn peek_does_not_prevent_expiry() {
+        let ttl = TTL * 4;
+        let mut map = ExpiringHashMap::new(ttl);
+        map.insert("peeked", 1);
+        map.insert("read", 2);
+
+        sleep(ttl * 3 / 5);
+        assert_eq!(map.peek(&"peeked"), Some(&1));
+        assert_eq!(map.get(&"read"), Some(&2));
+
+        sleep(ttl * 3 / 5);
+        assert_eq!(map.peek(&"peeked"), None);
+        assert_eq!(map.peek(&"read"), Some(&2));
+    }
 }
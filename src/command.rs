@@ -1,6 +1,12 @@
+use serde_json::{json, Value};
 use serenity::{
-    builder::{CreateApplicationCommand, CreateApplicationCommands},
-    model::{prelude::command::CommandOptionType, Permissions},
+    builder::{
+        CreateApplicationCommand, CreateApplicationCommandOption, CreateApplicationCommands,
+    },
+    model::{
+        prelude::command::{Command, CommandOptionType},
+        Permissions,
+    },
 };
 
 pub trait SlashCommand {
@@ -29,6 +35,13 @@ impl SlashCommand for UploadStoryCommand {
                     .required(true)
                     .description("The story to upload")
             })
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::Boolean)
+                    .name("dry_run")
+                    .required(false)
+                    .description("Only check the story and what uploading it would do")
+            })
     }
 }
 
@@ -47,6 +60,28 @@ impl SlashCommand for DeleteStoryCommand {
     }
 }
 
+pub struct RestoreStoryCommand;
+
+impl SlashCommand for RestoreStoryCommand {
+    const NAME: &'static str = "restorestory";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Bring back a deleted story, or list the deleted stories")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("story")
+                    .required(false)
+                    .description("The title of the deleted story")
+            })
+    }
+}
+
 pub struct PlayCommand;
 
 impl SlashCommand for PlayCommand {
@@ -61,6 +96,180 @@ impl SlashCommand for PlayCommand {
     }
 }
 
+pub struct SearchCommand;
+
+impl SlashCommand for SearchCommand {
+    const NAME: &'static str = "search";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Search the stories of the server")
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("query")
+                    .required(true)
+                    .description("What to look for in the titles")
+            })
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::Boolean)
+                    .name("content")
+                    .required(false)
+                    .description("Also look into the text of the stories")
+            })
+    }
+}
+
+pub struct ClearSessionCommand;
+
+impl SlashCommand for ClearSessionCommand {
+    const NAME: &'static str = "clearsession";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Stop the story a player is playing on this server")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::User)
+                    .name("player")
+                    .required(true)
+                    .description("The player whose session to clear")
+            })
+    }
+}
+
+pub struct SessionsCommand;
+
+impl SlashCommand for SessionsCommand {
+    const NAME: &'static str = "sessions";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("List the stories being played on this server, and by whom")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+    }
+}
+
+pub struct SetLanguageCommand;
+
+impl SlashCommand for SetLanguageCommand {
+    const NAME: &'static str = "setlanguage";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Set the default language of the bot on this server")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("language")
+                    .required(false)
+                    .description(
+                        "A language code like `en` or `pt-BR`, leave empty to see the current one",
+                    )
+            })
+    }
+}
+
+pub struct SetLayoutCommand;
+
+impl SlashCommand for SetLayoutCommand {
+    const NAME: &'static str = "setlayout";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Set how the choices of a passage are shown on this server")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("layout")
+                    .required(false)
+                    .description("Leave empty to see the current one")
+                    .add_string_choice("Button for a single choice, menu for more", "auto")
+                    .add_string_choice("Buttons, as long as they fit", "buttons")
+                    .add_string_choice("Always a menu", "menu")
+            })
+    }
+}
+
+pub struct SetSingleMessageCommand;
+
+impl SlashCommand for SetSingleMessageCommand {
+    const NAME: &'static str = "setsinglemessage";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Set whether stories show every passage in the same message on this server")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::Boolean)
+                    .name("enabled")
+                    .required(false)
+                    .description("Applies to the sessions started after, leave empty to see the current setting")
+            })
+    }
+}
+
+pub struct SetDelayCommand;
+
+impl SlashCommand for SetDelayCommand {
+    const NAME: &'static str = "setdelay";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Set how long to wait before showing the next passage, for pacing")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::Integer)
+                    .name("milliseconds")
+                    .required(false)
+                    .min_int_value(0)
+                    .max_int_value(10000)
+                    .description("0 to show it right away, leave empty to see the current setting")
+            })
+    }
+}
+
+pub struct StoriesCommand;
+
+impl SlashCommand for StoriesCommand {
+    const NAME: &'static str = "stories";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("List the stories you can play")
+    }
+}
+
 pub struct StopCommand;
 
 impl SlashCommand for StopCommand {
@@ -75,14 +284,780 @@ impl SlashCommand for StopCommand {
     }
 }
 
-pub trait SlashCommandCreator {
-    fn create_slash_command<S: SlashCommand>(&mut self) -> &mut Self;
+pub struct VersionCommand;
+
+impl SlashCommand for VersionCommand {
+    const NAME: &'static str = "version";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Show the version of the bot, handy for bug reports")
+    }
 }
 
-impl SlashCommandCreator for CreateApplicationCommands {
-    fn create_slash_command<S: SlashCommand>(&mut self) -> &mut Self {
-        self.create_application_command(|command| {
-            S::create_application_command(command).dm_permission(false)
-        })
+pub struct EndingsCommand;
+
+impl SlashCommand for EndingsCommand {
+    const NAME: &'static str = "endings";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("List the passages of a story that end it")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+    }
+}
+
+pub struct SetStartCommand;
+
+impl SlashCommand for SetStartCommand {
+    const NAME: &'static str = "setstart";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Set the passage a story starts from")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("passage")
+                    .required(false)
+                    .description("The title of the passage, leave empty to see the current one")
+            })
+    }
+}
+
+pub struct SetFreeNavigationCommand;
+
+impl SlashCommand for SetFreeNavigationCommand {
+    const NAME: &'static str = "setfreenavigation";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Set whether players can jump to any passage of a story with /jumpto")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::Boolean)
+                    .name("enabled")
+                    .required(false)
+                    .description("Leave empty to see the current setting")
+            })
+    }
+}
+
+pub struct JumpToCommand;
+
+impl SlashCommand for JumpToCommand {
+    const NAME: &'static str = "jumpto";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Jump to a passage of the story you are playing, if it allows it")
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("query")
+                    .required(true)
+                    .description("Part of the title of the passage")
+            })
+    }
+}
+
+pub struct SeriesCommand;
+
+impl SlashCommand for SeriesCommand {
+    const NAME: &'static str = "series";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("List the series of stories, or the stories of a series to play in order")
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("name")
+                    .required(false)
+                    .description("The name of the series")
+            })
+    }
+}
+
+pub struct SetSeriesCommand;
+
+impl SlashCommand for SetSeriesCommand {
+    const NAME: &'static str = "setseries";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Put a story in a series at a position, or remove it from the series")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("series")
+                    .required(true)
+                    .description("The name of the series, created if needed")
+            })
+            .create_option(story_option)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::Integer)
+                    .name("position")
+                    .required(false)
+                    .description("Where the story comes in the series, leave empty to remove it")
+            })
+    }
+}
+
+pub struct SetPathCommand;
+
+impl SlashCommand for SetPathCommand {
+    const NAME: &'static str = "setpath";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Record the passages of your session of a story as its recommended path")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+    }
+}
+
+pub struct DemoCommand;
+
+impl SlashCommand for DemoCommand {
+    const NAME: &'static str = "demo";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Watch the recommended path of a story play by itself")
+            .create_option(story_option)
+    }
+}
+
+pub struct TransferStoryCommand;
+
+impl SlashCommand for TransferStoryCommand {
+    const NAME: &'static str = "transferstory";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Copy a story to another server you are an administrator of")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("server")
+                    .required(true)
+                    .description("The id of the server to copy the story to")
+            })
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::Boolean)
+                    .name("replace")
+                    .required(false)
+                    .description("Replace the story of the same name on that server, if any")
+            })
+    }
+}
+
+pub struct StoryMapCommand;
+
+impl SlashCommand for StoryMapCommand {
+    const NAME: &'static str = "storymap";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Get the map of the passages of a story and their links")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("format")
+                    .required(false)
+                    .description("The format of the map, Graphviz DOT by default")
+                    .add_string_choice("Graphviz DOT", "dot")
+                    .add_string_choice("Mermaid", "mermaid")
+            })
+    }
+}
+
+pub struct ReportBugCommand;
+
+impl SlashCommand for ReportBugCommand {
+    const NAME: &'static str = "reportbug";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Tell the authors about a problem with the passage you are on")
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("description")
+                    .required(false)
+                    .max_length(150)
+                    .description("What went wrong")
+            })
+    }
+}
+
+pub struct BugReportsCommand;
+
+impl SlashCommand for BugReportsCommand {
+    const NAME: &'static str = "bugreports";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("List the latest problems reported by the players of a story")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+    }
+}
+
+pub struct VisitsCommand;
+
+impl SlashCommand for VisitsCommand {
+    const NAME: &'static str = "visits";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Show the most and least visited passages of a story")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+    }
+}
+
+pub struct DropoffsCommand;
+
+impl SlashCommand for DropoffsCommand {
+    const NAME: &'static str = "dropoffs";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Show where players stop playing a story without reaching an ending")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+    }
+}
+
+pub struct ValidateStoryCommand;
+
+impl SlashCommand for ValidateStoryCommand {
+    const NAME: &'static str = "validatestory";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Check a story for broken links, unreachable passages and other problems")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+    }
+}
+
+pub struct GotoCommand;
+
+impl SlashCommand for GotoCommand {
+    const NAME: &'static str = "goto";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Play a story from any of its passages, to test it")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("passage")
+                    .required(true)
+                    .description("The title of the passage to start from")
+            })
+    }
+}
+
+pub struct RecentCommand;
+
+impl SlashCommand for RecentCommand {
+    const NAME: &'static str = "recent";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("List the stories you played lately, to resume or replay them")
+    }
+}
+
+pub struct SurpriseCommand;
+
+impl SlashCommand for SurpriseCommand {
+    const NAME: &'static str = "surprise";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command.name(Self::NAME).description(
+            "Start a story picked for you, among the best rated you didn't play lately",
+        )
+    }
+}
+
+pub struct PreviewCommand;
+
+impl SlashCommand for PreviewCommand {
+    const NAME: &'static str = "preview";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Show a passage of a story as players see it, without playing it")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("passage")
+                    .required(true)
+                    .description("The title of the passage")
+            })
+    }
+}
+
+pub struct LinksCommand;
+
+impl SlashCommand for LinksCommand {
+    const NAME: &'static str = "links";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Show where the links of a passage lead, and which passages link to it")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(story_option)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("passage")
+                    .required(true)
+                    .description("The title of the passage")
+            })
+    }
+}
+
+pub struct AnalyticsCommand;
+
+impl SlashCommand for AnalyticsCommand {
+    const NAME: &'static str = "analytics";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Download how much the stories of this server were played, as CSV")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::Integer)
+                    .name("days")
+                    .required(false)
+                    .min_int_value(1)
+                    .max_int_value(365)
+                    .description("How many days back to look at, 30 by default")
+            })
+    }
+}
+
+pub struct ExportSessionCommand;
+
+impl SlashCommand for ExportSessionCommand {
+    const NAME: &'static str = "exportsession";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Get a share code of your current story, to continue it somewhere else")
+    }
+}
+
+pub struct ImportSessionCommand;
+
+impl SlashCommand for ImportSessionCommand {
+    const NAME: &'static str = "importsession";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Continue a story from a share code, replacing your current story")
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("code")
+                    .required(true)
+                    .description("The share code, from the /exportsession command")
+            })
+    }
+}
+
+pub struct SaveCommand;
+
+impl SlashCommand for SaveCommand {
+    const NAME: &'static str = "save";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Save your story, to load it back later with /load")
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("name")
+                    .required(true)
+                    .max_length(32)
+                    .description("The name of the save, replacing the save of the story with it")
+            })
+    }
+}
+
+pub struct LoadCommand;
+
+impl SlashCommand for LoadCommand {
+    const NAME: &'static str = "load";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Continue a story from a save, replacing your current story")
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("name")
+                    .required(true)
+                    .description("The name of the save, listed by /saves")
+            })
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("story")
+                    .required(false)
+                    .description("The title of the story, the one you are playing by default")
+            })
+    }
+}
+
+pub struct SavesCommand;
+
+impl SlashCommand for SavesCommand {
+    const NAME: &'static str = "saves";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("List the stories you saved on this server")
+    }
+}
+
+pub struct ReloadCacheCommand;
+
+impl SlashCommand for ReloadCacheCommand {
+    const NAME: &'static str = "reloadcache";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Reload the stories from their files")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+    }
+}
+
+pub struct StorageCommand;
+
+impl SlashCommand for StorageCommand {
+    const NAME: &'static str = "storage";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Show how much disk space the stories of this server use")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+    }
+}
+
+pub struct ToggleCommandCommand;
+
+impl SlashCommand for ToggleCommandCommand {
+    const NAME: &'static str = "togglecommand";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description("Disable a command on this server, or enable it again")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .create_option(|option| {
+                option
+                    .kind(CommandOptionType::String)
+                    .name("name")
+                    .required(true)
+                    .description("The name of the command, like upload")
+            })
+    }
+}
+
+pub struct VacuumCommand;
+
+impl SlashCommand for VacuumCommand {
+    const NAME: &'static str = "vacuum";
+
+    fn create_application_command(
+        command: &mut CreateApplicationCommand,
+    ) -> &mut CreateApplicationCommand {
+        command
+            .name(Self::NAME)
+            .description(
+                "Compact the database, giving back the space of what was deleted (bot admins only)",
+            )
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+    }
+}
+
+/// The `story` option of the commands about a story of the guild, by name.
+fn story_option(
+    option: &mut CreateApplicationCommandOption,
+) -> &mut CreateApplicationCommandOption {
+    option
+        .kind(CommandOptionType::String)
+        .name("story")
+        .required(true)
+        .description("The title of the story")
+}
+
+pub trait SlashCommandCreator {
+    fn create_slash_command<S: SlashCommand>(&mut self) -> &mut Self;
+}
+
+impl SlashCommandCreator for CreateApplicationCommands {
+    fn create_slash_command<S: SlashCommand>(&mut self) -> &mut Self {
+        self.create_application_command(|command| {
+            S::create_application_command(command).dm_permission(false)
+        })
+    }
+}
+
+/// Whether the commands registered on Discord are the ones built in `desired`, comparing what
+/// the bot sets: names, descriptions, options and permissions. Registering commands again is rate
+/// limited, so it is only done when they changed.
+pub fn commands_match(desired: &[Value], registered: &[Command]) -> bool {
+    let registered: Vec<Value> = registered
+        .iter()
+        .filter_map(|command| serde_json::to_value(command).ok())
+        .collect();
+    normalize_commands(desired) == normalize_commands(&registered)
+}
+
+/// The commands with only the fields compared, and their defaults filled, sorted by name.
+fn normalize_commands(commands: &[Value]) -> Vec<Value> {
+    let mut commands: Vec<Value> = commands
+        .iter()
+        .map(|command| {
+            json!({
+                "name": command["name"],
+                "description": command["description"],
+                "default_member_permissions": command["default_member_permissions"],
+                "dm_permission": command["dm_permission"].as_bool().unwrap_or(true),
+                "options": normalize_options(&command["options"]),
+            })
+        })
+        .collect();
+    commands.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    commands
+}
+
+fn normalize_options(options: &Value) -> Vec<Value> {
+    let Some(options) = options.as_array() else {
+        return vec![];
+    };
+    options
+        .iter()
+        .map(|option| {
+            let choices: Vec<Value> = option["choices"]
+                .as_array()
+                .map(|choices| {
+                    choices
+                        .iter()
+                        .map(|choice| json!({ "name": choice["name"], "value": choice["value"] }))
+                        .collect()
+                })
+                .unwrap_or_default();
+            json!({
+                "type": option["type"],
+                "name": option["name"],
+                "description": option["description"],
+                "required": option["required"].as_bool().unwrap_or(false),
+                "choices": choices,
+                "options": normalize_options(&option["options"]),
+                "channel_types": option["channel_types"].as_array().cloned().unwrap_or_default(),
+                "min_value": option["min_value"],
+                "max_value": option["max_value"],
+                "min_length": option["min_length"],
+                "max_length": option["max_length"],
+                "autocomplete": option["autocomplete"].as_bool().unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desired(
+        create: impl FnOnce(&mut CreateApplicationCommands) -> &mut CreateApplicationCommands,
+    ) -> Vec<Value> {
+        let mut commands = CreateApplicationCommands::default();
+        create(&mut commands);
+        commands.0
+    }
+
+    /// The commands as Discord would send them back once registered.
+    fn registered(commands: &[Value]) -> Vec<Command> {
+        commands
+            .iter()
+            .enumerate()
+            .map(|(index, command)| {
+                let mut command = command.clone();
+                command["id"] = json!((index + 1).to_string());
+                command["application_id"] = json!("1234");
+                command["version"] = json!("1");
+                command["type"] = json!(1);
+                serde_json::from_value(command).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_commands_match() {
+        let commands = desired(|commands| {
+            commands
+                .create_slash_command::<UploadStoryCommand>()
+                .create_slash_command::<SearchCommand>()
+        });
+        assert!(commands_match(&commands, &registered(&commands)));
+    }
+
+    #[test]
+    fn commands_match_in_any_order() {
+        let commands = desired(|commands| {
+            commands
+                .create_slash_command::<UploadStoryCommand>()
+                .create_slash_command::<SearchCommand>()
+        });
+        let reversed = desired(|commands| {
+            commands
+                .create_slash_command::<SearchCommand>()
+                .create_slash_command::<UploadStoryCommand>()
+        });
+        assert!(commands_match(&commands, &registered(&reversed)));
+    }
+
+    #[test]
+    fn changed_commands_dont_match() {
+        let commands = desired(|commands| commands.create_slash_command::<SearchCommand>());
+
+        let mut description = commands.clone();
+        description[0]["description"] = json!("Look for stories");
+        assert!(!commands_match(&commands, &registered(&description)));
+
+        let mut option = commands.clone();
+        option[0]["options"][0]["description"] = json!("What to look for");
+        assert!(!commands_match(&commands, &registered(&option)));
+
+        let mut required = commands.clone();
+        required[0]["options"][1]["required"] = json!(true);
+        assert!(!commands_match(&commands, &registered(&required)));
+    }
+
+    #[test]
+    fn added_or_removed_commands_dont_match() {
+        let one = desired(|commands| commands.create_slash_command::<SearchCommand>());
+        let two = desired(|commands| {
+            commands
+                .create_slash_command::<SearchCommand>()
+                .create_slash_command::<UploadStoryCommand>()
+        });
+        assert!(!commands_match(&two, &registered(&one)));
+        assert!(!commands_match(&one, &registered(&two)));
     }
 }
@@ -1,27 +1,312 @@
-use std::{env, fs::read_to_string, path::Path};
+use std::{
+    env,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use toml::{map::Map, Value};
 
 pub struct Config {
+    path: PathBuf,
     content: Value,
 }
 
 impl Config {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
-        let content = read_to_string(path).unwrap_or_default();
-        let content = content
-            .parse::<Value>()
-            .unwrap_or_else(|_| Value::Table(Map::new()));
+        let path = path.as_ref().to_path_buf();
+        let content = read_content(&path);
 
-        Self { content }
+        Self { path, content }
+    }
+
+    /// Read the TOML file again. Values coming from the environment are not affected.
+    pub fn reload(&mut self) {
+        self.content = read_content(&self.path);
     }
 
     pub fn get_string(&self, key: &str) -> Option<String> {
-        env::var(key).ok().or_else(|| {
-            self.content
-                .get(key)
+        self.get_string_path(&[key])
+    }
+
+    /// A list, either a TOML array or a comma separated string like `1234,5678`, the only form the
+    /// environment can override it with. Entries are trimmed, and the empty ones left out.
+    pub fn get_list(&self, key: &str) -> Vec<String> {
+        let entries: Vec<String> = match env::var(env_key(&[key])) {
+            Ok(list) => split_list(&list),
+            Err(_) => match self.get_value(&[key]) {
+                Some(Value::Array(values)) => values
+                    .iter()
+                    .map(|value| match value {
+                        Value::String(value) => value.to_string(),
+                        value => value.to_string(),
+                    })
+                    .collect(),
+                Some(Value::String(list)) => split_list(list),
+                _ => vec![],
+            },
+        };
+        entries
+            .iter()
+            .map(|entry| entry.trim())
+            .filter(|entry| !entry.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Look for a value in nested TOML tables, `&["cache", "ttl"]` being `ttl` in the `[cache]`
+    /// table. It can still be overridden by the environment variable of the flattened path,
+    /// `CACHE_TTL` here.
+    pub fn get_string_path(&self, path: &[&str]) -> Option<String> {
+        env::var(env_key(path)).ok().or_else(|| {
+            self.get_value(path)
                 .and_then(|value| value.as_str())
                 .map(|value| value.to_string())
         })
     }
+
+    /// A `[messages]` entry, from the table of the first of the languages having it, like
+    /// `[messages.fr]`, or else from `[messages]` itself.
+    pub fn get_message(&self, key: &str, languages: &[&str]) -> Option<String> {
+        languages
+            .iter()
+            .find_map(|language| self.get_string_path(&["messages", language, key]))
+            .or_else(|| self.get_string_path(&["messages", key]))
+    }
+
+    /// Like [`Config::get_string_path`], parsing the value. Invalid values are ignored.
+    pub fn get_parsed_path<T: FromStr>(&self, path: &[&str]) -> Option<T> {
+        env::var(env_key(path))
+            .ok()
+            .or_else(|| {
+                self.get_value(path).map(|value| match value {
+                    Value::String(value) => value.to_string(),
+                    value => value.to_string(),
+                })
+            })
+            .and_then(|value| value.parse().ok())
+    }
+
+    fn get_value(&self, path: &[&str]) -> Option<&Value> {
+        path.iter()
+            .try_fold(&self.content, |table, key| table.get(key))
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',').map(ToString::to_string).collect()
+}
+
+fn env_key(path: &[&str]) -> String {
+    path.join("_").to_uppercase()
+}
+
+fn read_content(path: &Path) -> Value {
+    let content = read_to_string(path).unwrap_or_default();
+    content
+        .parse::<Value>()
+        .unwrap_or_else(|_| Value::Table(Map::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn config(folder: &TempDir, content: &str) -> Config {
+        let path = folder.path().join("config.toml");
+        write(&path, content).unwrap();
+        Config::new(path)
+    }
+
+    #[test]
+    fn reload_reads_the_file_again() {
+        let folder = TempDir::new().unwrap();
+        let mut config = config(&folder, "reload_test_color = \"red\"");
+        assert_eq!(
+            config.get_string("reload_test_color").as_deref(),
+            Some("red")
+        );
+
+        write(
+            folder.path().join("config.toml"),
+            "reload_test_color = \"blue\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.get_string("reload_test_color").as_deref(),
+            Some("red")
+        );
+        config.reload();
+        assert_eq!(
+            config.get_string("reload_test_color").as_deref(),
+            Some("blue")
+        );
+    }
+
+    #[test]
+    fn reload_keeps_the_environment_first() {
+        let folder = TempDir::new().unwrap();
+        let mut config = config(&folder, "reload_test_env = \"file\"");
+        env::set_var("RELOAD_TEST_ENV", "environment");
+
+        write(
+            folder.path().join("config.toml"),
+            "reload_test_env = \"changed\"",
+        )
+        .unwrap();
+        config.reload();
+        assert_eq!(
+            config.get_string("reload_test_env").as_deref(),
+            Some("environment")
+        );
+    }
+
+    #[test]
+    fn reload_of_a_missing_file_is_empty() {
+        let folder = TempDir::new().unwrap();
+        let mut config = config(&folder, "reload_test_missing = \"here\"");
+
+        std::fs::remove_file(folder.path().join("config.toml")).unwrap();
+        config.reload();
+        assert_eq!(config.get_string("reload_test_missing"), None);
+    }
+
+    #[test]
+    fn nested_values_are_found_by_path() {
+        let folder = TempDir::new().unwrap();
+        let config = config(
+            &folder,
+            "[nested_test]\nname = \"story\"\nttl = 30\n\n[nested_test.deeper]\nkey = \"value\"",
+        );
+
+        assert_eq!(
+            config.get_string_path(&["nested_test", "name"]).as_deref(),
+            Some("story")
+        );
+        assert_eq!(
+            config
+                .get_string_path(&["nested_test", "deeper", "key"])
+                .as_deref(),
+            Some("value")
+        );
+        assert_eq!(config.get_parsed_path(&["nested_test", "ttl"]), Some(30));
+        // A table is not a string.
+        assert_eq!(config.get_string_path(&["nested_test"]), None);
+    }
+
+    #[test]
+    fn missing_paths_are_none() {
+        let folder = TempDir::new().unwrap();
+        let config = config(&folder, "[missing_test]\nname = \"story\"");
+
+        assert_eq!(config.get_string_path(&["missing_test", "other"]), None);
+        assert_eq!(config.get_string_path(&["missing_test", "name", "x"]), None);
+        assert_eq!(config.get_string_path(&["absent_test", "name"]), None);
+        assert_eq!(
+            config.get_parsed_path::<u64>(&["missing_test", "name"]),
+            None
+        );
+    }
+
+    #[test]
+    fn environment_overrides_nested_values() {
+        let folder = TempDir::new().unwrap();
+        let config = config(&folder, "[override_test]\nmax_per_player = 2");
+        assert_eq!(
+            config.get_parsed_path(&["override_test", "max_per_player"]),
+            Some(2)
+        );
+
+        env::set_var("OVERRIDE_TEST_MAX_PER_PLAYER", "5");
+        assert_eq!(
+            config.get_parsed_path(&["override_test", "max_per_player"]),
+            Some(5)
+        );
+        assert_eq!(
+            config
+                .get_string_path(&["override_test", "max_per_player"])
+                .as_deref(),
+            Some("5")
+        );
+    }
+
+    #[test]
+    fn environment_values_without_file_are_found() {
+        let folder = TempDir::new().unwrap();
+        let config = config(&folder, "");
+
+        env::set_var("FLATTENED_TEST_TTL", "12");
+        assert_eq!(config.get_parsed_path(&["flattened_test", "ttl"]), Some(12));
+        // Invalid values are ignored.
+        env::set_var("FLATTENED_TEST_INVALID", "twelve");
+        assert_eq!(
+            config.get_parsed_path::<u64>(&["flattened_test", "invalid"]),
+            None
+        );
+    }
+
+    #[test]
+    fn lists_are_read_from_arrays() {
+        let folder = TempDir::new().unwrap();
+        let config = config(&folder, "array_test = [\" 1234 \", 5678, \"\"]");
+
+        assert_eq!(config.get_list("array_test"), vec!["1234", "5678"]);
+    }
+
+    #[test]
+    fn lists_are_read_from_comma_separated_strings() {
+        let folder = TempDir::new().unwrap();
+        let config = config(&folder, "csv_test = \"1234, 5678,,\"");
+
+        assert_eq!(config.get_list("csv_test"), vec!["1234", "5678"]);
+    }
+
+    #[test]
+    fn environment_overrides_lists() {
+        let folder = TempDir::new().unwrap();
+        let config = config(&folder, "env_list_test = [\"1234\"]");
+
+        env::set_var("ENV_LIST_TEST", " 42 ,43");
+        assert_eq!(config.get_list("env_list_test"), vec!["42", "43"]);
+    }
+
+    #[test]
+    fn empty_or_missing_lists_are_empty() {
+        let folder = TempDir::new().unwrap();
+        let config = config(&folder, "empty_list_test = []\nblank_list_test = \" , \"");
+
+        assert!(config.get_list("empty_list_test").is_empty());
+        assert!(config.get_list("blank_list_test").is_empty());
+        assert!(config.get_list("missing_list_test").is_empty());
+    }
+
+    #[test]
+    fn messages_are_found_in_the_first_language_having_them() {
+        let folder = TempDir::new().unwrap();
+        let config = config(
+            &folder,
+            "[messages]\nhello_test = \"Hello\"\n\n[messages.fr]\nhello_test = \"Bonjour\"\n\n\
+            [messages.pt-BR]\nhello_test = \"Olá\"\n",
+        );
+
+        assert_eq!(
+            config
+                .get_message("hello_test", &["pt-BR", "fr"])
+                .as_deref(),
+            Some("Olá")
+        );
+        assert_eq!(
+            config.get_message("hello_test", &["de", "fr"]).as_deref(),
+            Some("Bonjour")
+        );
+        assert_eq!(
+            config.get_message("hello_test", &["de"]).as_deref(),
+            Some("Hello")
+        );
+        assert_eq!(config.get_message("missing_test", &["fr"]), None);
+    }
 }
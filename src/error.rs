@@ -0,0 +1,193 @@
+use std::fmt::Display;
+
+use anyhow::Error;
+use uuid::Uuid;
+
+tokio::task_local! {
+    /// Identifies the interaction being handled, in the logs and the error responses.
+    pub static CORRELATION_ID: String;
+}
+
+pub fn new_correlation_id() -> String {
+    Uuid::new_v4().simple().to_string()[..8].to_string()
+}
+
+/// The correlation id of the current interaction, or a new one outside of an interaction.
+pub fn correlation_id() -> String {
+    CORRELATION_ID
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| new_correlation_id())
+}
+
+/// Errors caused by what the user did, with a message telling them what went wrong.
+/// Any other error is a bug on our side.
+#[derive(Debug)]
+pub enum UserError {
+    NoGuild,
+    NoStories,
+    NoSession,
+    InvalidSelection,
+    InvalidShareCode,
+    StoryNotShareable,
+    StoryNotInGuild,
+    UnknownStory,
+    UnknownPassage(String),
+    NotAdmin,
+    /// The command acts on the whole bot, not only the server.
+    NotBotAdmin,
+    /// The player targeted by an administrator has no session.
+    NoPlayerSession,
+    /// The chosen link leads to a passage missing from the story.
+    BrokenLink,
+    /// The target guild of a transfer is unknown, or the user isn't an administrator there.
+    NotGuildAdmin,
+    StoryNameTaken(String),
+    /// The player already has as many sessions as allowed.
+    TooManySessions(usize),
+    /// The folder of the stories is read-only, a deployment issue.
+    StorageNotWritable,
+    /// Too many stories are being uploaded at once.
+    ServerBusy,
+    /// The story doesn't allow `/jumpto`.
+    NoFreeNavigation,
+    /// No passage of the story matches the search of `/jumpto`.
+    NoMatchingPassage(String),
+    /// The command was disabled on the guild with `/togglecommand`.
+    CommandDisabled,
+    UnknownCommand(String),
+    /// The bot is in maintenance, and the user isn't an administrator.
+    Maintenance,
+    UnknownSeries,
+    /// The previous story of the series wasn't finished yet.
+    SeriesStoryLocked(String),
+    /// A story of the same name was uploaded since the story to restore was deleted.
+    RestoredNameTaken(String),
+    /// `/setpath` records the session of the story, and there is none.
+    NotPlayingStory,
+    NoCanonicalPath,
+    /// The recorded path doesn't follow the links of the story, with the reason.
+    PathMismatch(String),
+    /// The player already has as many saves of the story as allowed.
+    TooManySaves(usize),
+    UnknownSave(String),
+    InvalidSaveName,
+}
+
+impl Display for UserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            UserError::NoGuild => "This command can only be used in a server.",
+            UserError::NoStories => "There are no stories on this server yet.",
+            UserError::NoSession => {
+                "You are not playing any story, start one with the `/play` command."
+            }
+            UserError::InvalidSelection => "This choice is not valid anymore, try again.",
+            UserError::InvalidShareCode => "This share code is not valid.",
+            UserError::StoryNotShareable => {
+                "This story has no IFID, so its sessions can't be shared."
+            }
+            UserError::StoryNotInGuild => "This story is not available on this server.",
+            UserError::UnknownStory => "There is no story with this name on this server.",
+            UserError::UnknownPassage(passage) => {
+                return write!(f, "There is no passage named `{passage}` in this story.");
+            }
+            UserError::NotAdmin => "Only administrators can use this command.",
+            UserError::NotBotAdmin => "Only the administrators of the bot can use this command.",
+            UserError::NoPlayerSession => "This player is not playing any story on this server.",
+            UserError::BrokenLink => {
+                "This choice leads nowhere, tell the authors of the story with `/reportbug`."
+            }
+            UserError::NotGuildAdmin => {
+                "You must be an administrator of this server, and the bot must be on it."
+            }
+            UserError::StoryNameTaken(name) => {
+                return write!(
+                    f,
+                    "There is already a story named `{name}` on this server, set `replace` to replace it."
+                );
+            }
+            UserError::TooManySessions(max) => {
+                return write!(
+                    f,
+                    "You can't play more than {max} stories at once, finish one or `/stop` it first."
+                );
+            }
+            UserError::StorageNotWritable => {
+                "The storage of the bot is not writable, contact its operator."
+            }
+            UserError::ServerBusy => "The bot is busy, try again in a moment.",
+            UserError::NoFreeNavigation => "This story doesn't let you jump to its passages.",
+            UserError::NoMatchingPassage(query) => {
+                return write!(f, "No passage of this story matches `{query}`.");
+            }
+            UserError::CommandDisabled => "This command is currently disabled on this server.",
+            UserError::Maintenance => "The bot is under maintenance, back soon.",
+            UserError::UnknownCommand(name) => {
+                return write!(f, "There is no command named `{name}`.");
+            }
+            UserError::UnknownSeries => "There is no series with this name on this server.",
+            UserError::SeriesStoryLocked(previous) => {
+                return write!(f, "Finish `{previous}` first to unlock this story.");
+            }
+            UserError::RestoredNameTaken(name) => {
+                return write!(
+                    f,
+                    "There is already a story named `{name}` on this server, delete it first."
+                );
+            }
+            UserError::NotPlayingStory => {
+                "You are not playing this story, play it along the path to record first."
+            }
+            UserError::NoCanonicalPath => {
+                "This story has no recommended path, its authors can record one with `/setpath`."
+            }
+            UserError::PathMismatch(reason) => {
+                return write!(
+                    f,
+                    "The path doesn't follow the story anymore: {reason} Record it again with `/setpath`."
+                );
+            }
+            UserError::TooManySaves(max) => {
+                return write!(
+                    f,
+                    "You can't keep more than {max} saves of a story, save under the name of one to replace it."
+                );
+            }
+            UserError::UnknownSave(name) => {
+                return write!(
+                    f,
+                    "You have no save named `{name}`, see yours with `/saves`."
+                );
+            }
+            UserError::InvalidSaveName => "Give your save a name.",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for UserError {}
+
+/// What to tell the user about an error.
+pub struct ErrorResponse {
+    pub message: String,
+    /// Set for internal errors, logged along the error so the user can quote it in bug reports.
+    pub correlation_id: Option<String>,
+}
+
+impl ErrorResponse {
+    pub fn new(error: &Error) -> Self {
+        if let Some(user_error) = error.downcast_ref::<UserError>() {
+            Self {
+                message: user_error.to_string(),
+                correlation_id: None,
+            }
+        } else {
+            let correlation_id = correlation_id();
+            println!("[{correlation_id}] Error: {error:?}");
+            Self {
+                message: "Something went wrong, try again later.".to_string(),
+                correlation_id: Some(correlation_id),
+            }
+        }
+    }
+}
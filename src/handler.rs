@@ -1,62 +1,255 @@
-use anyhow::Result;
+use std::{
+    future::Future,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock, RwLockReadGuard,
+    },
+};
+
+use anyhow::{anyhow, Result};
 use serenity::{
     async_trait,
+    builder::CreateApplicationCommands,
     model::prelude::{
         command::Command,
-        interaction::{message_component::MessageComponentInteraction, Interaction},
-        Ready,
+        interaction::{
+            application_command::ApplicationCommandInteraction,
+            message_component::MessageComponentInteraction, Interaction,
+        },
+        GuildId, Member, Message, Permissions, Ready, User, UserId,
     },
     prelude::*,
 };
 
+use tokio::{sync::Semaphore, task::JoinError};
+
 use crate::{
+    author::{
+        analytics_interaction, bug_reports_interaction, dropoffs_interaction, endings_interaction,
+        links_interaction, preview_interaction, set_free_navigation_interaction,
+        set_path_interaction, set_series_interaction, set_start_interaction, story_map_interaction,
+        transfer_story_interaction, validate_story_interaction, visits_interaction,
+    },
+    clock::Clock,
     command::{
-        DeleteStoryCommand, PlayCommand, SlashCommand, SlashCommandCreator, StopCommand,
-        UploadStoryCommand,
+        commands_match, AnalyticsCommand, BugReportsCommand, ClearSessionCommand,
+        DeleteStoryCommand, DemoCommand, DropoffsCommand, EndingsCommand, ExportSessionCommand,
+        GotoCommand, ImportSessionCommand, JumpToCommand, LinksCommand, LoadCommand, PlayCommand,
+        PreviewCommand, RecentCommand, ReloadCacheCommand, ReportBugCommand, RestoreStoryCommand,
+        SaveCommand, SavesCommand, SearchCommand, SeriesCommand, SessionsCommand, SetDelayCommand,
+        SetFreeNavigationCommand, SetLanguageCommand, SetLayoutCommand, SetPathCommand,
+        SetSeriesCommand, SetSingleMessageCommand, SetStartCommand, SlashCommand,
+        SlashCommandCreator, StopCommand, StorageCommand, StoriesCommand, StoryMapCommand,
+        SurpriseCommand, ToggleCommandCommand, TransferStoryCommand, UploadStoryCommand,
+        VacuumCommand, ValidateStoryCommand, VersionCommand, VisitsCommand,
     },
+    config::Config,
+    error::{correlation_id, new_correlation_id, UserError, CORRELATION_ID},
     interaction::{
-        actual_deletion, delete_story_interaction, text_interaction, update_message_text,
-        upload_story_interaction, DELETE_STORY_MENU,
+        actual_deletion, configured_message, delete_story_interaction, error_interaction,
+        error_message_component, list_stories_interaction, reload_cache_interaction,
+        restore_story_interaction, search_interaction, set_delay_interaction,
+        set_language_interaction, set_layout_interaction, set_single_message_interaction,
+        storage_interaction, text_interaction, toggle_command_interaction,
+        upload_story_interaction, vacuum_interaction, version_interaction, Branding, BRANDING,
+        DELETE_STORY_MENU,
     },
     persistance::Storage,
     play::{
-        actual_start, next_chapter_from_button, next_chapter_from_menu, play_story_interaction,
-        stop_story_interaction, the_end, PICK_NEXT_PASSAGE, PICK_NEXT_PASSAGE_BUTTON,
-        START_STORY_MENU, THE_END,
+        acknowledge_warning, actual_start, clear_session_interaction, demo_interaction,
+        export_session_interaction, goto_interaction, import_session_interaction,
+        jump_to_interaction, jump_to_passage, load_interaction, next_chapter_at_random,
+        next_chapter_from_button, next_chapter_from_menu, play_recent_story, play_series_story,
+        play_story_interaction, preview_choice, rate_story, recent_interaction,
+        report_bug_interaction, resume_session, save_interaction, saves_interaction,
+        series_interaction, sessions_interaction, show_inventory, stop_story_interaction,
+        surprise_interaction, the_end, ACKNOWLEDGE_WARNING, JUMP_TO_PASSAGE_MENU,
+        PICK_NEXT_PASSAGE, PICK_NEXT_PASSAGE_BUTTON, PICK_RANDOM_PASSAGE, PLAY_RECENT_STORY_BUTTON,
+        PLAY_SERIES_STORY_BUTTON, PREVIEW_PREFIX, RATE_STORY_BUTTON, RESUME_SESSION_BUTTON,
+        SHOW_INVENTORY_BUTTON, START_STORY_MENU, THE_END, THE_END_IN_PLACE,
     },
+    random::Rng,
 };
 
+/// Commands restricted to the administrators of the server, or the bot admins. Discord hides them
+/// from other members unless the server allows them in its integration settings, which is how bot
+/// admins get to see them, and they are checked again in case the interaction was crafted.
+const ADMIN_COMMANDS: &[&str] = &[
+    UploadStoryCommand::NAME,
+    DeleteStoryCommand::NAME,
+    RestoreStoryCommand::NAME,
+    ClearSessionCommand::NAME,
+    SessionsCommand::NAME,
+    SetLanguageCommand::NAME,
+    SetLayoutCommand::NAME,
+    SetSingleMessageCommand::NAME,
+    SetDelayCommand::NAME,
+    EndingsCommand::NAME,
+    SetStartCommand::NAME,
+    TransferStoryCommand::NAME,
+    StoryMapCommand::NAME,
+    BugReportsCommand::NAME,
+    AnalyticsCommand::NAME,
+    VisitsCommand::NAME,
+    DropoffsCommand::NAME,
+    PreviewCommand::NAME,
+    LinksCommand::NAME,
+    GotoCommand::NAME,
+    ValidateStoryCommand::NAME,
+    SetFreeNavigationCommand::NAME,
+    ReloadCacheCommand::NAME,
+    ToggleCommandCommand::NAME,
+    StorageCommand::NAME,
+    SetSeriesCommand::NAME,
+    SetPathCommand::NAME,
+];
+
+/// Commands acting on the whole bot rather than a server, only for the `BOT_ADMINS`.
+const BOT_ADMIN_COMMANDS: &[&str] = &[VacuumCommand::NAME];
+
+const NOT_IMPLEMENTED_MESSAGE: &str = "Command `{command}` not implemented :(";
+
+/// The answer to a message only mentioning the bot, with `[chat] answer_mentions`.
+const MENTION_ANSWER: &str =
+    "Hi! Use `/play` to play a story of this server, and `/stories` to see which ones there are.";
+
+/// Components only shown by administrator commands, checked again when used in case the
+/// interaction was crafted.
+const ADMIN_COMPONENTS: &[&str] = &[DELETE_STORY_MENU];
+
+#[derive(Clone)]
 pub struct Handler {
-    pub storage: Mutex<Storage<String>>,
+    /// Shared with the task writing the game states kept in memory by autosave.
+    pub storage: Arc<Mutex<Storage<String>>>,
+    /// Shared with the task reloading it on SIGHUP.
+    pub config: Arc<RwLock<Config>>,
+    pub rng: Arc<dyn Rng>,
+    /// Waits for the delays, so tests don't have to.
+    pub clock: Arc<dyn Clock>,
+    /// Set once connected to Discord, for the health check.
+    pub ready: Arc<AtomicBool>,
+    /// Bounds how many uploaded stories are downloaded, and kept in memory, at once.
+    pub downloads: Arc<Semaphore>,
 }
 
 impl Handler {
+    pub fn config(&self) -> RwLockReadGuard<'_, Config> {
+        self.config.read().expect("config lock poisoned")
+    }
+
+    /// Whether the user of the interaction is an administrator, see [`is_admin`].
+    fn is_admin(&self, member: Option<&Member>, user: &User) -> bool {
+        is_admin(
+            member.and_then(|member| member.permissions),
+            user.id,
+            &self.config().get_list("BOT_ADMINS"),
+        )
+    }
+
+    /// Whether the user is listed in the `BOT_ADMINS` of the config.
+    fn is_bot_admin(&self, user: &User) -> bool {
+        self.config()
+            .get_list("BOT_ADMINS")
+            .contains(&user.id.to_string())
+    }
+
+    /// Whether `[maintenance] enabled` is set, only letting administrators use the bot. It can be
+    /// turned on and off by reloading the config.
+    fn in_maintenance(&self) -> bool {
+        self.config()
+            .get_parsed_path(&["maintenance", "enabled"])
+            .unwrap_or(false)
+    }
+
+    /// Whether the command was disabled on the guild. `/togglecommand` can't be, so it can always
+    /// enable the others again.
+    async fn is_disabled(&self, command: &ApplicationCommandInteraction) -> bool {
+        is_command_disabled(
+            &*self.storage.lock().await,
+            command.guild_id,
+            &command.data.name,
+        )
+    }
+
+    /// What to answer to a command this version doesn't know, `{command}` in `[messages]
+    /// not_implemented` being replaced by its name.
+    async fn not_implemented_message(&self, command: &ApplicationCommandInteraction) -> String {
+        not_implemented_text(
+            configured_message(self, command, "not_implemented").await,
+            &command.data.name,
+        )
+    }
+
+    /// Whether `[chat] answer_mentions` is set. Messages mentioning the bot have their content
+    /// even without the privileged `MESSAGE_CONTENT` intent.
+    fn answers_mentions(&self) -> bool {
+        self.config()
+            .get_parsed_path(&["chat", "answer_mentions"])
+            .unwrap_or(false)
+    }
+
     pub async fn handle_message_component(
         &self,
         ctx: &Context,
         message_component: &MessageComponentInteraction,
     ) -> Result<()> {
+        let is_admin = self.is_admin(message_component.member.as_ref(), &message_component.user);
+        check_maintenance(self.in_maintenance(), is_admin)?;
+        check_admin_component(&message_component.data.custom_id, is_admin)?;
+        if message_component.data.custom_id.starts_with(PREVIEW_PREFIX) {
+            return preview_choice(ctx, message_component).await;
+        }
         match message_component.data.custom_id.as_str() {
             DELETE_STORY_MENU => actual_deletion(self, ctx, message_component).await?,
             START_STORY_MENU => actual_start(self, ctx, message_component).await?,
             PICK_NEXT_PASSAGE => next_chapter_from_menu(self, ctx, message_component).await?,
-            THE_END => the_end(self, ctx, message_component).await?,
+            THE_END | THE_END_IN_PLACE => the_end(self, ctx, message_component).await?,
+            ACKNOWLEDGE_WARNING => acknowledge_warning(self, ctx, message_component).await?,
+            JUMP_TO_PASSAGE_MENU => jump_to_passage(self, ctx, message_component).await?,
+            RESUME_SESSION_BUTTON => resume_session(self, ctx, message_component).await?,
+            SHOW_INVENTORY_BUTTON => show_inventory(self, ctx, message_component).await?,
             other => {
                 if other.starts_with(PICK_NEXT_PASSAGE_BUTTON) {
                     // This is passage with a single selection
                     next_chapter_from_button(self, ctx, message_component).await?;
+                } else if other.starts_with(PICK_RANDOM_PASSAGE) {
+                    next_chapter_at_random(self, ctx, message_component).await?;
+                } else if other.starts_with(PLAY_RECENT_STORY_BUTTON) {
+                    play_recent_story(self, ctx, message_component).await?;
+                } else if other.starts_with(PLAY_SERIES_STORY_BUTTON) {
+                    play_series_story(self, ctx, message_component).await?;
+                } else if other.starts_with(RATE_STORY_BUTTON) {
+                    rate_story(self, ctx, message_component).await?;
                 }
-                println!("Message component {other}");
+                println!("[{}] Message component {other}", correlation_id());
             }
         }
         Ok(())
     }
-}
 
-#[async_trait]
-impl EventHandler for Handler {
-    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+    async fn handle_interaction(&self, ctx: Context, interaction: Interaction) {
         if let Interaction::ApplicationCommand(command) = interaction {
+            println!("[{}] Command {}", correlation_id(), command.data.name);
+            let is_admin = self.is_admin(command.member.as_ref(), &command.user);
+            if let Err(error) = check_maintenance(self.in_maintenance(), is_admin) {
+                error_interaction(&error, &ctx, &command).await;
+                return;
+            }
+            if let Err(error) = check_admin_command(&command.data.name, is_admin) {
+                error_interaction(&error, &ctx, &command).await;
+                return;
+            }
+            if BOT_ADMIN_COMMANDS.contains(&command.data.name.as_str())
+                && !self.is_bot_admin(&command.user)
+            {
+                error_interaction(&UserError::NotBotAdmin.into(), &ctx, &command).await;
+                return;
+            }
+            if self.is_disabled(&command).await {
+                error_interaction(&UserError::CommandDisabled.into(), &ctx, &command).await;
+                return;
+            }
             match command.data.name.as_str() {
                 UploadStoryCommand::NAME => {
                     upload_story_interaction(self, &ctx, &command).await;
@@ -67,49 +260,587 @@ impl EventHandler for Handler {
                 PlayCommand::NAME => {
                     play_story_interaction(self, &ctx, &command).await;
                 }
+                SearchCommand::NAME => {
+                    search_interaction(self, &ctx, &command).await;
+                }
+                SetLanguageCommand::NAME => {
+                    set_language_interaction(self, &ctx, &command).await;
+                }
+                SetLayoutCommand::NAME => {
+                    set_layout_interaction(self, &ctx, &command).await;
+                }
+                SetSingleMessageCommand::NAME => {
+                    set_single_message_interaction(self, &ctx, &command).await;
+                }
+                SetDelayCommand::NAME => {
+                    set_delay_interaction(self, &ctx, &command).await;
+                }
+                StoriesCommand::NAME => {
+                    list_stories_interaction(self, &ctx, &command).await;
+                }
+                ClearSessionCommand::NAME => {
+                    clear_session_interaction(self, &ctx, &command).await;
+                }
+                SessionsCommand::NAME => {
+                    sessions_interaction(self, &ctx, &command).await;
+                }
                 StopCommand::NAME => {
                     stop_story_interaction(self, &ctx, &command).await;
                 }
+                EndingsCommand::NAME => {
+                    endings_interaction(self, &ctx, &command).await;
+                }
+                SetStartCommand::NAME => {
+                    set_start_interaction(self, &ctx, &command).await;
+                }
+                TransferStoryCommand::NAME => {
+                    transfer_story_interaction(self, &ctx, &command).await;
+                }
+                StoryMapCommand::NAME => {
+                    story_map_interaction(self, &ctx, &command).await;
+                }
+                ReportBugCommand::NAME => {
+                    report_bug_interaction(self, &ctx, &command).await;
+                }
+                BugReportsCommand::NAME => {
+                    bug_reports_interaction(self, &ctx, &command).await;
+                }
+                AnalyticsCommand::NAME => {
+                    analytics_interaction(self, &ctx, &command).await;
+                }
+                VisitsCommand::NAME => {
+                    visits_interaction(self, &ctx, &command).await;
+                }
+                DropoffsCommand::NAME => {
+                    dropoffs_interaction(self, &ctx, &command).await;
+                }
+                LinksCommand::NAME => {
+                    links_interaction(self, &ctx, &command).await;
+                }
+                PreviewCommand::NAME => {
+                    preview_interaction(self, &ctx, &command).await;
+                }
+                RecentCommand::NAME => {
+                    recent_interaction(self, &ctx, &command).await;
+                }
+                SurpriseCommand::NAME => {
+                    surprise_interaction(self, &ctx, &command).await;
+                }
+                GotoCommand::NAME => {
+                    goto_interaction(self, &ctx, &command).await;
+                }
+                ValidateStoryCommand::NAME => {
+                    validate_story_interaction(self, &ctx, &command).await;
+                }
+                ExportSessionCommand::NAME => {
+                    export_session_interaction(self, &ctx, &command).await;
+                }
+                ImportSessionCommand::NAME => {
+                    import_session_interaction(self, &ctx, &command).await;
+                }
+                SaveCommand::NAME => {
+                    save_interaction(self, &ctx, &command).await;
+                }
+                LoadCommand::NAME => {
+                    load_interaction(self, &ctx, &command).await;
+                }
+                SavesCommand::NAME => {
+                    saves_interaction(self, &ctx, &command).await;
+                }
+                ReloadCacheCommand::NAME => {
+                    reload_cache_interaction(self, &ctx, &command).await;
+                }
+                ToggleCommandCommand::NAME => {
+                    toggle_command_interaction(self, &ctx, &command, &toggleable_commands()).await;
+                }
+                VacuumCommand::NAME => {
+                    vacuum_interaction(self, &ctx, &command).await;
+                }
+                StorageCommand::NAME => {
+                    storage_interaction(self, &ctx, &command).await;
+                }
+                SetFreeNavigationCommand::NAME => {
+                    set_free_navigation_interaction(self, &ctx, &command).await;
+                }
+                JumpToCommand::NAME => {
+                    jump_to_interaction(self, &ctx, &command).await;
+                }
+                RestoreStoryCommand::NAME => {
+                    restore_story_interaction(self, &ctx, &command).await;
+                }
+                SetPathCommand::NAME => {
+                    set_path_interaction(self, &ctx, &command).await;
+                }
+                DemoCommand::NAME => {
+                    demo_interaction(self, &ctx, &command).await;
+                }
+                SeriesCommand::NAME => {
+                    series_interaction(self, &ctx, &command).await;
+                }
+                SetSeriesCommand::NAME => {
+                    set_series_interaction(self, &ctx, &command).await;
+                }
+                VersionCommand::NAME => {
+                    version_interaction(&ctx, &command).await;
+                }
                 rest => {
-                    println!("Command {rest} not implemented :(");
-                    text_interaction(
-                        format!("Command `{rest}` not implemented :("),
-                        &ctx,
-                        &command,
-                    )
-                    .await;
+                    // Likely a command of another version of the bot, until the commands are
+                    // registered again.
+                    println!(
+                        "[{}] Warning: command {rest} not implemented",
+                        correlation_id()
+                    );
+                    text_interaction(self.not_implemented_message(&command).await, &ctx, &command)
+                        .await;
                 }
             }
         } else if let Interaction::MessageComponent(message_component) = interaction {
-            if self
+            if let Err(error) = self
                 .handle_message_component(&ctx, &message_component)
                 .await
-                .is_err()
             {
-                let _ignored_result = update_message_text(
-                    "Error",
-                    "Something went wrong, try again later.",
-                    &ctx,
-                    &message_component,
-                )
-                .await;
+                let _ignored_result =
+                    error_message_component(&error, &ctx, &message_component).await;
             };
         } else {
-            println!("Something happened");
+            println!("[{}] Something happened", correlation_id());
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let correlation_id = new_correlation_id();
+        let handler = self.clone();
+        let branding = Branding::new(&self.config());
+        let handled = spawn_in_scope(correlation_id.clone(), branding.clone(), {
+            let (ctx, interaction) = (ctx.clone(), interaction.clone());
+            async move { handler.handle_interaction(ctx, interaction).await }
+        })
+        .await;
+        if let Err(error) = handled {
+            CORRELATION_ID
+                .scope(
+                    correlation_id,
+                    BRANDING.scope(branding, report_panic(error, &ctx, &interaction)),
+                )
+                .await;
+        }
+    }
+
+    async fn message(&self, ctx: Context, message: Message) {
+        // Other bots are ignored too, so bots never answer each other in a loop.
+        if message.author.bot || message.guild_id.is_none() {
+            return;
+        }
+        if !self.answers_mentions() || self.in_maintenance() {
+            return;
+        }
+        match parse_message(&message.content, ctx.cache.current_user_id()) {
+            Some(MessageCommand::Help) => {
+                if let Err(error) = message.reply(&ctx.http, MENTION_ANSWER).await {
+                    println!("Couldn't answer the mention: {error}");
+                }
+            }
+            None => {}
         }
     }
 
     async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
+        self.ready.store(true, Ordering::Relaxed);
+
+        let commands = application_commands();
 
-        Command::set_global_application_commands(&ctx.http, |commands| {
-            commands
-                .create_slash_command::<UploadStoryCommand>()
-                .create_slash_command::<DeleteStoryCommand>()
-                .create_slash_command::<PlayCommand>()
-                .create_slash_command::<StopCommand>()
+        let registered = Command::get_global_application_commands(&ctx.http).await;
+        if registered.is_ok_and(|registered| commands_match(&commands.0, &registered)) {
+            println!("The commands are up to date");
+            return;
+        }
+        if let Err(error) = Command::set_global_application_commands(&ctx.http, |builder| {
+            *builder = commands;
+            builder
         })
         .await
-        .unwrap();
+        {
+            println!("Couldn't register the commands: {error}");
+        }
+    }
+}
+
+/// Whether the user has the administrator permission on the server, or is listed in the
+/// `BOT_ADMINS`.
+fn is_admin(permissions: Option<Permissions>, user_id: UserId, bot_admins: &[String]) -> bool {
+    permissions.is_some_and(|permissions| permissions.administrator())
+        || bot_admins.contains(&user_id.to_string())
+}
+
+/// The configured `not_implemented` message, or else [`NOT_IMPLEMENTED_MESSAGE`], naming the
+/// command.
+fn not_implemented_text(configured: Option<String>, command: &str) -> String {
+    configured
+        .as_deref()
+        .unwrap_or(NOT_IMPLEMENTED_MESSAGE)
+        .replace("{command}", command)
+}
+
+/// Whether the command was disabled on the guild, see [`Handler::is_disabled`]. Commands sent
+/// outside of a guild are never disabled.
+fn is_command_disabled<P: AsRef<Path>>(
+    storage: &Storage<P>,
+    guild_id: Option<GuildId>,
+    command: &str,
+) -> bool {
+    let Some(guild_id) = guild_id else {
+        return false;
+    };
+    if command == ToggleCommandCommand::NAME {
+        return false;
+    }
+    storage
+        .is_command_disabled(&guild_id.to_string(), command)
+        .unwrap_or(false)
+}
+
+/// During maintenance, only administrators can use the bot.
+fn check_maintenance(in_maintenance: bool, is_admin: bool) -> Result<()> {
+    if in_maintenance && !is_admin {
+        return Err(UserError::Maintenance.into());
+    }
+    Ok(())
+}
+
+/// Only administrators can use the [`ADMIN_COMMANDS`].
+fn check_admin_command(name: &str, is_admin: bool) -> Result<()> {
+    if ADMIN_COMMANDS.contains(&name) && !is_admin {
+        return Err(UserError::NotAdmin.into());
+    }
+    Ok(())
+}
+
+/// Only administrators can use the [`ADMIN_COMPONENTS`].
+fn check_admin_component(custom_id: &str, is_admin: bool) -> Result<()> {
+    if ADMIN_COMPONENTS.contains(&custom_id) && !is_admin {
+        return Err(UserError::NotAdmin.into());
+    }
+    Ok(())
+}
+
+/// What a message asks the bot.
+#[derive(Debug, PartialEq)]
+enum MessageCommand {
+    /// Only mentioning the bot, optionally with `help`.
+    Help,
+}
+
+/// What the message asks the bot, if it's for the bot at all.
+fn parse_message(content: &str, bot_id: UserId) -> Option<MessageCommand> {
+    let content = content.trim();
+    // Nicknamed users are mentioned with a `!`.
+    let rest = [format!("<@{bot_id}>"), format!("<@!{bot_id}>")]
+        .iter()
+        .find_map(|mention| content.strip_prefix(mention.as_str()))?
+        .trim();
+    (rest.is_empty() || rest.eq_ignore_ascii_case("help")).then_some(MessageCommand::Help)
+}
+
+/// Run the handling of an interaction in its own task, with its correlation id and branding, so a
+/// panic is caught when joining it instead of leaving the interaction unanswered.
+async fn spawn_in_scope<F>(
+    correlation_id: String,
+    branding: Branding,
+    future: F,
+) -> Result<(), JoinError>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(CORRELATION_ID.scope(correlation_id, BRANDING.scope(branding, future))).await
+}
+
+/// Tell the user the interaction failed, as it may not have been answered before the panic.
+async fn report_panic(error: JoinError, ctx: &Context, interaction: &Interaction) {
+    let error = anyhow!("Panicked while handling the interaction: {error}");
+    match interaction {
+        Interaction::ApplicationCommand(command) => error_interaction(&error, ctx, command).await,
+        Interaction::MessageComponent(message_component) => {
+            let _ignored_result = error_message_component(&error, ctx, message_component).await;
+        }
+        _ => println!("[{}] Error: {error:?}", correlation_id()),
+    }
+}
+
+/// The commands of the bot, as registered on Discord.
+fn application_commands() -> CreateApplicationCommands {
+    let mut commands = CreateApplicationCommands::default();
+    commands
+        .create_slash_command::<UploadStoryCommand>()
+        .create_slash_command::<DeleteStoryCommand>()
+        .create_slash_command::<RestoreStoryCommand>()
+        .create_slash_command::<PlayCommand>()
+        .create_slash_command::<SearchCommand>()
+        .create_slash_command::<SetLanguageCommand>()
+        .create_slash_command::<SetLayoutCommand>()
+        .create_slash_command::<SetSingleMessageCommand>()
+        .create_slash_command::<SetDelayCommand>()
+        .create_slash_command::<StoriesCommand>()
+        .create_slash_command::<StopCommand>()
+        .create_slash_command::<ClearSessionCommand>()
+        .create_slash_command::<SessionsCommand>()
+        .create_slash_command::<EndingsCommand>()
+        .create_slash_command::<SetStartCommand>()
+        .create_slash_command::<TransferStoryCommand>()
+        .create_slash_command::<StoryMapCommand>()
+        .create_slash_command::<ReportBugCommand>()
+        .create_slash_command::<BugReportsCommand>()
+        .create_slash_command::<AnalyticsCommand>()
+        .create_slash_command::<VisitsCommand>()
+        .create_slash_command::<DropoffsCommand>()
+        .create_slash_command::<PreviewCommand>()
+        .create_slash_command::<LinksCommand>()
+        .create_slash_command::<RecentCommand>()
+        .create_slash_command::<SurpriseCommand>()
+        .create_slash_command::<GotoCommand>()
+        .create_slash_command::<ValidateStoryCommand>()
+        .create_slash_command::<ExportSessionCommand>()
+        .create_slash_command::<ImportSessionCommand>()
+        .create_slash_command::<SaveCommand>()
+        .create_slash_command::<LoadCommand>()
+        .create_slash_command::<SavesCommand>()
+        .create_slash_command::<ReloadCacheCommand>()
+        .create_slash_command::<ToggleCommandCommand>()
+        .create_slash_command::<VacuumCommand>()
+        .create_slash_command::<StorageCommand>()
+        .create_slash_command::<SetFreeNavigationCommand>()
+        .create_slash_command::<JumpToCommand>()
+        .create_slash_command::<SetPathCommand>()
+        .create_slash_command::<DemoCommand>()
+        .create_slash_command::<SeriesCommand>()
+        .create_slash_command::<SetSeriesCommand>()
+        .create_slash_command::<VersionCommand>();
+    commands
+}
+
+/// The names of the commands `/togglecommand` can disable, all but itself.
+fn toggleable_commands() -> Vec<String> {
+    application_commands()
+        .0
+        .iter()
+        .filter_map(|command| command["name"].as_str())
+        .filter(|name| *name != ToggleCommandCommand::NAME)
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const BOT_ID: UserId = UserId(1234);
+
+    #[test]
+    fn mentions_of_the_bot_ask_for_help() {
+        assert_eq!(parse_message("<@1234>", BOT_ID), Some(MessageCommand::Help));
+        assert_eq!(
+            parse_message(" <@!1234> ", BOT_ID),
+            Some(MessageCommand::Help)
+        );
+        assert_eq!(
+            parse_message("<@1234> HELP", BOT_ID),
+            Some(MessageCommand::Help)
+        );
+    }
+
+    #[test]
+    fn other_messages_are_ignored() {
+        assert_eq!(parse_message("", BOT_ID), None);
+        assert_eq!(parse_message("help", BOT_ID), None);
+        assert_eq!(parse_message("<@5678>", BOT_ID), None);
+        assert_eq!(parse_message("<@12345>", BOT_ID), None);
+        assert_eq!(parse_message("<@1234> what is this?", BOT_ID), None);
+        assert_eq!(parse_message("Ask <@1234>", BOT_ID), None);
+    }
+
+    #[test]
+    fn administrators_of_the_server_are_admins() {
+        assert!(is_admin(Some(Permissions::ADMINISTRATOR), UserId(1), &[]));
+        assert!(is_admin(
+            Some(Permissions::ADMINISTRATOR | Permissions::SEND_MESSAGES),
+            UserId(1),
+            &["2".to_string()]
+        ));
+    }
+
+    #[test]
+    fn bot_admins_are_admins_without_the_permission() {
+        let bot_admins = ["2".to_string(), "3".to_string()];
+        assert!(is_admin(
+            Some(Permissions::SEND_MESSAGES),
+            UserId(3),
+            &bot_admins
+        ));
+        assert!(is_admin(None, UserId(2), &bot_admins));
+    }
+
+    #[test]
+    fn other_members_are_not_admins() {
+        let bot_admins = ["2".to_string()];
+        assert!(!is_admin(
+            Some(Permissions::MANAGE_GUILD),
+            UserId(1),
+            &bot_admins
+        ));
+        assert!(!is_admin(None, UserId(1), &bot_admins));
+        assert!(!is_admin(None, UserId(22), &bot_admins));
+    }
+
+    #[test]
+    fn unknown_commands_are_named_in_the_default_message() {
+        assert_eq!(
+            not_implemented_text(None, "dance"),
+            "Command `dance` not implemented :("
+        );
+    }
+
+    #[test]
+    fn unknown_commands_are_named_in_the_configured_message() {
+        assert_eq!(
+            not_implemented_text(Some("`/{command}` is coming soon".to_string()), "dance"),
+            "`/dance` is coming soon"
+        );
+        assert_eq!(
+            not_implemented_text(Some("Not yet".to_string()), "dance"),
+            "Not yet"
+        );
+    }
+
+    #[test]
+    fn disabled_commands_are_disabled_on_their_guild_only() {
+        let folder = TempDir::new().unwrap();
+        let storage = Storage::new(folder.path(), Duration::from_secs(60)).unwrap();
+        storage
+            .set_command_disabled("1", PlayCommand::NAME, true)
+            .unwrap();
+
+        assert!(is_command_disabled(
+            &storage,
+            Some(GuildId(1)),
+            PlayCommand::NAME
+        ));
+        assert!(!is_command_disabled(
+            &storage,
+            Some(GuildId(2)),
+            PlayCommand::NAME
+        ));
+        assert!(!is_command_disabled(&storage, None, PlayCommand::NAME));
+        assert!(!is_command_disabled(
+            &storage,
+            Some(GuildId(1)),
+            StopCommand::NAME
+        ));
+    }
+
+    #[test]
+    fn the_toggle_command_is_never_disabled() {
+        let folder = TempDir::new().unwrap();
+        let storage = Storage::new(folder.path(), Duration::from_secs(60)).unwrap();
+        storage
+            .set_command_disabled("1", ToggleCommandCommand::NAME, true)
+            .unwrap();
+
+        assert!(!is_command_disabled(
+            &storage,
+            Some(GuildId(1)),
+            ToggleCommandCommand::NAME
+        ));
+    }
+
+    #[tokio::test]
+    async fn panics_of_the_handling_are_caught() {
+        let handled = spawn_in_scope("1234abcd".to_string(), Branding::default(), async {
+            panic!("Broken handler");
+        })
+        .await;
+
+        assert!(handled.unwrap_err().is_panic());
+    }
+
+    #[tokio::test]
+    async fn interactions_are_handled_in_their_scope() {
+        let handled = spawn_in_scope("1234abcd".to_string(), Branding::default(), async {
+            assert_eq!(correlation_id(), "1234abcd");
+            assert!(BRANDING.try_with(|_| ()).is_ok());
+        })
+        .await;
+
+        assert!(handled.is_ok());
+    }
+
+    #[test]
+    fn administrators_use_the_admin_commands() {
+        assert!(check_admin_command(UploadStoryCommand::NAME, true).is_ok());
+        for name in [
+            UploadStoryCommand::NAME,
+            DeleteStoryCommand::NAME,
+            ClearSessionCommand::NAME,
+        ] {
+            let error = check_admin_command(name, false).unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<UserError>(),
+                Some(UserError::NotAdmin)
+            ));
+        }
+    }
+
+    #[test]
+    fn bot_admins_use_the_admin_commands_without_the_permission() {
+        let bot_admins = ["2".to_string()];
+        let is_bot_admin = is_admin(Some(Permissions::SEND_MESSAGES), UserId(2), &bot_admins);
+        assert!(check_admin_command(UploadStoryCommand::NAME, is_bot_admin).is_ok());
+
+        let is_member = is_admin(Some(Permissions::SEND_MESSAGES), UserId(1), &bot_admins);
+        assert!(check_admin_command(UploadStoryCommand::NAME, is_member).is_err());
+    }
+
+    #[test]
+    fn other_commands_are_for_everyone() {
+        assert!(check_admin_command(PlayCommand::NAME, false).is_ok());
+        assert!(check_admin_command(StoriesCommand::NAME, false).is_ok());
+    }
+
+    #[test]
+    fn administrators_use_the_admin_components() {
+        assert!(check_admin_component(DELETE_STORY_MENU, true).is_ok());
+        let error = check_admin_component(DELETE_STORY_MENU, false).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<UserError>(),
+            Some(UserError::NotAdmin)
+        ));
+    }
+
+    #[test]
+    fn other_components_are_for_everyone() {
+        assert!(check_admin_component(START_STORY_MENU, false).is_ok());
+        assert!(check_admin_component(THE_END, false).is_ok());
+    }
+
+    #[test]
+    fn administrators_use_the_bot_during_maintenance() {
+        assert!(check_maintenance(true, true).is_ok());
+        assert!(check_maintenance(false, true).is_ok());
+    }
+
+    #[test]
+    fn other_users_are_turned_away_during_maintenance() {
+        let error = check_maintenance(true, false).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<UserError>(),
+            Some(UserError::Maintenance)
+        ));
+        assert!(check_maintenance(false, false).is_ok());
     }
 }
@@ -0,0 +1,79 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use serenity::prelude::Mutex;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use crate::persistance::Storage;
+
+/// How long a probe waits for the storage, busy with a long operation, before reporting it as
+/// unavailable.
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Serve `GET /health` on the port, answering 200 once the bot is ready and the database
+/// reachable, and 503 otherwise, for the liveness and readiness probes of orchestrators.
+pub async fn serve_health(
+    port: u16,
+    ready: Arc<AtomicBool>,
+    storage: Arc<Mutex<Storage<String>>>,
+) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    println!("Health check listening on port {port}");
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let ready = ready.clone();
+            let storage = storage.clone();
+            // Each probe waits for the storage on its own, so a slow one never blocks the others.
+            tokio::spawn(async move {
+                let healthy = ready.load(Ordering::Relaxed)
+                    && matches!(
+                        timeout(STORAGE_TIMEOUT, storage.lock()).await,
+                        Ok(storage) if storage.ping().is_ok()
+                    );
+                if let Err(error) = respond(stream, healthy).await {
+                    println!("Couldn't answer health check: {error}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn respond(mut stream: TcpStream, healthy: bool) -> Result<()> {
+    // Only the request line matters, anything after it is ignored.
+    let mut buffer = [0; 1024];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/health")) if healthy => ("200 OK", "ok"),
+        (Some("GET"), Some("/health")) => ("503 Service Unavailable", "not ready"),
+        _ => ("404 Not Found", "not found"),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -1,6 +1,9 @@
-use anyhow::{anyhow, Result};
-use reqwest::Client;
+use anyhow::{Error, Result};
+use std::time::Duration;
+
+use reqwest::{Client, Response, StatusCode};
 use serenity::{
+    builder::{CreateComponents, CreateEmbed},
     model::prelude::{
         command::CommandOptionType,
         interaction::{
@@ -8,15 +11,118 @@ use serenity::{
             message_component::MessageComponentInteraction,
             InteractionResponseType,
         },
-        Attachment,
+        UserId,
     },
     prelude::Context,
 };
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::{error::Elapsed, sleep, timeout},
+};
 
-use crate::{persistance::SaveStory, utils::story_title, Handler};
+use crate::{
+    config::Config,
+    error::{correlation_id, ErrorResponse, UserError},
+    persistance::{SaveStory, DEFAULT_TRASH_RETENTION_DAYS},
+    play::{add_resume_button, ChoiceLayout},
+    utils::{
+        diff_stories, parse_and_validate, sanitize_display, story_title, truncate_label, Grade,
+        MAX_OPTION_LABEL_LENGTH,
+    },
+    Handler,
+};
 
 pub const DELETE_STORY_MENU: &str = "delete_story_menu";
 
+/// How many characters of the filename of an upload are shown, unless `[upload] max_filename_length`
+/// is set.
+const DEFAULT_MAX_FILENAME_LENGTH: usize = 64;
+
+/// Discord rejects messages with more than 10 embeds, embeds with more than 25 fields, and
+/// messages whose embeds have more than 6000 characters in all.
+const MAX_EMBEDS: usize = 10;
+const MAX_EMBED_FIELDS: usize = 25;
+pub const MAX_EMBEDS_LENGTH: usize = 6000;
+const MAX_FIELD_NAME_LENGTH: usize = 256;
+const MAX_FIELD_VALUE_LENGTH: usize = 1024;
+
+/// How long an upload waits for the other downloads to end, while the deferred response says
+/// it's being worked on.
+const DOWNLOAD_QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long downloading an uploaded story can take, retries included, unless `[download] timeout`
+/// is set.
+const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many problems of an uploaded story are listed.
+const MAX_UPLOAD_FINDINGS: usize = 15;
+/// How many changes of an updated story are listed.
+const MAX_UPLOAD_CHANGES: usize = 15;
+
+/// Shown while a slow command is being worked on, unless `[messages] working` is set.
+const WORKING_MESSAGE: &str = "Working on it…";
+
+/// Who the embeds come from, set by `EMBED_AUTHOR_NAME` and `EMBED_AUTHOR_ICON_URL`.
+#[derive(Clone, Default)]
+pub struct Branding {
+    name: Option<String>,
+    icon_url: Option<String>,
+}
+
+tokio::task_local! {
+    /// The branding of the interaction being handled, read from the config when it started.
+    pub static BRANDING: Branding;
+}
+
+impl Branding {
+    /// Icon URLs Discord wouldn't load are left out, see [`is_valid_icon_url`].
+    pub fn new(config: &Config) -> Self {
+        Self {
+            name: config
+                .get_string("EMBED_AUTHOR_NAME")
+                .filter(|name| !name.trim().is_empty()),
+            icon_url: config
+                .get_string("EMBED_AUTHOR_ICON_URL")
+                .filter(|url| is_valid_icon_url(url)),
+        }
+    }
+}
+
+/// Whether Discord can show the image at the URL as an icon: only http and https are fetched.
+pub fn is_valid_icon_url(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Show the embed as coming from the branding of the interaction. Discord needs a name for the
+/// author, so the icon is only shown along one.
+pub fn branded(embed: &mut CreateEmbed) -> &mut CreateEmbed {
+    let _outside_interaction = BRANDING.try_with(|branding| {
+        if let Some(name) = &branding.name {
+            embed.author(|author| {
+                author.name(name);
+                if let Some(icon_url) = &branding.icon_url {
+                    author.icon_url(icon_url);
+                }
+                author
+            });
+        }
+    });
+    embed
+}
+
+/// How many characters the branding adds to an embed, counted by Discord in the length of
+/// the message.
+pub fn branding_length() -> usize {
+    BRANDING
+        .try_with(|branding| {
+            branding
+                .name
+                .as_ref()
+                .map_or(0, |name| name.chars().count())
+        })
+        .unwrap_or_default()
+}
+
 pub async fn text_interaction<T: ToString>(
     text: T,
     ctx: &Context,
@@ -28,13 +134,528 @@ pub async fn text_interaction<T: ToString>(
                 .kind(InteractionResponseType::ChannelMessageWithSource)
                 .interaction_response_data(|response| {
                     response
-                        .embed(|embed| embed.title("Action").description(text))
+                        .embed(|embed| branded(embed).title("Action").description(text))
+                        .ephemeral(true)
+                })
+        })
+        .await
+    {
+        println!(
+            "[{}] Cannot respond to slash command: {why}",
+            correlation_id()
+        );
+    }
+}
+
+/// Respond to the command with the fields, as `(name, value)`, split over as many embeds as
+/// Discord allows, and the components. The fields that don't fit are dropped, and counted in the
+/// footer of the last embed.
+pub async fn fields_interaction(
+    title: &str,
+    fields: &[(String, String)],
+    components: CreateComponents,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let embeds = field_embeds(title, fields);
+    if let Err(why) = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|response| {
+                    response
+                        .set_embeds(embeds)
+                        .set_components(components)
+                        .ephemeral(true)
+                })
+        })
+        .await
+    {
+        println!(
+            "[{}] Cannot respond to slash command: {why}",
+            correlation_id()
+        );
+    }
+}
+
+fn field_embeds(title: &str, fields: &[(String, String)]) -> Vec<CreateEmbed> {
+    // Every embed repeats the title and the branding.
+    let embed_length = title.chars().count() + branding_length();
+    // Leaves room for the footer.
+    let mut length = 100;
+    let mut embeds: Vec<CreateEmbed> = vec![];
+    let mut field_count = 0;
+    for (name, value) in fields {
+        let name = truncate_label(name, MAX_FIELD_NAME_LENGTH);
+        let value = truncate_label(value, MAX_FIELD_VALUE_LENGTH);
+        let new_embed = field_count % MAX_EMBED_FIELDS == 0;
+        length += name.chars().count() + value.chars().count();
+        if new_embed {
+            length += embed_length;
+        }
+        if length > MAX_EMBEDS_LENGTH {
+            break;
+        }
+        if new_embed {
+            if embeds.len() == MAX_EMBEDS {
+                break;
+            }
+            let mut embed = CreateEmbed::default();
+            branded(&mut embed);
+            embed.title(title);
+            embeds.push(embed);
+        }
+        if let Some(embed) = embeds.last_mut() {
+            embed.field(name, value, false);
+        }
+        field_count += 1;
+    }
+
+    if embeds.is_empty() {
+        let mut embed = CreateEmbed::default();
+        branded(&mut embed);
+        embed.title(title);
+        embeds.push(embed);
+    }
+    if field_count < fields.len() {
+        let dropped = fields.len() - field_count;
+        println!(
+            "[{}] Only {field_count} of {} fields fit in the message",
+            correlation_id(),
+            fields.len()
+        );
+        if let Some(embed) = embeds.last_mut() {
+            embed.footer(|footer| footer.text(format!("And {dropped} more.")));
+        }
+    }
+    embeds
+}
+
+/// The value of a string option of the command.
+pub fn string_option<'a>(
+    command: &'a ApplicationCommandInteraction,
+    name: &str,
+) -> Option<&'a str> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| match &option.resolved {
+            Some(CommandDataOptionValue::String(value)) => Some(value.as_str()),
+            _ => None,
+        })
+}
+
+/// The id of the user picked in a user option of the command.
+pub fn user_option(command: &ApplicationCommandInteraction, name: &str) -> Option<UserId> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| match &option.resolved {
+            Some(CommandDataOptionValue::User(user, _)) => Some(user.id),
+            _ => None,
+        })
+}
+
+/// The value of a boolean option of the command.
+pub fn bool_option(command: &ApplicationCommandInteraction, name: &str) -> Option<bool> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| match &option.resolved {
+            Some(CommandDataOptionValue::Boolean(value)) => Some(*value),
+            _ => None,
+        })
+}
+
+/// The value of an integer option of the command.
+pub fn integer_option(command: &ApplicationCommandInteraction, name: &str) -> Option<i64> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| match &option.resolved {
+            Some(CommandDataOptionValue::Integer(value)) => Some(*value),
+            _ => None,
+        })
+}
+
+/// Respond to the command with what went wrong.
+pub async fn error_interaction(
+    error: &Error,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let response = ErrorResponse::new(error);
+    if let Err(why) = command
+        .create_interaction_response(&ctx.http, |interaction_response| {
+            interaction_response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| {
+                    data.embed(|embed| error_embed(embed, &response))
+                        .ephemeral(true)
+                })
+        })
+        .await
+    {
+        println!(
+            "[{}] Cannot respond to slash command: {why}",
+            correlation_id()
+        );
+    }
+}
+
+/// Replace the message of the component with what went wrong.
+pub async fn error_message_component(
+    error: &Error,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let response = ErrorResponse::new(error);
+    message_component
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| {
+                    d.embed(|embed| error_embed(embed, &response))
+                        .components(|c| c)
+                })
+        })
+        .await?;
+    Ok(())
+}
+
+fn error_embed<'a>(embed: &'a mut CreateEmbed, response: &ErrorResponse) -> &'a mut CreateEmbed {
+    branded(embed).title("Error").description(&response.message);
+    if let Some(correlation_id) = &response.correlation_id {
+        embed.footer(|footer| footer.text(format!("Error id: {correlation_id}")));
+    }
+    embed
+}
+
+pub async fn set_language_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let guild_id = if let Some(guild_id) = command.guild_id {
+        guild_id.to_string()
+    } else {
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
+        return;
+    };
+    let language = string_option(command, "language").map(str::trim);
+
+    let storage = handler.storage.lock().await;
+    let answer = match language {
+        Some(language) if !is_language_code(language) => Ok(format!(
+            "`{language}` is not a language code, like `en` or `pt-BR`"
+        )),
+        Some(language) => storage
+            .set_guild_language(&guild_id, language)
+            .map(|_| format!("The language of the server is now `{language}`")),
+        None => storage
+            .get_guild_language(&guild_id)
+            .map(|language| format!("The language of the server is `{language}`")),
+    };
+    drop(storage);
+
+    match answer {
+        Ok(answer) => text_interaction(answer, ctx, command).await,
+        Err(error) => error_interaction(&error, ctx, command).await,
+    }
+}
+
+pub async fn set_layout_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let guild_id = if let Some(guild_id) = command.guild_id {
+        guild_id.to_string()
+    } else {
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
+        return;
+    };
+    let layout = string_option(command, "layout").map(str::parse::<ChoiceLayout>);
+
+    let storage = handler.storage.lock().await;
+    let answer = match layout {
+        Some(Err(error)) => Err(error),
+        Some(Ok(layout)) => storage
+            .set_guild_layout(&guild_id, layout)
+            .map(|_| format!("The choice layout of the server is now `{layout}`")),
+        None => storage
+            .get_guild_layout(&guild_id)
+            .map(|layout| format!("The choice layout of the server is `{layout}`")),
+    };
+    drop(storage);
+
+    match answer {
+        Ok(answer) => text_interaction(answer, ctx, command).await,
+        Err(error) => error_interaction(&error, ctx, command).await,
+    }
+}
+
+pub async fn set_single_message_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let guild_id = if let Some(guild_id) = command.guild_id {
+        guild_id.to_string()
+    } else {
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
+        return;
+    };
+    let enabled = bool_option(command, "enabled");
+
+    let storage = handler.storage.lock().await;
+    let answer = match enabled {
+        Some(enabled) => storage
+            .set_guild_single_message(&guild_id, enabled)
+            .map(|_| single_message_description(enabled, "now ")),
+        None => storage
+            .get_guild_single_message(&guild_id)
+            .map(|enabled| single_message_description(enabled, "")),
+    };
+    drop(storage);
+
+    match answer {
+        Ok(answer) => text_interaction(answer, ctx, command).await,
+        Err(error) => error_interaction(&error, ctx, command).await,
+    }
+}
+
+/// Disable the command on the guild, or enable it again. `command_names` are the commands that
+/// can be toggled.
+pub async fn toggle_command_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    command_names: &[String],
+) {
+    let guild_id = if let Some(guild_id) = command.guild_id {
+        guild_id.to_string()
+    } else {
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
+        return;
+    };
+    let name = string_option(command, "name")
+        .unwrap_or_default()
+        .trim()
+        .trim_start_matches('/');
+    if !command_names
+        .iter()
+        .any(|command_name| command_name == name)
+    {
+        error_interaction(
+            &UserError::UnknownCommand(name.to_string()).into(),
+            ctx,
+            command,
+        )
+        .await;
+        return;
+    }
+
+    let storage = handler.storage.lock().await;
+    let answer = storage
+        .is_command_disabled(&guild_id, name)
+        .and_then(|disabled| {
+            storage.set_command_disabled(&guild_id, name, !disabled)?;
+            Ok(match disabled {
+                true => format!("`/{name}` is enabled again on this server"),
+                false => format!("`/{name}` is now disabled on this server"),
+            })
+        });
+    drop(storage);
+
+    match answer {
+        Ok(answer) => text_interaction(answer, ctx, command).await,
+        Err(error) => error_interaction(&error, ctx, command).await,
+    }
+}
+
+pub async fn set_delay_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let guild_id = if let Some(guild_id) = command.guild_id {
+        guild_id.to_string()
+    } else {
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
+        return;
+    };
+    let milliseconds = integer_option(command, "milliseconds");
+
+    let storage = handler.storage.lock().await;
+    let answer = match milliseconds {
+        Some(milliseconds) => storage
+            .set_guild_delay(&guild_id, Duration::from_millis(milliseconds as u64))
+            .map(|_| format!("Passages now show up {milliseconds}ms after a choice")),
+        None => storage
+            .get_guild_delay(&guild_id)
+            .map(|delay| format!("Passages show up {}ms after a choice", delay.as_millis())),
+    };
+    drop(storage);
+
+    match answer {
+        Ok(answer) => text_interaction(answer, ctx, command).await,
+        Err(error) => error_interaction(&error, ctx, command).await,
+    }
+}
+
+fn single_message_description(enabled: bool, now: &str) -> String {
+    if enabled {
+        format!("New stories {now}show every passage in the same message")
+    } else {
+        format!("New stories {now}show every passage in a new message")
+    }
+}
+
+/// Language codes as used by Discord locales, like `fr` or `pt-BR`.
+fn is_language_code(language: &str) -> bool {
+    let mut parts = language.split('-');
+    let language_part = parts.next().unwrap_or_default();
+    let region = parts.next();
+
+    language_part.len() == 2
+        && language_part.chars().all(|c| c.is_ascii_lowercase())
+        && region
+            .iter()
+            .all(|region| region.len() == 2 && region.chars().all(|c| c.is_ascii_uppercase()))
+        && parts.next().is_none()
+}
+
+pub async fn reload_cache_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let count = handler.storage.lock().await.invalidate_cache();
+
+    text_interaction(
+        format!("Cache cleared, {count} cached stories will be reloaded from their files"),
+        ctx,
+        command,
+    )
+    .await;
+}
+
+pub async fn vacuum_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    // Compacting a large database can take longer than Discord waits for a response.
+    if let Err(why) = working_interaction(handler, ctx, command).await {
+        println!(
+            "[{}] Cannot respond to slash command: {why}",
+            correlation_id()
+        );
+        return;
+    }
+
+    // Every other command waits on the storage until it's done.
+    let vacuumed = handler.storage.lock().await.vacuum();
+    match vacuumed {
+        Ok((before, after)) => {
+            println!(
+                "[{}] Vacuumed the database from {before} to {after} bytes",
+                correlation_id()
+            );
+            followup_text(
+                format!(
+                    "Database compacted from {} to {}",
+                    format_size(before),
+                    format_size(after)
+                ),
+                ctx,
+                command,
+            )
+            .await;
+        }
+        Err(error) => followup_error(&error, ctx, command).await,
+    }
+}
+
+pub async fn storage_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let Some(guild_id) = command.guild_id.map(|guild_id| guild_id.to_string()) else {
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
+        return;
+    };
+
+    let usage = {
+        let storage = handler.storage.lock().await;
+        storage
+            .list_guild_stories(&guild_id, false)
+            .and_then(|stories| Ok((stories.len(), storage.guild_storage_bytes(&guild_id)?)))
+    };
+    match usage {
+        Ok((count, bytes)) => {
+            text_interaction(
+                format!(
+                    "Stories of this server: {count}, using {}",
+                    format_size(bytes)
+                ),
+                ctx,
+                command,
+            )
+            .await;
+        }
+        Err(error) => error_interaction(&error, ctx, command).await,
+    }
+}
+
+/// A size in bytes, in the largest unit keeping it above 1, like `1.5 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} bytes");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+pub async fn version_interaction(ctx: &Context, command: &ApplicationCommandInteraction) {
+    if let Err(why) = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|response| {
+                    response
+                        .embed(|embed| {
+                            branded(embed)
+                                .title("Version")
+                                .field("Story Teller", env!("CARGO_PKG_VERSION"), true)
+                                .field("Commit", env!("GIT_COMMIT"), true)
+                                .field("twee_v3", env!("TWEE_V3_VERSION"), true)
+                                .field("serenity", env!("SERENITY_VERSION"), true)
+                        })
                         .ephemeral(true)
                 })
         })
         .await
     {
-        println!("Cannot respond to slash command: {why}");
+        println!(
+            "[{}] Cannot respond to slash command: {why}",
+            correlation_id()
+        );
     }
 }
 
@@ -46,10 +667,31 @@ pub async fn upload_story_interaction(
     let guild_id = if let Some(guild_id) = command.guild_id {
         guild_id.to_string()
     } else {
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
         return;
     };
 
-    if let Some(attachment) = command
+    // Downloading can take longer than Discord waits for a response.
+    if let Err(why) = working_interaction(handler, ctx, command).await {
+        println!(
+            "[{}] Cannot respond to slash command: {why}",
+            correlation_id()
+        );
+        return;
+    }
+
+    match upload_story(handler, &guild_id, command).await {
+        Ok(answer) => followup_text(answer, ctx, command).await,
+        Err(error) => followup_error(&error, ctx, command).await,
+    }
+}
+
+async fn upload_story(
+    handler: &Handler,
+    guild_id: &str,
+    command: &ApplicationCommandInteraction,
+) -> Result<String> {
+    let Some(attachment) = command
         .data
         .options
         .iter()
@@ -58,51 +700,303 @@ pub async fn upload_story_interaction(
             Some(CommandDataOptionValue::Attachment(attachment)) => Some(attachment),
             _ => None,
         })
+    else {
+        return Ok("No attachment found".to_string());
+    };
+
+    let (attempts, backoff, download_timeout, max_filename_length) = {
+        let config = handler.config();
+        (
+            config
+                .get_parsed_path(&["download", "attempts"])
+                .unwrap_or(3),
+            Duration::from_millis(
+                config
+                    .get_parsed_path(&["download", "backoff_ms"])
+                    .unwrap_or(500),
+            ),
+            config
+                .get_parsed_path(&["download", "timeout"])
+                .map_or(DEFAULT_DOWNLOAD_TIMEOUT, Duration::from_secs),
+            config
+                .get_parsed_path(&["upload", "max_filename_length"])
+                .unwrap_or(DEFAULT_MAX_FILENAME_LENGTH),
+        )
+    };
+    let dry_run = bool_option(command, "dry_run").unwrap_or(false);
+    // Both come from the uploader, and are shown in code spans.
+    let filename = sanitize_display(&attachment.filename, max_filename_length);
+
+    // Held until the story is saved, as its content stays in memory until then.
+    let _permit = download_permit(&handler.downloads, DOWNLOAD_QUEUE_TIMEOUT).await?;
+    let content = match download_story(&attachment.url, attempts, backoff, download_timeout).await {
+        Ok(Ok(content)) => content,
+        Ok(Err(_)) => return Ok(format!("Couldn't download `{filename}`")),
+        Err(_) => {
+            println!(
+                "[{}] Download timed out after {download_timeout:?}",
+                correlation_id()
+            );
+            return Ok(format!(
+                "Downloading `{filename}` timed out, try again later"
+            ));
+        }
+    };
+    let report = match parse_and_validate(&content) {
+        Ok(report) => report,
+        Err(error) => {
+            println!("[{}] Parsing the story panicked: {error}", correlation_id());
+            return Ok(format!("`{filename}` is not a valid story"));
+        }
+    };
+    let summary = report.summary(MAX_UPLOAD_FINDINGS);
+    let Some(story_title) = story_title(&content).filter(|_| report.grade() != Grade::Fail) else {
+        return Ok(format!("`{filename}` is not a valid story:\n{summary}"));
+    };
+    let (saved, previous) = {
+        let mut storage = handler.storage.lock().await;
+        let previous = storage
+            .find_guild_story(guild_id, &story_title)?
+            .and_then(|story_id| storage.get_story_content(story_id).ok());
+        let saved = match dry_run {
+            true => storage.check_story(guild_id, &content)?,
+            false => storage.save_story(guild_id, &content)?,
+        };
+        (saved, previous)
+    };
+    let story_title = sanitize_display(&story_title, MAX_OPTION_LABEL_LENGTH);
+
+    let mut answer = match (&saved, dry_run) {
+        (SaveStory::New, false) => {
+            format!("Successfully uploaded `{filename}`, creating story `{story_title}`")
+        }
+        (SaveStory::Update, false) => {
+            format!("Successfully uploaded `{filename}`, updating existing story `{story_title}`")
+        }
+        (SaveStory::New, true) => {
+            format!("Checked `{filename}`, uploading it would create story `{story_title}`")
+        }
+        (SaveStory::Update, true) => format!(
+            "Checked `{filename}`, uploading it would update existing story `{story_title}`"
+        ),
+    };
+    // What the update changed, for the author to check it's what they meant.
+    if let (SaveStory::Update, Some(previous)) = (saved, previous) {
+        let diff = diff_stories(&previous, &content);
+        answer = match diff.is_empty() {
+            true => format!("{answer}, with the same passages and links"),
+            false => format!("{answer}:\n{}", diff.summary(MAX_UPLOAD_CHANGES)),
+        };
+    }
+    if report.grade() == Grade::Warn {
+        return Ok(format!("{answer}, but it has problems:\n{summary}"));
+    }
+    Ok(answer)
+}
+
+/// The `[messages]` entry of the config in the language of the user, or else in the one of the
+/// guild set with `/setlanguage`.
+pub async fn configured_message(
+    handler: &Handler,
+    command: &ApplicationCommandInteraction,
+    key: &str,
+) -> Option<String> {
+    let guild_language = match command.guild_id {
+        Some(guild_id) => handler
+            .storage
+            .lock()
+            .await
+            .get_guild_language(&guild_id.to_string())
+            .ok(),
+        None => None,
+    };
+    let languages: Vec<&str> = [Some(command.locale.as_str()), guild_language.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect();
+    handler.config().get_message(key, &languages)
+}
+
+/// Defer the response to the command, showing a message while the bot is working on it.
+/// The answer must then be sent with [`followup_text`] or [`followup_error`].
+async fn working_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let text = configured_message(handler, command, "working")
+        .await
+        .unwrap_or_else(|| WORKING_MESSAGE.to_string());
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|data| data.ephemeral(true))
+        })
+        .await?;
+    command
+        .edit_original_interaction_response(&ctx.http, |response| {
+            response.embed(|embed| branded(embed).title("Action").description(text))
+        })
+        .await?;
+    Ok(())
+}
+
+async fn followup_text<T: ToString>(
+    text: T,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(why) = command
+        .create_followup_message(&ctx.http, |followup| {
+            followup
+                .embed(|embed| branded(embed).title("Action").description(text))
+                .ephemeral(true)
+        })
+        .await
+    {
+        println!(
+            "[{}] Cannot follow up slash command: {why}",
+            correlation_id()
+        );
+    }
+}
+
+async fn followup_error(error: &Error, ctx: &Context, command: &ApplicationCommandInteraction) {
+    let response = ErrorResponse::new(error);
+    if let Err(why) = command
+        .create_followup_message(&ctx.http, |followup| {
+            followup
+                .embed(|embed| error_embed(embed, &response))
+                .ephemeral(true)
+        })
+        .await
     {
-        if let Ok(content) = fetch_attachment(attachment).await {
-            let story_title = story_title(&content);
-            if story_title.is_some() {
-                let mut database = handler.storage.lock().await;
-                let answer = match database.save_story(&guild_id, &content) {
-                    Ok(save_story) => match save_story {
-                        SaveStory::New => {
-                            format!(
-                                "Successfully uploaded `{}`, creating story `{}`",
-                                attachment.filename,
-                                story_title.unwrap()
-                            )
-                        }
-                        SaveStory::Update => format!(
-                            "Successfully uploaded `{}`, updating existing story `{}`",
-                            attachment.filename,
-                            story_title.unwrap()
-                        ),
-                    },
-                    Err(_) => format!(
-                        "Error while uploading `{}`, try again later.",
-                        attachment.filename
-                    ),
-                };
-                text_interaction(answer, ctx, command).await;
-            } else {
-                text_interaction(
-                    format!("`{}` is not a valid story", attachment.filename),
-                    ctx,
-                    command,
-                )
-                .await;
-            }
-        } else {
-            text_interaction(
-                format!("Couldn't download `{}`", attachment.filename),
-                ctx,
-                command,
-            )
-            .await;
+        println!(
+            "[{}] Cannot follow up slash command: {why}",
+            correlation_id()
+        );
+    }
+}
+
+pub async fn list_stories_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let guild_id = if let Some(guild_id) = command.guild_id {
+        guild_id.to_string()
+    } else {
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
+        return;
+    };
+
+    let player_id = command.user.id.to_string();
+    let (stories, session) = {
+        let mut storage = handler.storage.lock().await;
+        let session = storage.retrieve_game_state(&player_id, &guild_id).ok();
+        (
+            storage.list_guild_stories_detailed(&guild_id, true),
+            session,
+        )
+    };
+    let stories = match stories {
+        Ok(stories) if stories.is_empty() => {
+            error_interaction(&UserError::NoStories.into(), ctx, command).await;
+            return;
+        }
+        Ok(stories) => stories,
+        Err(error) => {
+            error_interaction(&error, ctx, command).await;
+            return;
         }
+    };
+
+    // A shortcut to the session of the player, instead of `/play`.
+    let mut components = CreateComponents::default();
+    if let Some(story) =
+        session.and_then(|session| stories.iter().find(|story| story.id == session.story_id))
+    {
+        add_resume_button(&mut components, &story.name);
+    }
+
+    let fields: Vec<(String, String)> = stories
+        .into_iter()
+        .map(|story| {
+            // Discord shows these timestamps relatively, like "3 days ago".
+            let mut dates = vec![];
+            if let Some(created_at) = story.created_at {
+                dates.push(format!("Uploaded <t:{created_at}:R>"));
+            }
+            if let Some(modified_at) = story.modified_at {
+                if story.modified_at > story.created_at {
+                    dates.push(format!("updated <t:{modified_at}:R>"));
+                }
+            }
+            // Fields can't be empty.
+            if dates.is_empty() {
+                dates.push("Uploaded a while ago".to_string());
+            }
+            let mut value = dates.join(", ");
+            if let Some(rating) = story.rating {
+                value = format!("{rating}\n{value}");
+            }
+            if let Some(author) = story.author {
+                value = format!("By {author}\n{value}");
+            }
+            (story.name, value)
+        })
+        .collect();
+
+    fields_interaction("Stories", &fields, components, ctx, command).await;
+}
+
+pub async fn search_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    let guild_id = if let Some(guild_id) = command.guild_id {
+        guild_id.to_string()
     } else {
-        text_interaction("No attachment found", ctx, command).await;
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
+        return;
+    };
+    let query = string_option(command, "query").unwrap_or_default().trim();
+    if query.is_empty() {
+        text_interaction("Please tell what to search for", ctx, command).await;
+        return;
     }
+    let search_content = bool_option(command, "content").unwrap_or(false);
+
+    let results = handler
+        .storage
+        .lock()
+        .await
+        .search_stories(&guild_id, query, search_content);
+    let results = match results {
+        Ok(results) => results,
+        Err(error) => {
+            error_interaction(&error, ctx, command).await;
+            return;
+        }
+    };
+
+    let text = if results.is_empty() {
+        format!("No story matches `{query}`")
+    } else {
+        results
+            .iter()
+            .map(|result| match result.content_matches {
+                0 => format!("`{}`", result.name),
+                1 => format!("`{}`, 1 match in the story", result.name),
+                count => format!("`{}`, {count} matches in the story", result.name),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    text_interaction(text, ctx, command).await;
 }
 
 pub async fn delete_story_interaction(
@@ -113,44 +1007,45 @@ pub async fn delete_story_interaction(
     let guild_id = if let Some(guild_id) = command.guild_id {
         guild_id.to_string()
     } else {
+        error_interaction(&UserError::NoGuild.into(), ctx, command).await;
         return;
     };
 
     let text = "Please select the story you want to delete:";
     let database = handler.storage.lock().await;
-    let all_stories = database.list_guild_stories(&guild_id);
+    let all_stories = database.list_guild_stories(&guild_id, false);
 
-    let stories = if let Ok(stories) = all_stories {
-        stories
-    } else {
-        text_interaction(
-            "We couldn't list the stories, try again later.",
-            ctx,
-            command,
-        )
-        .await;
-        return;
+    let stories = match all_stories {
+        Ok(stories) if stories.is_empty() => {
+            error_interaction(&UserError::NoStories.into(), ctx, command).await;
+            return;
+        }
+        Ok(stories) => stories,
+        Err(error) => {
+            error_interaction(&error, ctx, command).await;
+            return;
+        }
     };
 
-    if stories.is_empty() {
-        text_interaction("There are no stories", ctx, command).await;
-        return;
-    }
-
     if let Err(why) = command
         .create_interaction_response(&ctx.http, |response| {
             response
                 .kind(InteractionResponseType::ChannelMessageWithSource)
                 .interaction_response_data(|message| {
                     message
-                        .embed(|embed| embed.title("Action").description(text))
+                        .embed(|embed| branded(embed).title("Action").description(text))
                         .components(|components| {
                             components.create_action_row(|row| {
                                 row.create_select_menu(|menu| {
                                     menu.custom_id(DELETE_STORY_MENU).options(|mut options| {
                                         for (story_id, story_name) in stories {
                                             options = options.create_option(|create_option| {
-                                                create_option.label(story_name).value(story_id)
+                                                create_option
+                                                    .label(truncate_label(
+                                                        &story_name,
+                                                        MAX_OPTION_LABEL_LENGTH,
+                                                    ))
+                                                    .value(story_id)
                                             });
                                         }
                                         options
@@ -163,7 +1058,10 @@ pub async fn delete_story_interaction(
         })
         .await
     {
-        println!("Cannot respond to slash command: {why}");
+        println!(
+            "[{}] Cannot respond to slash command: {why}",
+            correlation_id()
+        );
     }
 }
 
@@ -176,16 +1074,19 @@ pub async fn actual_deletion(
         .data
         .values
         .first()
-        .ok_or_else(|| anyhow!("No id selected"))
-        .and_then(|id| id.parse::<i64>().map_err(Into::into))?;
+        .ok_or(UserError::InvalidSelection)
+        .and_then(|id| id.parse::<i64>().map_err(|_| UserError::InvalidSelection))?;
 
     let mut database = handler.storage.lock().await;
-    let story_name = database.delete_story(story_id)?;
+    let story_name = database.soft_delete_story(story_id)?;
     drop(database);
 
     update_message_text(
         "Deletion",
-        format!("Story `{story_name}` successfully deleted"),
+        format!(
+            "Story `{story_name}` successfully deleted, `/restorestory` can bring it back for {} days",
+            trash_retention_days(handler)
+        ),
         ctx,
         message_component,
     )
@@ -194,6 +1095,68 @@ pub async fn actual_deletion(
     Ok(())
 }
 
+fn trash_retention_days(handler: &Handler) -> i64 {
+    handler
+        .config()
+        .get_parsed_path(&["trash", "retention_days"])
+        .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS)
+}
+
+pub async fn restore_story_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = restore_story_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+/// Restore the last deleted story of the name, or list the trash without a name.
+async fn restore_story_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+
+    let mut storage = handler.storage.lock().await;
+    let deleted = storage.deleted_stories(&guild_id)?;
+    let Some(name) = string_option(command, "story") else {
+        drop(storage);
+        let text = if deleted.is_empty() {
+            "No story was deleted lately.".to_string()
+        } else {
+            let retention = trash_retention_days(handler) * 24 * 60 * 60;
+            deleted
+                .iter()
+                .map(|story| {
+                    format!(
+                        "`{}`, deleted <t:{}:R>, kept until <t:{}:d>",
+                        story.name,
+                        story.deleted_at,
+                        story.deleted_at + retention
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        text_interaction(text, ctx, command).await;
+        return Ok(());
+    };
+
+    let story_id = deleted
+        .iter()
+        .find(|story| story.name.eq_ignore_ascii_case(name.trim()))
+        .map(|story| story.id)
+        .ok_or(UserError::UnknownStory)?;
+    let story_name = storage.restore_story(&guild_id, story_id)?;
+    drop(storage);
+
+    text_interaction(format!("Story `{story_name}` restored"), ctx, command).await;
+    Ok(())
+}
+
 pub async fn update_message_text<Ti: ToString, Te: ToString>(
     title: Ti,
     text: Te,
@@ -204,7 +1167,7 @@ pub async fn update_message_text<Ti: ToString, Te: ToString>(
         .create_interaction_response(&ctx.http, |r| {
             r.kind(InteractionResponseType::UpdateMessage)
                 .interaction_response_data(|d| {
-                    d.embed(|embed| embed.title(title).description(text))
+                    d.embed(|embed| branded(embed).title(title).description(text))
                         .components(|c| c)
                 })
         })
@@ -212,19 +1175,175 @@ pub async fn update_message_text<Ti: ToString, Te: ToString>(
     Ok(())
 }
 
-async fn fetch_attachment(attachment: &Attachment) -> Result<String, reqwest::Error> {
-    println!("Fetching attachment {}", attachment.url);
+/// Download the story at `url`, giving up after `download_timeout` even while retrying.
+async fn download_story(
+    url: &str,
+    attempts: u32,
+    backoff: Duration,
+    download_timeout: Duration,
+) -> Result<Result<String, reqwest::Error>, Elapsed> {
+    timeout(download_timeout, fetch_attachment(url, attempts, backoff)).await
+}
+
+/// A permit to download an uploaded story, waiting up to `queue_timeout` for another download to
+/// end. The bot is too busy if none does.
+async fn download_permit(
+    downloads: &Semaphore,
+    queue_timeout: Duration,
+) -> Result<SemaphorePermit<'_>> {
+    Ok(timeout(queue_timeout, downloads.acquire())
+        .await
+        .map_err(|_| UserError::ServerBusy)??)
+}
+
+/// Download the attachment, trying up to `attempts` times, waiting `backoff` before the first retry
+/// and doubling it for the next ones.
+async fn fetch_attachment(
+    url: &str,
+    attempts: u32,
+    mut backoff: Duration,
+) -> Result<String, reqwest::Error> {
+    let attempts = attempts.max(1);
+
+    println!("[{}] Fetching attachment {url}", correlation_id());
     // That is not ideal, but somehow there seems to be some issues with certificates and fly.io.
     // Fast fix.
     let client = Client::builder()
         .danger_accept_invalid_certs(true)
         .build()?;
 
-    match client.get(&attachment.url).send().await {
-        Ok(response) => response.text().await,
-        Err(e) => {
-            println!("Error while fetching attachment: {e}");
-            Err(e)
+    let mut attempt = 1;
+    loop {
+        let result = match client
+            .get(url)
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+        {
+            Ok(response) => response.text().await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(content) => return Ok(content),
+            Err(e) if attempt < attempts && is_retryable(&e) => {
+                println!(
+                    "[{}] Error while fetching attachment, attempt {attempt}/{attempts}: {e}",
+                    correlation_id()
+                );
+                sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => {
+                println!(
+                    "[{}] Error while fetching attachment: {e}",
+                    correlation_id()
+                );
+                return Err(e);
+            }
         }
     }
 }
+
+/// Server errors and transport errors might go away, client errors like a 404 won't.
+fn is_retryable(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        None => !error.is_builder(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// The characters of the embeds Discord counts against [`MAX_EMBEDS_LENGTH`].
+    fn embeds_length(embeds: &[CreateEmbed]) -> usize {
+        let length = |value: &Value| value.as_str().map_or(0, |text| text.chars().count());
+        embeds
+            .iter()
+            .map(|embed| {
+                let fields: usize = embed.0.get("fields").map_or(0, |fields| {
+                    fields
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|field| length(&field["name"]) + length(&field["value"]))
+                        .sum()
+                });
+                embed.0.get("title").map_or(0, length)
+                    + embed
+                        .0
+                        .get("author")
+                        .map_or(0, |author| length(&author["name"]))
+                    + embed
+                        .0
+                        .get("footer")
+                        .map_or(0, |footer| length(&footer["text"]))
+                    + fields
+            })
+            .sum()
+    }
+
+    #[test]
+    fn field_embeds_fit_in_a_message_with_the_branding() {
+        let branding = Branding {
+            name: Some("B".repeat(256)),
+            icon_url: None,
+        };
+        let fields: Vec<(String, String)> = (0..300)
+            .map(|index| (format!("Field {index}"), "v".repeat(20)))
+            .collect();
+
+        let embeds = BRANDING.sync_scope(branding, || field_embeds(&"T".repeat(200), &fields));
+
+        assert!(embeds.len() <= MAX_EMBEDS);
+        assert!(embeds_length(&embeds) <= MAX_EMBEDS_LENGTH);
+        assert!(embeds.last().unwrap().0.contains_key("footer"));
+    }
+
+    #[tokio::test]
+    async fn slow_downloads_time_out() {
+        // A server accepting the connection, but never answering.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/story.twee", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            sleep(Duration::from_secs(60)).await;
+        });
+
+        let download = download_story(
+            &url,
+            3,
+            Duration::from_millis(10),
+            Duration::from_millis(200),
+        )
+        .await;
+
+        assert!(download.is_err());
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn downloads_wait_for_a_permit() {
+        let downloads = Semaphore::new(1);
+        let permit = download_permit(&downloads, Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        let busy = download_permit(&downloads, Duration::from_millis(10)).await;
+        assert!(matches!(
+            busy.unwrap_err().downcast_ref(),
+            Some(UserError::ServerBusy)
+        ));
+
+        drop(permit);
+        assert!(download_permit(&downloads, Duration::from_millis(10))
+            .await
+            .is_ok());
+    }
+}
@@ -1,29 +1,123 @@
-use std::env;
+use std::{
+    env, fs,
+    path::Path,
+    sync::{atomic::AtomicBool, Arc, RwLock},
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use clock::TokioClock;
 use config::Config;
 use handler::Handler;
-use persistance::Storage;
+use health::serve_health;
+use interaction::is_valid_icon_url;
+use persistance::{now, Storage, DEFAULT_TRASH_RETENTION_DAYS};
+use random::{Rng, SeededRng, ThreadRng};
 use serenity::{framework::standard::StandardFramework, prelude::*};
+use tokio::sync::Semaphore;
 
+mod author;
+mod clock;
 mod collections;
 mod command;
 mod config;
+mod error;
 mod handler;
+mod health;
 mod interaction;
 mod persistance;
 mod play;
+mod random;
 mod utils;
 
 const CONFIG_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml");
+const SAVE_FOLDER: &str = "save";
+/// How often the stories left in the trash past their retention are deleted for good.
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How many uploaded stories are downloaded at once, unless `[download] max_concurrent` is set.
+const DEFAULT_MAX_DOWNLOADS: usize = 4;
+
+/// The names `GATEWAY_INTENTS` accepts, those of the Discord documentation.
+const INTENT_NAMES: &[(&str, GatewayIntents)] = &[
+    ("NON_PRIVILEGED", GatewayIntents::non_privileged()),
+    ("GUILDS", GatewayIntents::GUILDS),
+    ("GUILD_MEMBERS", GatewayIntents::GUILD_MEMBERS),
+    ("GUILD_BANS", GatewayIntents::GUILD_BANS),
+    (
+        "GUILD_EMOJIS_AND_STICKERS",
+        GatewayIntents::GUILD_EMOJIS_AND_STICKERS,
+    ),
+    ("GUILD_INTEGRATIONS", GatewayIntents::GUILD_INTEGRATIONS),
+    ("GUILD_WEBHOOKS", GatewayIntents::GUILD_WEBHOOKS),
+    ("GUILD_INVITES", GatewayIntents::GUILD_INVITES),
+    ("GUILD_VOICE_STATES", GatewayIntents::GUILD_VOICE_STATES),
+    ("GUILD_PRESENCES", GatewayIntents::GUILD_PRESENCES),
+    ("GUILD_MESSAGES", GatewayIntents::GUILD_MESSAGES),
+    (
+        "GUILD_MESSAGE_REACTIONS",
+        GatewayIntents::GUILD_MESSAGE_REACTIONS,
+    ),
+    ("GUILD_MESSAGE_TYPING", GatewayIntents::GUILD_MESSAGE_TYPING),
+    ("DIRECT_MESSAGES", GatewayIntents::DIRECT_MESSAGES),
+    (
+        "DIRECT_MESSAGE_REACTIONS",
+        GatewayIntents::DIRECT_MESSAGE_REACTIONS,
+    ),
+    (
+        "DIRECT_MESSAGE_TYPING",
+        GatewayIntents::DIRECT_MESSAGE_TYPING,
+    ),
+    ("MESSAGE_CONTENT", GatewayIntents::MESSAGE_CONTENT),
+    (
+        "GUILD_SCHEDULED_EVENTS",
+        GatewayIntents::GUILD_SCHEDULED_EVENTS,
+    ),
+    (
+        "AUTO_MODERATION_CONFIGURATION",
+        GatewayIntents::AUTO_MODERATION_CONFIGURATION,
+    ),
+    (
+        "AUTO_MODERATION_EXECUTION",
+        GatewayIntents::AUTO_MODERATION_EXECUTION,
+    ),
+];
+
+#[derive(Parser)]
+#[command(version, about = "A Discord bot to play interactive stories")]
+struct Args {
+    /// Path of the TOML config file.
+    #[arg(long, default_value = CONFIG_FILE)]
+    config: String,
+    /// Where the stories and the database are saved, overriding `SAVE_FOLDER`.
+    #[arg(long)]
+    save_folder: Option<String>,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = Config::new(CONFIG_FILE);
-    let save_folder = config
-        .get_string("SAVE_FOLDER")
-        .expect("missing save folder");
-    let database = Storage::new(save_folder)?;
+    let args = Args::parse();
+    let config = Config::new(&args.config);
+    let save_folder = args
+        .save_folder
+        .or_else(|| config.get_string("SAVE_FOLDER"))
+        .unwrap_or_else(|| SAVE_FOLDER.to_string());
+    let cache_ttl = config.get_parsed_path(&["cache", "ttl"]).unwrap_or(300);
+    let mut database = Storage::new(save_folder, Duration::from_secs(cache_ttl))?;
+    if let Some(seed_folder) = config.get_string("SEED_STORIES_DIR") {
+        seed_stories(&mut database, seed_folder)?;
+    }
+    let autosave_interval = config
+        .get_parsed_path(&["sessions", "autosave_interval"])
+        .filter(|seconds| *seconds > 0)
+        .map(Duration::from_secs);
+    if autosave_interval.is_some() {
+        database.enable_autosave();
+    }
+    let storage = Arc::new(Mutex::new(database));
+    if let Some(autosave_interval) = autosave_interval {
+        autosave(storage.clone(), autosave_interval);
+    }
 
     let framework = StandardFramework::new();
 
@@ -31,18 +125,218 @@ async fn main() -> Result<()> {
     let token = config
         .get_string("DISCORD_TOKEN")
         .expect("missing discord token");
-    let intents = GatewayIntents::non_privileged();
+    let rng: Arc<dyn Rng> = match config.get_parsed_path(&["random", "seed"]) {
+        Some(seed) => Arc::new(SeededRng::new(seed)),
+        None => Arc::new(ThreadRng),
+    };
+    // Read once, reloading the config doesn't change it.
+    let max_downloads = config
+        .get_parsed_path(&["download", "max_concurrent"])
+        .unwrap_or(DEFAULT_MAX_DOWNLOADS)
+        .max(1);
+    let health_port = config.get_parsed_path(&["health", "port"]);
+    let intents = gateway_intents(&config.get_list("GATEWAY_INTENTS"))?;
+    if let Some(url) = config.get_string("EMBED_AUTHOR_ICON_URL") {
+        if !is_valid_icon_url(&url) {
+            println!("Ignoring EMBED_AUTHOR_ICON_URL {url}, it must be an http or https URL");
+        }
+    }
+    let config = Arc::new(RwLock::new(config));
+    purge_trash(storage.clone(), config.clone());
+    #[cfg(unix)]
+    reload_config_on_sighup(config.clone())?;
+
+    let ready = Arc::new(AtomicBool::new(false));
+    if let Some(port) = health_port {
+        serve_health(port, ready.clone(), storage.clone()).await?;
+    }
+
     let mut client = Client::builder(token, intents)
         .event_handler(Handler {
-            storage: Mutex::new(database),
+            storage: storage.clone(),
+            config,
+            rng,
+            clock: Arc::new(TokioClock),
+            ready,
+            downloads: Arc::new(Semaphore::new(max_downloads)),
         })
         .framework(framework)
         .await?;
+
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        match shutdown_signal().await {
+            Ok(()) => shard_manager.lock().await.shutdown_all().await,
+            Err(error) => println!("Couldn't listen for the shutdown signals: {error}"),
+        }
+    });
+
     // start listening for events by starting a single shard
-    if let Err(why) = client.start().await {
+    let result = client.start().await;
+
+    // No progress kept in memory is lost on shutdown.
+    let flushed = storage.lock().await.flush_game_states()?;
+    if flushed > 0 {
+        println!("Saved {flushed} game states before shutting down");
+    }
+
+    if let Err(why) = result {
         println!("An error occurred while running the client: {why}");
         Err(why)?
     } else {
         Ok(())
     }
 }
+
+/// The intents named in the list, ignoring the case, or the non-privileged ones for an empty list.
+fn gateway_intents(names: &[String]) -> Result<GatewayIntents> {
+    if names.is_empty() {
+        return Ok(GatewayIntents::non_privileged());
+    }
+    names
+        .iter()
+        .try_fold(GatewayIntents::empty(), |intents, name| {
+            INTENT_NAMES
+                .iter()
+                .find(|(intent_name, _)| intent_name.eq_ignore_ascii_case(name))
+                .map(|(_, intent)| intents | *intent)
+                .ok_or_else(|| anyhow!("Unknown gateway intent `{name}` in GATEWAY_INTENTS"))
+        })
+}
+
+/// Write the game states kept in memory every `interval`.
+fn autosave(storage: Arc<Mutex<Storage<String>>>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            if let Err(error) = storage.lock().await.flush_game_states() {
+                println!("Couldn't save game states: {error}");
+            }
+        }
+    });
+}
+
+/// Delete for good, every [`TRASH_PURGE_INTERVAL`], the stories deleted more than
+/// `[trash] retention_days` ago.
+fn purge_trash(storage: Arc<Mutex<Storage<String>>>, config: Arc<RwLock<Config>>) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(TRASH_PURGE_INTERVAL);
+        loop {
+            ticks.tick().await;
+            let retention_days = config
+                .read()
+                .expect("config lock poisoned")
+                .get_parsed_path(&["trash", "retention_days"])
+                .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS);
+            let deleted_before = now() - retention_days * 24 * 60 * 60;
+            match storage.lock().await.purge_trash(deleted_before) {
+                Ok(0) => {}
+                Ok(purged) => println!("Deleted {purged} stories from the trash for good"),
+                Err(error) => println!("Couldn't purge the trash: {error}"),
+            }
+        }
+    });
+}
+
+/// Re-read the config file every time the process receives a SIGHUP.
+#[cfg(unix)]
+fn reload_config_on_sighup(config: Arc<RwLock<Config>>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            config.write().expect("config lock poisoned").reload();
+            println!("Config reloaded");
+        }
+    });
+    Ok(())
+}
+
+/// Wait for Ctrl-C, or the SIGTERM sent by service managers and containers to stop the bot.
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminations = signal(SignalKind::terminate())?;
+    tokio::select! {
+        interrupted = tokio::signal::ctrl_c() => interrupted?,
+        _ = terminations.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> Result<()> {
+    Ok(tokio::signal::ctrl_c().await?)
+}
+
+/// Save every `.twee` file of the folder as a global story.
+fn seed_stories<P: AsRef<Path>>(storage: &mut Storage<String>, seed_folder: P) -> Result<()> {
+    for entry in fs::read_dir(seed_folder)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("twee") {
+            continue;
+        }
+
+        let seeded = fs::read_to_string(&path)
+            .map_err(Into::into)
+            .and_then(|content| storage.seed_global_story(&content));
+        match seeded {
+            Ok(true) => println!("Seeded story {}", path.display()),
+            Ok(false) => {}
+            Err(why) => println!("Couldn't seed story {}: {why}", path.display()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn no_intent_is_the_non_privileged_ones() {
+        assert_eq!(
+            gateway_intents(&[]).unwrap(),
+            GatewayIntents::non_privileged()
+        );
+        assert_eq!(
+            gateway_intents(&names(&["NON_PRIVILEGED"])).unwrap(),
+            GatewayIntents::non_privileged()
+        );
+    }
+
+    #[test]
+    fn intents_are_combined_ignoring_the_case() {
+        assert_eq!(
+            gateway_intents(&names(&["guilds", "Message_Content"])).unwrap(),
+            GatewayIntents::GUILDS | GatewayIntents::MESSAGE_CONTENT
+        );
+        assert_eq!(
+            gateway_intents(&names(&["NON_PRIVILEGED", "GUILD_MEMBERS"])).unwrap(),
+            GatewayIntents::non_privileged() | GatewayIntents::GUILD_MEMBERS
+        );
+    }
+
+    #[test]
+    fn every_name_maps_to_its_flag() {
+        for (name, intent) in INTENT_NAMES {
+            assert_eq!(gateway_intents(&names(&[name])).unwrap(), *intent);
+        }
+    }
+
+    #[test]
+    fn unknown_intents_are_an_error() {
+        let error = gateway_intents(&names(&["GUILDS", "EVERYTHING"])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown gateway intent `EVERYTHING` in GATEWAY_INTENTS"
+        );
+    }
+}
@@ -1,7 +1,11 @@
 use std::{
+    collections::HashMap,
+    fmt::Display,
     fs,
+    io::ErrorKind,
     path::{Path, PathBuf},
-    time::Duration,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Result};
@@ -9,7 +13,12 @@ use rusqlite::Connection;
 use twee_v3::Story;
 use uuid::Uuid;
 
-use crate::{collections::ExpiringHashMap, play::GameState};
+use crate::{
+    collections::ExpiringHashMap,
+    error::UserError,
+    play::{ChoiceLayout, GameState},
+    utils::{story_author, story_ifid, story_title},
+};
 
 const CREATE_STORIES: &str = "
 create table if not exists stories(
@@ -32,44 +41,433 @@ CREATE TABLE IF NOT EXISTS story_state(
         ON DELETE CASCADE
 );";
 
+const CREATE_ACKNOWLEDGED_WARNINGS: &str = "
+CREATE TABLE IF NOT EXISTS acknowledged_warnings(
+    `player_id` TEXT NOT NULL,
+    `guild_id` TEXT NOT NULL,
+    `passage` TEXT NOT NULL,
+    PRIMARY KEY(`player_id`, `guild_id`, `passage`)
+);";
+
+const CREATE_GUILD_SETTINGS: &str = "
+CREATE TABLE IF NOT EXISTS guild_settings(
+    `guild_id` TEXT NOT NULL,
+    `key` TEXT NOT NULL,
+    `value` TEXT NOT NULL,
+    PRIMARY KEY(`guild_id`, `key`)
+);";
+
+const CREATE_STORY_SETTINGS: &str = "
+CREATE TABLE IF NOT EXISTS story_settings(
+    `story_id` INT NOT NULL,
+    `key` TEXT NOT NULL,
+    `value` TEXT NOT NULL,
+    PRIMARY KEY(`story_id`, `key`)
+);";
+
+const START_PASSAGE_SETTING: &str = "start";
+/// Whether players can jump to any passage of the story with `/jumpto`.
+const FREE_NAVIGATION_SETTING: &str = "free_navigation";
+/// How many days deleted stories stay in the trash, unless `[trash] retention_days` is set.
+pub const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+
+/// The passages of the path `/demo` plays, as a JSON array.
+const CANONICAL_PATH_SETTING: &str = "canonical_path";
+
+const CREATE_BUG_REPORTS: &str = "
+CREATE TABLE IF NOT EXISTS bug_reports(
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+    `guild_id` TEXT NOT NULL,
+    `story_id` INT NOT NULL,
+    `player_id` TEXT NOT NULL,
+    `passage` TEXT NOT NULL,
+    `broken_link` TEXT,
+    `description` TEXT,
+    `created_at` INTEGER NOT NULL
+);";
+
+const CREATE_EVENTS: &str = "
+CREATE TABLE IF NOT EXISTS events(
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+    `guild_id` TEXT NOT NULL,
+    `story_id` INT NOT NULL,
+    `player_id` TEXT NOT NULL,
+    `kind` TEXT NOT NULL,
+    `steps` INTEGER NOT NULL,
+    `created_at` INTEGER NOT NULL
+);";
+
+/// The stars players gave to the stories they finished, once per story.
+const CREATE_RATINGS: &str = "
+CREATE TABLE IF NOT EXISTS ratings(
+    `player_id` TEXT NOT NULL,
+    `story_id` INT NOT NULL,
+    `stars` INTEGER NOT NULL,
+    `rated_at` INTEGER NOT NULL,
+    PRIMARY KEY(`player_id`, `story_id`)
+);";
+
+/// Stories of a guild grouped to be played in order, see [`Storage::series_stories`].
+/// Names are compared ignoring the case.
+const CREATE_SERIES: &str = "
+CREATE TABLE IF NOT EXISTS series(
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+    `guild_id` TEXT NOT NULL,
+    `name` TEXT NOT NULL COLLATE NOCASE,
+    UNIQUE(`guild_id`, `name`)
+);";
+
+/// Positions can leave gaps, only their order matters.
+const CREATE_SERIES_MEMBERSHIP: &str = "
+CREATE TABLE IF NOT EXISTS series_membership(
+    `series_id` INT NOT NULL,
+    `story_id` INT NOT NULL,
+    `position` INTEGER NOT NULL,
+    PRIMARY KEY(`series_id`, `story_id`)
+);";
+
+/// The sessions players saved with `/save`, a few per story. Names are compared ignoring the
+/// case.
+const CREATE_SAVE_SLOTS: &str = "
+CREATE TABLE IF NOT EXISTS save_slots(
+    `player_id` TEXT NOT NULL,
+    `guild_id` TEXT NOT NULL,
+    `story_id` INT NOT NULL,
+    `name` TEXT NOT NULL COLLATE NOCASE,
+    `passage` TEXT NOT NULL,
+    `state_blob` TEXT NOT NULL,
+    `saved_at` INTEGER NOT NULL,
+    PRIMARY KEY(`player_id`, `guild_id`, `story_id`, `name`)
+);";
+
+const PLAY_EVENT: &str = "play";
+const COMPLETION_EVENT: &str = "completion";
+const VISIT_EVENT: &str = "visit";
+/// The player stopped the session with `/stop`.
+const STOP_EVENT: &str = "stop";
+/// An administrator cleared the session with `/clearsession`.
+const CLEAR_EVENT: &str = "clear";
+
+/// How long a player must not have played a story they didn't finish to be counted in the
+/// drop-offs, as they may just be taking a break.
+const IDLE_SESSION_TIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Something a player did, recorded for analytics.
+struct Event {
+    guild_id: String,
+    story_id: i64,
+    player_id: String,
+    kind: &'static str,
+    /// The passage the player was on.
+    passage: Option<String>,
+    steps: usize,
+    created_at: i64,
+}
+
+impl Event {
+    fn new(game_state: &GameState, kind: &'static str) -> Self {
+        Self {
+            guild_id: game_state.guild_id.clone(),
+            story_id: game_state.story_id,
+            player_id: game_state.player_id.clone(),
+            kind,
+            passage: Some(game_state.current_chapter.clone()),
+            steps: game_state.history.len(),
+            created_at: now(),
+        }
+    }
+}
+
+/// How many bug reports are listed at most, the most recent first.
+const MAX_BUG_REPORTS: usize = 20;
+
+/// The language of the bot on guilds that didn't choose one.
+pub const DEFAULT_LANGUAGE: &str = "en";
+const LANGUAGE_SETTING: &str = "language";
+const LAYOUT_SETTING: &str = "layout";
+const SINGLE_MESSAGE_SETTING: &str = "single_message";
+const DELAY_SETTING: &str = "delay";
+/// Followed by the name of a command disabled on the guild, like `disabled:upload`.
+const DISABLED_COMMAND_SETTING_PREFIX: &str = "disabled:";
+
+/// How long the progress of a player past their last checkpoint is kept.
+const UNSAVED_STATE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The guild id of the stories seeded at startup, playable on every guild.
+pub const GLOBAL_GUILD_ID: &str = "global";
+
+pub struct StoryDetails {
+    pub id: i64,
+    pub name: String,
+    pub author: Option<String>,
+    pub rating: Option<Rating>,
+    /// Unix timestamps, unknown for stories saved before they were recorded.
+    pub created_at: Option<i64>,
+    pub modified_at: Option<i64>,
+}
+
+pub struct SearchResult {
+    pub name: String,
+    pub title_match: bool,
+    /// How many times the query appears in the story, when searching the content.
+    pub content_matches: usize,
+}
+
+/// Searching the content of stories means reading their files, so only that many are searched.
+const MAX_CONTENT_SEARCH: usize = 50;
+
+/// A session being played on a guild.
+pub struct SessionDetails {
+    pub player_id: String,
+    pub story_name: String,
+    pub passage: String,
+}
+
+/// A problem reported by a player with `/reportbug`, for the authors of the story.
+pub struct BugReport {
+    pub player_id: String,
+    /// The passage the player was on.
+    pub passage: String,
+    /// The target of the link the player chose, if it doesn't exist.
+    pub broken_link: Option<String>,
+    pub description: Option<String>,
+    pub created_at: i64,
+}
+
+/// The average of the ratings of a story, see [`Storage::average_rating`].
+pub struct Rating {
+    pub average: f64,
+    pub count: i64,
+}
+
+impl Display for Rating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.count == 1 { "" } else { "s" };
+        write!(f, "★ {:.1} ({} rating{plural})", self.average, self.count)
+    }
+}
+
+/// The id and the file of the story of the guild a new version replaces.
+type PreviousStory = (i64, String);
+
+/// A story in the trash of a guild, see [`Storage::deleted_stories`].
+pub struct DeletedStory {
+    pub id: i64,
+    pub name: String,
+    /// A Unix timestamp.
+    pub deleted_at: i64,
+}
+
+/// A session saved by a player, see [`Storage::save_slots`].
+pub struct SaveSlot {
+    pub story_name: String,
+    pub name: String,
+    pub passage: String,
+    /// A Unix timestamp.
+    pub saved_at: i64,
+}
+
+/// A series of stories of a guild, see [`Storage::list_series`].
+pub struct Series {
+    pub name: String,
+    pub story_count: i64,
+}
+
+/// How far a player went in a story, see [`Storage::story_progress`].
+#[derive(Clone, Copy, Default)]
+pub struct StoryProgress {
+    pub played: bool,
+    pub completed: bool,
+}
+
+/// A story a player played, see [`Storage::recent_stories`].
+pub struct RecentStory {
+    pub story_id: i64,
+    pub name: String,
+    /// When the player last started it, as a unix timestamp.
+    pub played_at: i64,
+}
+
+/// How many players left a story at a passage, see [`Storage::dropoff_report`].
+pub struct Dropoff {
+    pub passage: String,
+    /// Stopped with `/stop` or `/clearsession`.
+    pub stopped: i64,
+    /// Not played for a while.
+    pub idle: i64,
+}
+
 pub enum SaveStory {
     New,
     Update,
 }
 
+/// A story read from its file, with its raw content for what twee_v3 doesn't expose.
+struct CachedStory {
+    story: Story<String>,
+    content: String,
+}
+
 pub struct Storage<P: AsRef<Path>> {
     storage_folder: P,
     connection: Connection,
-    stories: ExpiringHashMap<String, Story<String>>,
+    stories: ExpiringHashMap<i64, CachedStory>,
+    /// Game states past the last checkpoint, by player and guild. They are never written, and
+    /// sessions resume from their last checkpoint once they expire.
+    unsaved_states: ExpiringHashMap<(String, String), GameState>,
+    /// With autosave, the latest game states not written yet, by player and guild.
+    pending_states: Option<HashMap<(String, String), GameState>>,
+    /// With autosave, the passage visits not written yet, as they happen on every choice.
+    pending_visits: Vec<Event>,
 }
 
 impl<P> Storage<P>
 where
     P: AsRef<Path>,
 {
-    pub fn new(storage_folder: P) -> Result<Self> {
+    /// Open the storage, keeping the stories read from their files in cache for `cache_ttl`
+    /// after their last access.
+    pub fn new(storage_folder: P, cache_ttl: Duration) -> Result<Self> {
         if !storage_folder.as_ref().exists() {
             fs::create_dir_all(&storage_folder)?;
         }
         let database_path = storage_folder.as_ref().join("data.sqlite");
         let connection = Connection::open(database_path)?;
-        let stories = ExpiringHashMap::new(Duration::from_secs(300));
+        let stories = ExpiringHashMap::new(cache_ttl);
 
         create_tables(&connection)?;
 
-        Ok(Self {
+        let mut storage = Self {
             connection,
             storage_folder,
             stories,
-        })
+            unsaved_states: ExpiringHashMap::new(UNSAVED_STATE_TTL),
+            pending_states: None,
+            pending_visits: vec![],
+        };
+        storage.backfill_metadata()?;
+        if let Err(error) = storage.check_writable() {
+            println!(
+                "Warning: the stories can't be saved in {}, uploads will fail: {error}",
+                storage.storage_folder.as_ref().display()
+            );
+        }
+        Ok(storage)
     }
 
-    pub fn save_story(&mut self, guild_id: &str, story_content: &str) -> Result<SaveStory> {
+    /// Write and delete a file in the stories folder, to catch a read-only volume at startup
+    /// rather than on the first upload.
+    fn check_writable(&self) -> Result<()> {
+        let probe = self.stories_folder()?.join(".writable");
+        fs::write(&probe, []).map_err(not_writable)?;
+        fs::remove_file(probe).map_err(not_writable)?;
+        Ok(())
+    }
+
+    /// Keep game states in memory instead of writing every change, to be written by
+    /// [`Storage::flush_game_states`]. They never expire: they are only forgotten once written.
+    pub fn enable_autosave(&mut self) {
+        self.pending_states = Some(HashMap::new());
+    }
+
+    /// Write the game states kept in memory by autosave, returning how many were written.
+    pub fn flush_game_states(&mut self) -> Result<usize> {
+        let Some(pending_states) = self.pending_states.as_mut() else {
+            return Ok(0);
+        };
+        let mut game_states = std::mem::take(pending_states).into_iter();
+        let count = game_states.len();
+        while let Some((key, game_state)) = game_states.next() {
+            if let Err(error) = self.write_game_state(&game_state) {
+                // What isn't written is kept for the next flush.
+                if let Some(pending_states) = self.pending_states.as_mut() {
+                    pending_states.insert(key, game_state);
+                    pending_states.extend(game_states);
+                }
+                return Err(error);
+            }
+        }
+        self.flush_visits()?;
+        Ok(count)
+    }
+
+    /// Write the passage visits kept in memory by autosave, in a single transaction.
+    fn flush_visits(&mut self) -> Result<()> {
+        if self.pending_visits.is_empty() {
+            return Ok(());
+        }
+        let transaction = self.connection.transaction()?;
+        for event in &self.pending_visits {
+            insert_event(&transaction, event)?;
+        }
+        transaction.commit()?;
+        // Only forgotten once written, a failed transaction is retried on the next flush.
+        self.pending_visits.clear();
+        Ok(())
+    }
+
+    /// Stories saved before the `ifid` and `author` columns existed need them to be shared and
+    /// credited.
+    fn backfill_metadata(&mut self) -> Result<()> {
+        let story_ids = self
+            .connection
+            .prepare("SELECT id FROM stories WHERE ifid IS NULL OR author IS NULL")?
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for story_id in story_ids {
+            let Ok(content) = self.get_story_content(story_id) else {
+                continue;
+            };
+            let (ifid, author) = (story_ifid(&content), story_author(&content));
+            if ifid.is_some() || author.is_some() {
+                self.connection.execute(
+                    "UPDATE stories SET ifid = COALESCE(ifid, ?1), author = COALESCE(author, ?2)
+                    WHERE id = ?3",
+                    (ifid, author, story_id),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse the story, and find the story of the guild it replaces, as saving it does.
+    fn prepare_story(
+        &self,
+        guild_id: &str,
+        story_content: &str,
+    ) -> Result<(Story<String>, Option<PreviousStory>)> {
         let story = Story::try_from(story_content)
             .map_err(|_| anyhow!("Invalid story"))?
             .into_owned();
 
         let name = story.title().ok_or(anyhow!("Story without title"))?;
+        let previous = self.previous_story(guild_id, name)?;
+        Ok((story, previous))
+    }
+
+    /// What [`Storage::save_story`] would do with the story, without writing anything.
+    pub fn check_story(&self, guild_id: &str, story_content: &str) -> Result<SaveStory> {
+        match self.prepare_story(guild_id, story_content)? {
+            (_, Some(_)) => Ok(SaveStory::Update),
+            (_, None) => Ok(SaveStory::New),
+        }
+    }
+
+    pub fn save_story(&mut self, guild_id: &str, story_content: &str) -> Result<SaveStory> {
+        let (story, previous) = self.prepare_story(guild_id, story_content)?;
+        self.write_story(guild_id, story, story_content, previous)
+    }
+
+    /// Write the story to a new file, updating the previous story in place if there is one.
+    fn write_story(
+        &mut self,
+        guild_id: &str,
+        story: Story<String>,
+        story_content: &str,
+        previous: Option<PreviousStory>,
+    ) -> Result<SaveStory> {
+        let name = story.title().ok_or(anyhow!("Story without title"))?;
 
         let (filename, file_path) = loop {
             let filename = format!("{}.twee", Uuid::new_v4());
@@ -79,43 +477,229 @@ where
             }
         };
 
-        let did_overwrite = self.cleanup_previous(guild_id, name)?;
-
-        fs::write(&file_path, story_content)?;
-        if let Err(e) = self.connection.execute(
-            "INSERT INTO stories (guild_id, name, filename) VALUES (?1, ?2, ?3)",
-            (guild_id, name, filename.as_str()),
-        ) {
+        fs::write(&file_path, story_content).map_err(not_writable)?;
+        let saved = match &previous {
+            // Updating in place keeps the sessions of the players, that are reconciled on their next move.
+            Some((story_id, _)) => self.connection.execute(
+                "UPDATE stories SET name = ?1, filename = ?2, ifid = ?3, modified_at = ?4, author = ?5
+                WHERE id = ?6",
+                (
+                    name,
+                    filename.as_str(),
+                    story_ifid(story_content),
+                    now(),
+                    story_author(story_content),
+                    story_id,
+                ),
+            ),
+            None => self.connection.execute(
+                "INSERT INTO stories (guild_id, name, filename, ifid, created_at, modified_at, author)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6)",
+                (
+                    guild_id,
+                    name,
+                    filename.as_str(),
+                    story_ifid(story_content),
+                    now(),
+                    story_author(story_content),
+                ),
+            ),
+        };
+        if let Err(e) = saved {
             println!("Couldn't save story to database, deleting file");
             fs::remove_file(file_path)?;
 
             return Err(e.into());
         }
-        self.stories.insert(name.to_string(), story);
+        let story_id = match &previous {
+            Some((story_id, _)) => *story_id,
+            None => self.connection.last_insert_rowid(),
+        };
+        let content = story_content.to_string();
+        self.stories
+            .insert(story_id, CachedStory { story, content });
+
+        match previous {
+            Some((_, previous_filename)) => {
+                // Deleting the previous file, we don't care that much if it fails.
+                let _ = fs::remove_file(self.stories_folder()?.join(previous_filename));
+                Ok(SaveStory::Update)
+            }
+            None => Ok(SaveStory::New),
+        }
+    }
+
+    /// Copy the story to another guild, as if it was uploaded there. A story with the same name on
+    /// the target guild is only replaced if `replace` is set.
+    pub fn copy_story_to_guild(
+        &mut self,
+        story_id: i64,
+        target_guild_id: &str,
+        replace: bool,
+    ) -> Result<SaveStory> {
+        let content = self.get_story_content(story_id)?;
+        let name = story_title(&content).ok_or(anyhow!("Story without title"))?;
+        if !replace && self.previous_story(target_guild_id, &name)?.is_some() {
+            return Err(UserError::StoryNameTaken(name).into());
+        }
+        self.save_story(target_guild_id, &content)
+    }
+
+    /// Save a story available to every guild, unless the same version is already seeded.
+    /// Seeds are matched by IFID, falling back to their name, and a changed seed is updated in
+    /// place, keeping the sessions and statistics of its players. Returns whether the story was
+    /// saved.
+    pub fn seed_global_story(&mut self, story_content: &str) -> Result<bool> {
+        let (story, same_name) = self.prepare_story(GLOBAL_GUILD_ID, story_content)?;
+        let previous = match story_ifid(story_content) {
+            // The title of the seed may have changed.
+            Some(ifid) => self.seed_with_ifid(&ifid)?.or(same_name),
+            None => same_name,
+        };
+        if let Some((_, filename)) = &previous {
+            let path = self.stories_folder()?.join(filename);
+            if fs::read_to_string(path).ok().as_deref() == Some(story_content) {
+                return Ok(false);
+            }
+        }
 
-        Ok(match did_overwrite {
-            true => SaveStory::Update,
-            false => SaveStory::New,
-        })
+        self.write_story(GLOBAL_GUILD_ID, story, story_content, previous)?;
+        Ok(true)
     }
 
-    fn cleanup_previous(&mut self, guild_id: &str, name: &str) -> Result<bool> {
-        const QUERY: &str = "SELECT id FROM stories WHERE guild_id = ?1 AND name = ?2";
+    /// The id and filename of the seeded story with the IFID, if any.
+    fn seed_with_ifid(&self, ifid: &str) -> Result<Option<PreviousStory>> {
+        const QUERY: &str = "SELECT id, filename FROM stories
+            WHERE guild_id = ?1 AND ifid = ?2 AND deleted_at IS NULL";
         match self
             .connection
-            .query_row(QUERY, [guild_id, name], |row| row.get::<_, i64>(0))
-        {
-            Ok(story_id) => {
-                self.delete_story(story_id)?;
-                Ok(true)
-            }
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+            .query_row(QUERY, [GLOBAL_GUILD_ID, ifid], |row| {
+                let story_id: i64 = row.get(0)?;
+                let filename: String = row.get(1)?;
+                Ok((story_id, filename))
+            }) {
+            Ok(previous) => Ok(Some(previous)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The id and filename of the story of the guild with the same name, if any.
+    fn previous_story(&self, guild_id: &str, name: &str) -> Result<Option<PreviousStory>> {
+        const QUERY: &str = "SELECT id, filename FROM stories
+            WHERE guild_id = ?1 AND name = ?2 AND deleted_at IS NULL";
+        match self.connection.query_row(QUERY, [guild_id, name], |row| {
+            let story_id: i64 = row.get(0)?;
+            let filename: String = row.get(1)?;
+            Ok((story_id, filename))
+        }) {
+            Ok(previous) => Ok(Some(previous)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(e) => Err(e.into()),
         }
     }
 
+    /// When the story was last saved, as a unix timestamp, to detect updates during a session.
+    pub fn story_modified_at(&self, story_id: i64) -> Result<Option<i64>> {
+        const QUERY: &str = "SELECT modified_at FROM stories WHERE id = ?";
+        Ok(self
+            .connection
+            .query_row(QUERY, [story_id], |row| row.get(0))?)
+    }
+
+    /// The author credited by the story, if any.
+    pub fn story_author(&self, story_id: i64) -> Result<Option<String>> {
+        const QUERY: &str = "SELECT author FROM stories WHERE id = ?";
+        Ok(self
+            .connection
+            .query_row(QUERY, [story_id], |row| row.get(0))?)
+    }
+
+    /// Move the story to the trash of its guild: it can't be played or found anymore, but
+    /// [`Storage::restore_story`] brings it back, with its sessions and statistics, until
+    /// [`Storage::purge_trash`] deletes it for good.
+    pub fn soft_delete_story(&mut self, story_id: i64) -> Result<String> {
+        // Like when deleting, the sessions of the story kept in memory are written or dropped.
+        self.flush_game_states()?;
+        for (key, game_state) in self.unsaved_states.drain() {
+            if game_state.story_id != story_id {
+                self.unsaved_states.insert(key, game_state);
+            }
+        }
+        let name: String = self.connection.query_row(
+            "SELECT name FROM stories WHERE id = ?1 AND deleted_at IS NULL",
+            [story_id],
+            |row| row.get(0),
+        )?;
+        self.stories.remove(&story_id);
+        self.connection.execute(
+            "UPDATE stories SET deleted_at = ?1 WHERE id = ?2",
+            (now(), story_id),
+        )?;
+        Ok(name)
+    }
+
+    /// The stories in the trash of the guild, the last deleted first.
+    pub fn deleted_stories(&self, guild_id: &str) -> Result<Vec<DeletedStory>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, name, deleted_at FROM stories
+            WHERE guild_id = ?1 AND deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC",
+        )?;
+        let stories = statement
+            .query_map([guild_id], |row| {
+                Ok(DeletedStory {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    deleted_at: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(stories)
+    }
+
+    /// Take the story out of the trash, unless a story of the same name was uploaded since.
+    pub fn restore_story(&mut self, guild_id: &str, story_id: i64) -> Result<String> {
+        let name: String = self.connection.query_row(
+            "SELECT name FROM stories WHERE id = ?1 AND guild_id = ?2 AND deleted_at IS NOT NULL",
+            (story_id, guild_id),
+            |row| row.get(0),
+        )?;
+        if self.previous_story(guild_id, &name)?.is_some() {
+            return Err(UserError::RestoredNameTaken(name).into());
+        }
+        self.connection.execute(
+            "UPDATE stories SET deleted_at = NULL WHERE id = ?1",
+            [story_id],
+        )?;
+        Ok(name)
+    }
+
+    /// Delete for good the stories moved to the trash before `deleted_before`, a Unix timestamp.
+    /// Returns how many were deleted.
+    pub fn purge_trash(&mut self, deleted_before: i64) -> Result<usize> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id FROM stories WHERE deleted_at < ?1")?;
+        let story_ids = statement
+            .query_map([deleted_before], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        drop(statement);
+
+        for story_id in &story_ids {
+            self.delete_story(*story_id)?;
+        }
+        Ok(story_ids.len())
+    }
+
     /// Delete story with the id, and returns the name of the deleted story.
     pub fn delete_story(&mut self, story_id: i64) -> Result<String> {
+        // Pending sessions of the story must be written to be deleted along with it.
+        self.flush_game_states()?;
+        for (key, game_state) in self.unsaved_states.drain() {
+            if game_state.story_id != story_id {
+                self.unsaved_states.insert(key, game_state);
+            }
+        }
         let (name, filename) = self.connection.query_row(
             "SELECT name, filename FROM stories WHERE `id`=?",
             [story_id],
@@ -125,11 +709,32 @@ where
                 Ok((name, filename))
             },
         )?;
-        self.stories.remove(&name);
+        self.stories.remove(&story_id);
 
         let count = self
             .connection
             .execute("DELETE FROM stories WHERE `id` = ?1", [story_id])?;
+        // Foreign keys aren't enabled, so nothing cascades: every row of the story goes here.
+        self.connection
+            .execute("DELETE FROM story_state WHERE `story_id` = ?1", [story_id])?;
+        self.connection.execute(
+            "DELETE FROM story_settings WHERE `story_id` = ?1",
+            [story_id],
+        )?;
+        self.connection
+            .execute("DELETE FROM bug_reports WHERE `story_id` = ?1", [story_id])?;
+        self.pending_visits
+            .retain(|event| event.story_id != story_id);
+        self.connection
+            .execute("DELETE FROM events WHERE `story_id` = ?1", [story_id])?;
+        self.connection
+            .execute("DELETE FROM ratings WHERE `story_id` = ?1", [story_id])?;
+        self.connection.execute(
+            "DELETE FROM series_membership WHERE `story_id` = ?1",
+            [story_id],
+        )?;
+        self.connection
+            .execute("DELETE FROM save_slots WHERE `story_id` = ?1", [story_id])?;
 
         if count > 0 {
             // Deleting the story file, we don't care that much if it fails.
@@ -144,13 +749,18 @@ where
         }
     }
 
-    pub fn list_guild_stories(&self, guild_id: &str) -> Result<Vec<(i64, String)>> {
-        let mut statement = self
-            .connection
-            .prepare("SELECT id, name FROM stories WHERE guild_id = ?1")
-            .unwrap();
+    /// List the stories of the guild, with the global seeded stories if `include_global`.
+    pub fn list_guild_stories(
+        &self,
+        guild_id: &str,
+        include_global: bool,
+    ) -> Result<Vec<(i64, String)>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, name FROM stories
+            WHERE (guild_id = ?1 OR (?2 AND guild_id = ?3)) AND deleted_at IS NULL",
+        )?;
         let stories = statement
-            .query_map([guild_id], |row| {
+            .query_map((guild_id, include_global, GLOBAL_GUILD_ID), |row| {
                 let id: i64 = row.get(0)?;
                 let name: String = row.get(1)?;
                 Ok((id, name))
@@ -160,10 +770,132 @@ where
         Ok(stories)
     }
 
-    pub fn update_game_state(&self, game_state: &GameState) -> Result<()> {
+    /// Like [`Storage::list_guild_stories`], with their authors, ratings, and when they were
+    /// uploaded and updated.
+    pub fn list_guild_stories_detailed(
+        &self,
+        guild_id: &str,
+        include_global: bool,
+    ) -> Result<Vec<StoryDetails>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, name, author, created_at, modified_at,
+                (SELECT AVG(stars) FROM ratings WHERE story_id = stories.id),
+                (SELECT COUNT(*) FROM ratings WHERE story_id = stories.id)
+            FROM stories WHERE (guild_id = ?1 OR (?2 AND guild_id = ?3)) AND deleted_at IS NULL",
+        )?;
+        let stories = statement
+            .query_map((guild_id, include_global, GLOBAL_GUILD_ID), |row| {
+                let average: Option<f64> = row.get(5)?;
+                let count: i64 = row.get(6)?;
+                Ok(StoryDetails {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    author: row.get(2)?,
+                    rating: average.map(|average| Rating { average, count }),
+                    created_at: row.get(3)?,
+                    modified_at: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(stories)
+    }
+
+    /// Search the stories playable on the guild whose title contains the query, ignoring the case.
+    /// With `search_content`, also look into the passages of the stories.
+    /// Results come with title matches first, then the most content matches.
+    pub fn search_stories(
+        &self,
+        guild_id: &str,
+        query: &str,
+        search_content: bool,
+    ) -> Result<Vec<SearchResult>> {
+        let pattern = format!(
+            "%{}%",
+            query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let mut statement = self.connection.prepare(
+            "SELECT name, filename, name LIKE ?1 ESCAPE '\\' FROM stories
+            WHERE (guild_id = ?2 OR guild_id = ?3) AND deleted_at IS NULL",
+        )?;
+        let stories = statement
+            .query_map((pattern, guild_id, GLOBAL_GUILD_ID), |row| {
+                let name: String = row.get(0)?;
+                let filename: String = row.get(1)?;
+                let title_match: bool = row.get(2)?;
+                Ok((name, filename, title_match))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let query = query.to_lowercase();
+        let stories_folder = self.stories_folder()?;
+        let mut results: Vec<SearchResult> = stories
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, (name, filename, title_match))| {
+                let content_matches = if search_content && index < MAX_CONTENT_SEARCH {
+                    fs::read_to_string(stories_folder.join(filename))
+                        .map(|content| content.to_lowercase().matches(&query).count())
+                        .unwrap_or_default()
+                } else {
+                    0
+                };
+                (title_match || content_matches > 0).then_some(SearchResult {
+                    name,
+                    title_match,
+                    content_matches,
+                })
+            })
+            .collect();
+
+        results.sort_by(|a, b| {
+            b.title_match
+                .cmp(&a.title_match)
+                .then(b.content_matches.cmp(&a.content_matches))
+                .then(a.name.cmp(&b.name))
+        });
+        Ok(results)
+    }
+
+    pub fn update_game_state(&mut self, game_state: &GameState) -> Result<()> {
+        let key = (game_state.player_id.clone(), game_state.guild_id.clone());
+        self.unsaved_states.remove(&key);
+
+        match self.pending_states.as_mut() {
+            Some(pending_states) => {
+                pending_states.insert(key, game_state.clone());
+                Ok(())
+            }
+            None => self.write_game_state(game_state),
+        }
+    }
+
+    /// Save the game state of a new session, replacing the session the player had in the guild,
+    /// if any. The warnings acknowledged in the previous session are shown again.
+    pub fn start_session(&mut self, game_state: &GameState) -> Result<()> {
+        const CLEAR_WARNINGS: &str =
+            "DELETE FROM acknowledged_warnings WHERE player_id = ?1 AND guild_id = ?2";
+
+        self.connection.execute(
+            CLEAR_WARNINGS,
+            [&game_state.player_id, &game_state.guild_id],
+        )?;
+        self.update_game_state(game_state)
+    }
+
+    /// Keep the game state in memory only, until the player reaches a checkpoint: the last saved
+    /// one is where the session resumes if the bot restarts.
+    pub fn keep_unsaved_game_state(&mut self, game_state: &GameState) {
+        let key = (game_state.player_id.clone(), game_state.guild_id.clone());
+        self.unsaved_states.insert(key, game_state.clone());
+    }
+
+    fn write_game_state(&self, game_state: &GameState) -> Result<()> {
         const QUERY: &str =
-            "INSERT OR REPLACE into story_state (player_id, guild_id, story_id, current_step) VALUES
-        (?1, ?2, ?3, ?4)";
+            "INSERT OR REPLACE into story_state (player_id, guild_id, story_id, current_step, state_blob)
+        VALUES (?1, ?2, ?3, ?4, ?5)";
         self.connection.execute(
             QUERY,
             (
@@ -171,68 +903,2160 @@ where
                 &game_state.guild_id,
                 &game_state.story_id,
                 &game_state.current_chapter,
+                game_state.to_json()?,
             ),
         )?;
         Ok(())
     }
 
-    pub fn retrieve_game_state(&self, player_id: &str, guild_id: &str) -> Result<GameState> {
-        const QUERY: &str =
-            "SELECT story_id, current_step FROM story_state WHERE player_id = ?1 AND guild_id = ?2";
+    pub fn retrieve_game_state(&mut self, player_id: &str, guild_id: &str) -> Result<GameState> {
+        let key = (player_id.to_string(), guild_id.to_string());
+        if let Some(game_state) = self.unsaved_states.get(&key) {
+            return Ok(game_state.clone());
+        }
+        if let Some(game_state) = self
+            .pending_states
+            .as_mut()
+            .and_then(|pending_states| pending_states.get(&key))
+        {
+            return Ok(game_state.clone());
+        }
 
-        let (story_id, current_step) =
+        const QUERY: &str = "SELECT story_id, current_step, state_blob FROM story_state
+        WHERE player_id = ?1 AND guild_id = ?2";
+
+        let (story_id, current_step, state_blob) =
             self.connection
                 .query_row(QUERY, [player_id, guild_id], |row| {
                     let story_id: i64 = row.get(0)?;
                     let current_step: String = row.get(1)?;
-                    Ok((story_id, current_step))
+                    let state_blob: Option<String> = row.get(2)?;
+                    Ok((story_id, current_step, state_blob))
                 })?;
 
-        Ok(GameState::new(
-            player_id.to_string(),
-            guild_id.to_string(),
-            story_id,
-            current_step,
-        ))
+        // Sessions saved before the blob existed only have the current step.
+        match state_blob {
+            Some(state_blob) => GameState::from_json(&state_blob),
+            None => Ok(GameState::new(
+                player_id.to_string(),
+                guild_id.to_string(),
+                story_id,
+                current_step,
+            )),
+        }
     }
 
-    pub fn clear_game_state(&self, player_id: &str, guild_id: &str) -> Result<()> {
-        const QUERY: &str = "DELETE FROM story_state WHERE player_id = ?1 AND guild_id = ?2";
+    /// How many stories the player is currently playing on the guild.
+    pub fn player_session_count(&mut self, player_id: &str, guild_id: &str) -> Result<usize> {
+        const QUERY: &str =
+            "SELECT COUNT(*) FROM story_state WHERE player_id = ?1 AND guild_id = ?2";
 
-        self.connection.execute(QUERY, [player_id, guild_id])?;
+        // Sessions only kept in memory count too.
+        let key = (player_id.to_string(), guild_id.to_string());
+        if let Some(game_state) = self
+            .pending_states
+            .as_mut()
+            .and_then(|pending_states| pending_states.remove(&key))
+        {
+            self.write_game_state(&game_state)?;
+        }
 
-        Ok(())
+        let count: i64 = self
+            .connection
+            .query_row(QUERY, [player_id, guild_id], |row| row.get(0))?;
+        Ok(count as usize)
     }
 
-    pub fn get_story(&mut self, story_id: i64) -> Result<Story<String>> {
-        const QUERY: &str = "SELECT name, filename FROM stories WHERE id = ?";
-        let (name, filename): (String, String) =
-            self.connection
-                .query_row(QUERY, [story_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    /// Whether the player can start a story on the guild without going over `max_sessions`.
+    /// Starting a story replaces the session the player already has on the guild, so it only
+    /// takes a new slot when there is none to replace.
+    pub fn can_start_session(
+        &mut self,
+        player_id: &str,
+        guild_id: &str,
+        max_sessions: usize,
+    ) -> Result<bool> {
+        let count = self.player_session_count(player_id, guild_id)?;
+        let replaced = count.min(1);
+        Ok(count - replaced < max_sessions)
+    }
 
-        if let Some(story) = self.stories.get(&name) {
-            Ok(story.clone())
-        } else {
-            let path = self.stories_folder()?.join(filename);
-            let content = fs::read_to_string(path)?;
-            let story = Story::try_from(content)?;
+    /// The sessions being played on the guild, by story.
+    pub fn list_guild_sessions(&mut self, guild_id: &str) -> Result<Vec<SessionDetails>> {
+        // Every session must be known to the database to be listed.
+        self.flush_game_states()?;
+
+        let mut statement = self.connection.prepare(
+            "SELECT story_state.player_id, stories.name, story_state.current_step
+            FROM story_state JOIN stories ON stories.id = story_state.story_id
+            WHERE story_state.guild_id = ?1 AND stories.deleted_at IS NULL
+            ORDER BY stories.name, story_state.player_id",
+        )?;
+        let mut sessions = statement
+            .query_map([guild_id], |row| {
+                Ok(SessionDetails {
+                    player_id: row.get(0)?,
+                    story_name: row.get(1)?,
+                    passage: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        drop(statement);
 
-            self.stories.insert(name.clone(), story.clone());
-            Ok(story)
+        // Players past their last checkpoint are further than what is saved. Listing the
+        // sessions is not playing them, so it doesn't keep the unsaved progress alive.
+        for session in &mut sessions {
+            let key = (session.player_id.clone(), guild_id.to_string());
+            if let Some(game_state) = self.unsaved_states.peek(&key) {
+                session.passage = game_state.current_chapter.clone();
+            }
         }
+        Ok(sessions)
     }
 
-    fn stories_folder(&self) -> Result<PathBuf> {
-        let folder = self.storage_folder.as_ref().join("stories");
-        if !folder.exists() {
-            fs::create_dir_all(&folder)?;
+    pub fn clear_game_state(&mut self, player_id: &str, guild_id: &str) -> Result<()> {
+        const QUERY: &str = "DELETE FROM story_state WHERE player_id = ?1 AND guild_id = ?2";
+        const CLEAR_WARNINGS: &str =
+            "DELETE FROM acknowledged_warnings WHERE player_id = ?1 AND guild_id = ?2";
+
+        let key = (player_id.to_string(), guild_id.to_string());
+        self.unsaved_states.remove(&key);
+        if let Some(pending_states) = self.pending_states.as_mut() {
+            pending_states.remove(&key);
         }
-        Ok(folder)
+
+        self.connection.execute(QUERY, [player_id, guild_id])?;
+        self.connection
+            .execute(CLEAR_WARNINGS, [player_id, guild_id])?;
+
+        Ok(())
     }
-}
 
-fn create_tables(connection: &Connection) -> Result<()> {
-    connection.execute(CREATE_STORIES, [])?;
-    connection.execute(CREATE_STORY_STATE, [])?;
-    Ok(())
+    pub fn acknowledge_warning(
+        &self,
+        player_id: &str,
+        guild_id: &str,
+        passage: &str,
+    ) -> Result<()> {
+        const QUERY: &str =
+            "INSERT OR IGNORE INTO acknowledged_warnings (player_id, guild_id, passage)
+        VALUES (?1, ?2, ?3)";
+
+        self.connection
+            .execute(QUERY, [player_id, guild_id, passage])?;
+
+        Ok(())
+    }
+
+    pub fn is_warning_acknowledged(
+        &self,
+        player_id: &str,
+        guild_id: &str,
+        passage: &str,
+    ) -> Result<bool> {
+        const QUERY: &str = "SELECT COUNT(*) FROM acknowledged_warnings
+        WHERE player_id = ?1 AND guild_id = ?2 AND passage = ?3";
+
+        let count: i64 =
+            self.connection
+                .query_row(QUERY, [player_id, guild_id, passage], |row| row.get(0))?;
+
+        Ok(count > 0)
+    }
+
+    pub fn get_story(&mut self, story_id: i64) -> Result<Story<String>> {
+        Ok(self.cached_story(story_id)?.story.clone())
+    }
+
+    /// The raw twee content of a story, for what twee_v3 doesn't expose, like passage tags.
+    pub fn get_story_content(&mut self, story_id: i64) -> Result<String> {
+        Ok(self.cached_story(story_id)?.content.clone())
+    }
+
+    fn cached_story(&mut self, story_id: i64) -> Result<&CachedStory> {
+        const QUERY: &str = "SELECT filename FROM stories WHERE id = ? AND deleted_at IS NULL";
+
+        // Stories are cached by id, as guilds can have stories with the same name.
+        if self.stories.get(&story_id).is_none() {
+            let filename: String = self
+                .connection
+                .query_row(QUERY, [story_id], |row| row.get(0))?;
+            let path = self.stories_folder()?.join(filename);
+            let content = fs::read_to_string(path)?;
+            let story = Story::try_from(content.clone())?;
+
+            self.stories
+                .insert(story_id, CachedStory { story, content });
+        }
+        Ok(self
+            .stories
+            .get(&story_id)
+            .expect("The story was just cached"))
+    }
+
+    /// Find a story of the guild by its name, ignoring the case.
+    pub fn find_guild_story(&self, guild_id: &str, name: &str) -> Result<Option<i64>> {
+        const QUERY: &str =
+            "SELECT id FROM stories WHERE guild_id = ?1 AND name = ?2 COLLATE NOCASE
+            AND deleted_at IS NULL";
+
+        match self
+            .connection
+            .query_row(QUERY, [guild_id, name.trim()], |row| row.get(0))
+        {
+            Ok(story_id) => Ok(Some(story_id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Find the story with the IFID among the stories playable on the guild.
+    pub fn find_story_by_ifid(&self, guild_id: &str, ifid: &str) -> Result<Option<i64>> {
+        const QUERY: &str =
+            "SELECT id FROM stories WHERE ifid = ?1 AND (guild_id = ?2 OR guild_id = ?3)
+            AND deleted_at IS NULL";
+
+        match self
+            .connection
+            .query_row(QUERY, [ifid, guild_id, GLOBAL_GUILD_ID], |row| row.get(0))
+        {
+            Ok(story_id) => Ok(Some(story_id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Read a setting of the guild, `None` when it was never set.
+    /// Values set by a previous version and that can't be parsed anymore count as not set.
+    pub fn get_setting<T: FromStr>(&self, guild_id: &str, key: &str) -> Result<Option<T>> {
+        const QUERY: &str = "SELECT value FROM guild_settings WHERE guild_id = ?1 AND key = ?2";
+
+        match self
+            .connection
+            .query_row(QUERY, [guild_id, key], |row| row.get::<_, String>(0))
+        {
+            Ok(value) => Ok(value.parse().ok()),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn set_setting<T: ToString>(&self, guild_id: &str, key: &str, value: T) -> Result<()> {
+        const QUERY: &str =
+            "INSERT OR REPLACE INTO guild_settings (guild_id, key, value) VALUES (?1, ?2, ?3)";
+
+        self.connection
+            .execute(QUERY, [guild_id, key, &value.to_string()])?;
+        Ok(())
+    }
+
+    pub fn set_guild_language(&self, guild_id: &str, language: &str) -> Result<()> {
+        self.set_setting(guild_id, LANGUAGE_SETTING, language)
+    }
+
+    /// The language chosen by the guild, or [`DEFAULT_LANGUAGE`].
+    pub fn get_guild_language(&self, guild_id: &str) -> Result<String> {
+        Ok(self
+            .get_setting(guild_id, LANGUAGE_SETTING)?
+            .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string()))
+    }
+
+    pub fn set_guild_layout(&self, guild_id: &str, layout: ChoiceLayout) -> Result<()> {
+        self.set_setting(guild_id, LAYOUT_SETTING, layout)
+    }
+
+    /// How the guild wants the links of passages presented, [`ChoiceLayout::Auto`] by default.
+    pub fn get_guild_layout(&self, guild_id: &str) -> Result<ChoiceLayout> {
+        Ok(self
+            .get_setting(guild_id, LAYOUT_SETTING)?
+            .unwrap_or_default())
+    }
+
+    /// Make sessions of the story start from this passage, instead of the start of the story.
+    pub fn set_start_passage(&self, story_id: i64, passage: &str) -> Result<()> {
+        const QUERY: &str =
+            "INSERT OR REPLACE INTO story_settings (story_id, key, value) VALUES (?1, ?2, ?3)";
+
+        self.connection
+            .execute(QUERY, (story_id, START_PASSAGE_SETTING, passage))?;
+        Ok(())
+    }
+
+    /// The passage chosen with [`Storage::set_start_passage`], if any.
+    pub fn get_start_passage(&self, story_id: i64) -> Result<Option<String>> {
+        const QUERY: &str = "SELECT value FROM story_settings WHERE story_id = ?1 AND key = ?2";
+
+        match self
+            .connection
+            .query_row(QUERY, (story_id, START_PASSAGE_SETTING), |row| row.get(0))
+        {
+            Ok(passage) => Ok(Some(passage)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn set_free_navigation(&self, story_id: i64, free_navigation: bool) -> Result<()> {
+        const QUERY: &str =
+            "INSERT OR REPLACE INTO story_settings (story_id, key, value) VALUES (?1, ?2, ?3)";
+
+        self.connection.execute(
+            QUERY,
+            (
+                story_id,
+                FREE_NAVIGATION_SETTING,
+                free_navigation.to_string(),
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Whether players can jump to any passage of the story, off by default so linear stories
+    /// stay linear.
+    pub fn get_free_navigation(&self, story_id: i64) -> Result<bool> {
+        const QUERY: &str = "SELECT value FROM story_settings WHERE story_id = ?1 AND key = ?2";
+
+        match self
+            .connection
+            .query_row(QUERY, (story_id, FREE_NAVIGATION_SETTING), |row| {
+                row.get::<_, String>(0)
+            }) {
+            Ok(value) => Ok(value.parse().unwrap_or_default()),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn set_canonical_path(&self, story_id: i64, path: &[String]) -> Result<()> {
+        const QUERY: &str =
+            "INSERT OR REPLACE INTO story_settings (story_id, key, value) VALUES (?1, ?2, ?3)";
+
+        self.connection.execute(
+            QUERY,
+            (
+                story_id,
+                CANONICAL_PATH_SETTING,
+                serde_json::to_string(path)?,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// The passages the authors recommend going through, recorded with `/setpath`.
+    pub fn get_canonical_path(&self, story_id: i64) -> Result<Option<Vec<String>>> {
+        const QUERY: &str = "SELECT value FROM story_settings WHERE story_id = ?1 AND key = ?2";
+
+        match self
+            .connection
+            .query_row(QUERY, (story_id, CANONICAL_PATH_SETTING), |row| {
+                row.get::<_, String>(0)
+            }) {
+            Ok(value) => Ok(Some(serde_json::from_str(&value)?)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn set_guild_single_message(&self, guild_id: &str, single_message: bool) -> Result<()> {
+        self.set_setting(guild_id, SINGLE_MESSAGE_SETTING, single_message)
+    }
+
+    /// Whether new sessions on the guild show every passage in the same message, false by default.
+    pub fn get_guild_single_message(&self, guild_id: &str) -> Result<bool> {
+        Ok(self
+            .get_setting(guild_id, SINGLE_MESSAGE_SETTING)?
+            .unwrap_or(false))
+    }
+
+    pub fn set_guild_delay(&self, guild_id: &str, delay: Duration) -> Result<()> {
+        self.set_setting(guild_id, DELAY_SETTING, delay.as_millis())
+    }
+
+    /// How long to wait before showing the next passage on the guild, none by default.
+    pub fn get_guild_delay(&self, guild_id: &str) -> Result<Duration> {
+        Ok(Duration::from_millis(
+            self.get_setting(guild_id, DELAY_SETTING)?.unwrap_or(0),
+        ))
+    }
+
+    pub fn set_command_disabled(
+        &self,
+        guild_id: &str,
+        command: &str,
+        disabled: bool,
+    ) -> Result<()> {
+        self.set_setting(
+            guild_id,
+            &format!("{DISABLED_COMMAND_SETTING_PREFIX}{command}"),
+            disabled,
+        )
+    }
+
+    /// Whether the command was disabled on the guild with `/togglecommand`.
+    pub fn is_command_disabled(&self, guild_id: &str, command: &str) -> Result<bool> {
+        Ok(self
+            .get_setting(
+                guild_id,
+                &format!("{DISABLED_COMMAND_SETTING_PREFIX}{command}"),
+            )?
+            .unwrap_or(false))
+    }
+
+    pub fn add_bug_report(&self, guild_id: &str, story_id: i64, report: &BugReport) -> Result<()> {
+        const QUERY: &str = "INSERT INTO bug_reports
+        (guild_id, story_id, player_id, passage, broken_link, description, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+
+        self.connection.execute(
+            QUERY,
+            (
+                guild_id,
+                story_id,
+                &report.player_id,
+                &report.passage,
+                &report.broken_link,
+                &report.description,
+                report.created_at,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// The latest bug reports of the story on the guild, the most recent first.
+    pub fn list_bug_reports(&self, guild_id: &str, story_id: i64) -> Result<Vec<BugReport>> {
+        let mut statement = self.connection.prepare(
+            "SELECT player_id, passage, broken_link, description, created_at FROM bug_reports
+            WHERE guild_id = ?1 AND story_id = ?2 ORDER BY created_at DESC, id DESC LIMIT ?3",
+        )?;
+        let reports = statement
+            .query_map((guild_id, story_id, MAX_BUG_REPORTS), |row| {
+                Ok(BugReport {
+                    player_id: row.get(0)?,
+                    passage: row.get(1)?,
+                    broken_link: row.get(2)?,
+                    description: row.get(3)?,
+                    created_at: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(reports)
+    }
+
+    /// Record that the player started the story of the session.
+    pub fn record_play(&mut self, game_state: &GameState) -> Result<()> {
+        self.add_event(game_state, PLAY_EVENT)
+    }
+
+    /// Record that the player reached an end of the story of the session.
+    pub fn record_completion(&mut self, game_state: &GameState) -> Result<()> {
+        self.add_event(game_state, COMPLETION_EVENT)
+    }
+
+    /// Record that the player stopped the session, by themselves or by an administrator.
+    pub fn record_stop(&mut self, game_state: &GameState, by_admin: bool) -> Result<()> {
+        self.add_event(game_state, if by_admin { CLEAR_EVENT } else { STOP_EVENT })
+    }
+
+    /// Record that the player is shown the current passage of the session.
+    pub fn record_visit(&mut self, game_state: &GameState) -> Result<()> {
+        let event = Event::new(game_state, VISIT_EVENT);
+        if self.pending_states.is_some() {
+            self.pending_visits.push(event);
+            Ok(())
+        } else {
+            insert_event(&self.connection, &event)
+        }
+    }
+
+    fn add_event(&mut self, game_state: &GameState, kind: &'static str) -> Result<()> {
+        // The last event of a session tells how it ended, so visits must be written before.
+        self.flush_visits()?;
+        insert_event(&self.connection, &Event::new(game_state, kind))
+    }
+
+    /// Where the players of the story stopped without reaching an ending, on every guild, the
+    /// passage most players stopped at first. Only the last session of each player counts: the
+    /// ones stopped with `/stop` or `/clearsession`, and the ones not played for a week.
+    pub fn dropoff_report(&mut self, story_id: i64) -> Result<Vec<Dropoff>> {
+        self.flush_visits()?;
+        let idle_since = now() - IDLE_SESSION_TIME.as_secs() as i64;
+        let mut statement = self.connection.prepare(
+            "WITH last_events AS (
+                SELECT kind, passage, created_at FROM events AS event
+                WHERE story_id = ?1 AND id = (
+                    SELECT MAX(id) FROM events
+                    WHERE story_id = event.story_id
+                    AND guild_id = event.guild_id
+                    AND player_id = event.player_id
+                )
+            )
+            SELECT passage, SUM(kind IN (?4, ?5)), SUM(kind IN (?2, ?3)) FROM last_events
+            WHERE passage IS NOT NULL
+            AND (kind IN (?4, ?5) OR (kind IN (?2, ?3) AND created_at < ?6))
+            GROUP BY passage ORDER BY COUNT(*) DESC, passage",
+        )?;
+        let dropoffs = statement
+            .query_map(
+                (
+                    story_id,
+                    PLAY_EVENT,
+                    VISIT_EVENT,
+                    STOP_EVENT,
+                    CLEAR_EVENT,
+                    idle_since,
+                ),
+                |row| {
+                    Ok(Dropoff {
+                        passage: row.get(0)?,
+                        stopped: row.get(1)?,
+                        idle: row.get(2)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(dropoffs)
+    }
+
+    /// How many times each passage of the story was visited on the guild, the most visited
+    /// first. Passages never visited are not listed.
+    pub fn passage_visit_counts(
+        &mut self,
+        guild_id: &str,
+        story_id: i64,
+    ) -> Result<Vec<(String, i64)>> {
+        self.flush_visits()?;
+        let mut statement = self.connection.prepare(
+            "SELECT passage, COUNT(*) FROM events
+            WHERE guild_id = ?1 AND story_id = ?2 AND kind = ?3 AND passage IS NOT NULL
+            GROUP BY passage ORDER BY COUNT(*) DESC, passage",
+        )?;
+        let counts = statement
+            .query_map((guild_id, story_id, VISIT_EVENT), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(counts)
+    }
+
+    /// Per story metrics of the guild since the unix timestamp, as CSV with a header line: how many
+    /// times the story was started and finished, the average number of steps to finish it, and
+    /// how many players played it.
+    pub fn analytics_csv(&self, guild_id: &str, since: i64) -> Result<String> {
+        let mut statement = self.connection.prepare(
+            "SELECT stories.name,
+                SUM(events.kind = ?3),
+                SUM(events.kind = ?4),
+                AVG(CASE WHEN events.kind = ?4 THEN events.steps END),
+                COUNT(DISTINCT events.player_id)
+            FROM events JOIN stories ON stories.id = events.story_id
+            WHERE events.guild_id = ?1 AND events.created_at >= ?2 AND stories.deleted_at IS NULL
+            GROUP BY events.story_id ORDER BY stories.name",
+        )?;
+
+        let mut csv = String::from("story,plays,completions,average_steps,unique_players\n");
+        let mut rows = statement.query((guild_id, since, PLAY_EVENT, COMPLETION_EVENT))?;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            let plays: i64 = row.get(1)?;
+            let completions: i64 = row.get(2)?;
+            let average_steps: Option<f64> = row.get(3)?;
+            let players: i64 = row.get(4)?;
+            let average_steps = average_steps
+                .map(|steps| format!("{steps:.1}"))
+                .unwrap_or_default();
+            csv.push_str(&format!(
+                "{},{plays},{completions},{average_steps},{players}\n",
+                csv_field(&name)
+            ));
+        }
+        Ok(csv)
+    }
+
+    /// The stories the player started on the guild, the last one first.
+    pub fn recent_stories(
+        &self,
+        player_id: &str,
+        guild_id: &str,
+        limit: usize,
+    ) -> Result<Vec<RecentStory>> {
+        let mut statement = self.connection.prepare(
+            "SELECT stories.id, stories.name, MAX(events.created_at) FROM events
+            JOIN stories ON stories.id = events.story_id
+            WHERE events.player_id = ?1 AND events.guild_id = ?2 AND events.kind = ?3
+            AND stories.deleted_at IS NULL
+            GROUP BY events.story_id ORDER BY MAX(events.created_at) DESC, MAX(events.id) DESC
+            LIMIT ?4",
+        )?;
+        let stories = statement
+            .query_map((player_id, guild_id, PLAY_EVENT, limit), |row| {
+                Ok(RecentStory {
+                    story_id: row.get(0)?,
+                    name: row.get(1)?,
+                    played_at: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(stories)
+    }
+
+    /// The story the player last finished in the guild, to be rated.
+    pub fn last_completed_story(&self, player_id: &str, guild_id: &str) -> Result<Option<i64>> {
+        const QUERY: &str = "SELECT story_id FROM events
+            WHERE player_id = ?1 AND guild_id = ?2 AND kind = ?3
+            ORDER BY created_at DESC, id DESC LIMIT 1";
+
+        match self
+            .connection
+            .query_row(QUERY, (player_id, guild_id, COMPLETION_EVENT), |row| {
+                row.get(0)
+            }) {
+            Ok(story_id) => Ok(Some(story_id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Rate the story from 1 to 5 stars, replacing the previous rating of the player. Only the
+    /// story the player last completed on the guild can be rated, like its end message offers.
+    pub fn rate_story(
+        &self,
+        player_id: &str,
+        guild_id: &str,
+        story_id: i64,
+        stars: u8,
+    ) -> Result<()> {
+        if self.last_completed_story(player_id, guild_id)? != Some(story_id) {
+            return Err(UserError::InvalidSelection.into());
+        }
+        self.connection.execute(
+            "INSERT OR REPLACE INTO ratings (player_id, story_id, stars, rated_at)
+            VALUES (?1, ?2, ?3, ?4)",
+            (player_id, story_id, stars, now()),
+        )?;
+        Ok(())
+    }
+
+    /// The average rating of the story, if it was rated.
+    pub fn average_rating(&self, story_id: i64) -> Result<Option<Rating>> {
+        let (average, count) = self.connection.query_row(
+            "SELECT AVG(stars), COUNT(*) FROM ratings WHERE story_id = ?",
+            [story_id],
+            |row| Ok((row.get::<_, Option<f64>>(0)?, row.get(1)?)),
+        )?;
+        Ok(average.map(|average| Rating { average, count }))
+    }
+
+    /// Put the story at the position in the series of the guild, creating the series if needed,
+    /// or remove it from the series without a position. Series left empty are deleted.
+    pub fn set_series_position(
+        &mut self,
+        guild_id: &str,
+        series_name: &str,
+        story_id: i64,
+        position: Option<i64>,
+    ) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT OR IGNORE INTO series (guild_id, name) VALUES (?1, ?2)",
+            (guild_id, series_name),
+        )?;
+        let series_id: i64 = transaction.query_row(
+            "SELECT id FROM series WHERE guild_id = ?1 AND name = ?2",
+            (guild_id, series_name),
+            |row| row.get(0),
+        )?;
+        match position {
+            Some(position) => transaction.execute(
+                "INSERT OR REPLACE INTO series_membership (series_id, story_id, position)
+                VALUES (?1, ?2, ?3)",
+                (series_id, story_id, position),
+            )?,
+            None => transaction.execute(
+                "DELETE FROM series_membership WHERE series_id = ?1 AND story_id = ?2",
+                (series_id, story_id),
+            )?,
+        };
+        transaction.execute(
+            "DELETE FROM series WHERE id = ?1
+            AND NOT EXISTS (SELECT 1 FROM series_membership WHERE series_id = ?1)",
+            [series_id],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// The series of the guild, by name.
+    pub fn list_series(&self, guild_id: &str) -> Result<Vec<Series>> {
+        let mut statement = self.connection.prepare(
+            "SELECT series.name, COUNT(stories.id) FROM series
+            LEFT JOIN series_membership ON series_membership.series_id = series.id
+            LEFT JOIN stories ON stories.id = series_membership.story_id
+            AND stories.deleted_at IS NULL
+            WHERE series.guild_id = ?1
+            GROUP BY series.id ORDER BY series.name",
+        )?;
+        let series = statement
+            .query_map([guild_id], |row| {
+                Ok(Series {
+                    name: row.get(0)?,
+                    story_count: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(series)
+    }
+
+    pub fn find_series(&self, guild_id: &str, name: &str) -> Result<Option<i64>> {
+        const QUERY: &str = "SELECT id FROM series WHERE guild_id = ?1 AND name = ?2";
+
+        match self
+            .connection
+            .query_row(QUERY, [guild_id, name.trim()], |row| row.get(0))
+        {
+            Ok(series_id) => Ok(Some(series_id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The ids and names of the stories of the series, in the order they are meant to be played.
+    pub fn series_stories(&self, series_id: i64) -> Result<Vec<(i64, String)>> {
+        let mut statement = self.connection.prepare(
+            "SELECT stories.id, stories.name FROM series_membership
+            JOIN stories ON stories.id = series_membership.story_id
+            WHERE series_membership.series_id = ?1 AND stories.deleted_at IS NULL
+            ORDER BY series_membership.position, stories.id",
+        )?;
+        let stories = statement
+            .query_map([series_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(stories)
+    }
+
+    /// Whether the player started and finished each of the stories in the guild.
+    pub fn story_progress(
+        &self,
+        player_id: &str,
+        guild_id: &str,
+        story_ids: &[i64],
+    ) -> Result<Vec<StoryProgress>> {
+        let mut statement = self.connection.prepare(
+            "SELECT SUM(kind = ?4) > 0, SUM(kind = ?5) > 0 FROM events
+            WHERE player_id = ?1 AND guild_id = ?2 AND story_id = ?3",
+        )?;
+        story_ids
+            .iter()
+            .map(|story_id| {
+                Ok(statement.query_row(
+                    (player_id, guild_id, story_id, PLAY_EVENT, COMPLETION_EVENT),
+                    |row| {
+                        Ok(StoryProgress {
+                            played: row.get::<_, Option<bool>>(0)?.unwrap_or(false),
+                            completed: row.get::<_, Option<bool>>(1)?.unwrap_or(false),
+                        })
+                    },
+                )?)
+            })
+            .collect()
+    }
+
+    /// Save the session under the name, replacing the save of its story with the same name.
+    /// Returns whether one was replaced. Players keep at most `max_slots` saves of each story.
+    pub fn save_slot(
+        &mut self,
+        game_state: &GameState,
+        name: &str,
+        max_slots: usize,
+    ) -> Result<bool> {
+        let transaction = self.connection.transaction()?;
+        let (count, replaced): (i64, bool) = transaction.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(name = ?4), 0) > 0 FROM save_slots
+            WHERE player_id = ?1 AND guild_id = ?2 AND story_id = ?3",
+            (
+                &game_state.player_id,
+                &game_state.guild_id,
+                game_state.story_id,
+                name,
+            ),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if !replaced && count as usize >= max_slots {
+            return Err(UserError::TooManySaves(max_slots).into());
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO save_slots
+            (player_id, guild_id, story_id, name, passage, state_blob, saved_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            (
+                &game_state.player_id,
+                &game_state.guild_id,
+                game_state.story_id,
+                name,
+                &game_state.current_chapter,
+                game_state.to_json()?,
+                now(),
+            ),
+        )?;
+        transaction.commit()?;
+        Ok(replaced)
+    }
+
+    /// The session the player saved under the name, for the story if given, or else the last one
+    /// saved with this name.
+    pub fn load_slot(
+        &self,
+        player_id: &str,
+        guild_id: &str,
+        story_id: Option<i64>,
+        name: &str,
+    ) -> Result<Option<GameState>> {
+        const QUERY: &str = "SELECT save_slots.state_blob FROM save_slots
+            JOIN stories ON stories.id = save_slots.story_id
+            WHERE save_slots.player_id = ?1 AND save_slots.guild_id = ?2
+            AND (?3 IS NULL OR save_slots.story_id = ?3) AND save_slots.name = ?4
+            AND stories.deleted_at IS NULL
+            ORDER BY save_slots.saved_at DESC LIMIT 1";
+
+        match self
+            .connection
+            .query_row(QUERY, (player_id, guild_id, story_id, name), |row| {
+                row.get::<_, String>(0)
+            }) {
+            Ok(state_blob) => Ok(Some(GameState::from_json(&state_blob)?)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The saves of the player on the guild, by story and name.
+    pub fn save_slots(&self, player_id: &str, guild_id: &str) -> Result<Vec<SaveSlot>> {
+        let mut statement = self.connection.prepare(
+            "SELECT stories.name, save_slots.name, save_slots.passage, save_slots.saved_at
+            FROM save_slots JOIN stories ON stories.id = save_slots.story_id
+            WHERE save_slots.player_id = ?1 AND save_slots.guild_id = ?2
+            AND stories.deleted_at IS NULL
+            ORDER BY stories.name, save_slots.name",
+        )?;
+        let slots = statement
+            .query_map([player_id, guild_id], |row| {
+                Ok(SaveSlot {
+                    story_name: row.get(0)?,
+                    name: row.get(1)?,
+                    passage: row.get(2)?,
+                    saved_at: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(slots)
+    }
+
+    /// Check that the database can be queried.
+    pub fn ping(&self) -> Result<()> {
+        self.connection.query_row("SELECT 1", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    /// Forget the cached stories, so they are read again from their files.
+    /// Returns how many stories were cached.
+    pub fn invalidate_cache(&mut self) -> usize {
+        self.stories.clear()
+    }
+
+    /// The total size in bytes of the files of the stories of the guild, those in its trash
+    /// left out. Missing files count for nothing.
+    pub fn guild_storage_bytes(&self, guild_id: &str) -> Result<u64> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT filename FROM stories WHERE guild_id = ? AND deleted_at IS NULL")?;
+        let filenames = statement
+            .query_map([guild_id], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let stories_folder = self.stories_folder()?;
+        Ok(filenames
+            .into_iter()
+            .filter_map(|filename| fs::metadata(stories_folder.join(filename)).ok())
+            .map(|metadata| metadata.len())
+            .sum())
+    }
+
+    /// Rebuild the database to give back the space left by deleted rows, returning its size in
+    /// bytes before and after. The database is locked while it runs, and `&mut self` ensures no
+    /// transaction of ours is open, which `VACUUM` refuses.
+    pub fn vacuum(&mut self) -> Result<(u64, u64)> {
+        let before = self.database_size()?;
+        self.connection.execute("VACUUM", [])?;
+        Ok((before, self.database_size()?))
+    }
+
+    fn database_size(&self) -> Result<u64> {
+        Ok(self.connection.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn stories_folder(&self) -> Result<PathBuf> {
+        let folder = self.storage_folder.as_ref().join("stories");
+        if !folder.exists() {
+            fs::create_dir_all(&folder).map_err(not_writable)?;
+        }
+        Ok(folder)
+    }
+}
+
+/// Tell apart the storage being read-only, that only the operator of the bot can fix.
+fn not_writable(error: std::io::Error) -> anyhow::Error {
+    match error.kind() {
+        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+            UserError::StorageNotWritable.into()
+        }
+        _ => error.into(),
+    }
+}
+
+fn create_tables(connection: &Connection) -> Result<()> {
+    connection.execute(CREATE_STORIES, [])?;
+    add_column(connection, "stories", "ifid", "TEXT")?;
+    add_column(connection, "stories", "created_at", "INTEGER")?;
+    add_column(connection, "stories", "modified_at", "INTEGER")?;
+    add_column(connection, "stories", "author", "TEXT")?;
+    add_column(connection, "stories", "deleted_at", "INTEGER")?;
+    connection.execute(CREATE_STORY_STATE, [])?;
+    add_column(connection, "story_state", "state_blob", "TEXT")?;
+    connection.execute(CREATE_ACKNOWLEDGED_WARNINGS, [])?;
+    connection.execute(CREATE_GUILD_SETTINGS, [])?;
+    connection.execute(CREATE_STORY_SETTINGS, [])?;
+    connection.execute(CREATE_BUG_REPORTS, [])?;
+    connection.execute(CREATE_EVENTS, [])?;
+    add_column(connection, "events", "passage", "TEXT")?;
+    connection.execute(CREATE_RATINGS, [])?;
+    connection.execute(CREATE_SERIES, [])?;
+    connection.execute(CREATE_SERIES_MEMBERSHIP, [])?;
+    connection.execute(CREATE_SAVE_SLOTS, [])?;
+    Ok(())
+}
+
+fn insert_event(connection: &Connection, event: &Event) -> Result<()> {
+    const QUERY: &str = "INSERT INTO events
+    (guild_id, story_id, player_id, kind, passage, steps, created_at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+
+    connection.execute(
+        QUERY,
+        (
+            &event.guild_id,
+            event.story_id,
+            &event.player_id,
+            event.kind,
+            &event.passage,
+            event.steps,
+            event.created_at,
+        ),
+    )?;
+    Ok(())
+}
+
+/// Quote the field if it would break the CSV line, doubling its quotes.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Add a column to a table created by a previous version, if it isn't there yet.
+fn add_column(connection: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut statement = connection.prepare(&format!("PRAGMA table_info({table})"))?;
+    let exists = statement
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        connection.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn storage(folder: &TempDir) -> Storage<PathBuf> {
+        Storage::new(folder.path().to_path_buf(), Duration::from_secs(60)).unwrap()
+    }
+
+    fn seed(title: &str, text: &str) -> String {
+        format!(
+            ":: StoryTitle\n{title}\n\n:: StoryData\n{{\"ifid\": \"c3d3a9b6-0b0e-4f4e-9d8f-1f6b1a2e3c4d\"}}\n\n:: Start\n{text}\n"
+        )
+    }
+
+    #[test]
+    fn unchanged_seed_is_not_saved_again() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let content = seed("Seed", "Hello");
+
+        assert!(storage.seed_global_story(&content).unwrap());
+        let story_id = storage
+            .find_story_by_ifid("any", "C3D3A9B6-0B0E-4F4E-9D8F-1F6B1A2E3C4D")
+            .unwrap()
+            .unwrap();
+        let modified_at = storage.story_modified_at(story_id).unwrap();
+
+        assert!(!storage.seed_global_story(&content).unwrap());
+        assert_eq!(storage.story_modified_at(story_id).unwrap(), modified_at);
+    }
+
+    #[test]
+    fn unchanged_seed_without_ifid_is_not_saved_again() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let content = ":: StoryTitle\nPlain\n\n:: Start\nHello\n";
+
+        assert!(storage.seed_global_story(content).unwrap());
+        assert!(!storage.seed_global_story(content).unwrap());
+    }
+
+    #[test]
+    fn changed_seed_keeps_its_sessions() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        storage.seed_global_story(&seed("Seed", "Hello")).unwrap();
+        let story_id = storage
+            .find_story_by_ifid("guild", "C3D3A9B6-0B0E-4F4E-9D8F-1F6B1A2E3C4D")
+            .unwrap()
+            .unwrap();
+        let game_state = GameState::new(
+            "player".to_string(),
+            "guild".to_string(),
+            story_id,
+            "Start".to_string(),
+        );
+        storage.update_game_state(&game_state).unwrap();
+        storage.record_completion(&game_state).unwrap();
+        storage.rate_story("player", "guild", story_id, 4).unwrap();
+
+        assert!(storage
+            .seed_global_story(&seed("Renamed seed", "Hello again"))
+            .unwrap());
+
+        let updated_id = storage
+            .find_story_by_ifid("guild", "C3D3A9B6-0B0E-4F4E-9D8F-1F6B1A2E3C4D")
+            .unwrap();
+        assert_eq!(updated_id, Some(story_id));
+        assert!(storage
+            .get_story_content(story_id)
+            .unwrap()
+            .contains("Hello again"));
+        let kept = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(kept.story_id, story_id);
+        let ratings: i64 = storage
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM ratings WHERE story_id = ?1",
+                [story_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(ratings, 1);
+    }
+
+    fn session(storage: &mut Storage<PathBuf>, player_id: &str) -> GameState {
+        storage.seed_global_story(&seed("Seed", "Hello")).unwrap();
+        let story_id = storage
+            .find_story_by_ifid("guild", "C3D3A9B6-0B0E-4F4E-9D8F-1F6B1A2E3C4D")
+            .unwrap()
+            .unwrap();
+        GameState::new(
+            player_id.to_string(),
+            "guild".to_string(),
+            story_id,
+            "Start".to_string(),
+        )
+    }
+
+    #[test]
+    fn starting_a_story_replaces_the_session_at_the_cap() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let game_state = session(&mut storage, "player");
+
+        assert!(storage.can_start_session("player", "guild", 1).unwrap());
+        storage.update_game_state(&game_state).unwrap();
+        assert_eq!(storage.player_session_count("player", "guild").unwrap(), 1);
+        assert!(storage.can_start_session("player", "guild", 1).unwrap());
+        assert!(!storage.can_start_session("player", "guild", 0).unwrap());
+    }
+
+    #[test]
+    fn sessions_are_counted_per_player_and_guild() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        storage.enable_autosave();
+        let game_state = session(&mut storage, "player");
+        storage.update_game_state(&game_state).unwrap();
+
+        // Sessions still waiting to be written count too.
+        assert_eq!(storage.player_session_count("player", "guild").unwrap(), 1);
+        assert_eq!(storage.player_session_count("other", "guild").unwrap(), 0);
+        assert_eq!(storage.player_session_count("player", "other").unwrap(), 0);
+    }
+
+    #[test]
+    fn stopping_a_session_frees_its_slot() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let game_state = session(&mut storage, "player");
+        storage.update_game_state(&game_state).unwrap();
+
+        storage.clear_game_state("player", "guild").unwrap();
+        assert_eq!(storage.player_session_count("player", "guild").unwrap(), 0);
+        assert!(storage.can_start_session("player", "guild", 1).unwrap());
+    }
+
+    #[test]
+    fn pending_states_are_written_on_flush() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        storage.enable_autosave();
+        let game_state = session(&mut storage, "player");
+        storage.update_game_state(&game_state).unwrap();
+        storage.record_visit(&game_state).unwrap();
+
+        assert_eq!(storage.flush_game_states().unwrap(), 1);
+        assert_eq!(storage.flush_game_states().unwrap(), 0);
+        storage.pending_states = None;
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.current_chapter, "Start");
+    }
+
+    #[test]
+    fn pending_states_are_kept_when_writing_fails() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        storage.enable_autosave();
+        let first = session(&mut storage, "first");
+        let second = session(&mut storage, "second");
+        storage.update_game_state(&first).unwrap();
+        storage.update_game_state(&second).unwrap();
+        storage.record_visit(&first).unwrap();
+
+        storage
+            .connection
+            .execute_batch("ALTER TABLE story_state RENAME TO broken; ALTER TABLE events RENAME TO broken_events;")
+            .unwrap();
+        assert!(storage.flush_game_states().is_err());
+        assert_eq!(storage.pending_states.as_ref().unwrap().len(), 2);
+        assert!(storage.flush_visits().is_err());
+        assert_eq!(storage.pending_visits.len(), 1);
+
+        storage
+            .connection
+            .execute_batch("ALTER TABLE broken RENAME TO story_state; ALTER TABLE broken_events RENAME TO events;")
+            .unwrap();
+        assert_eq!(storage.flush_game_states().unwrap(), 2);
+        assert!(storage.pending_visits.is_empty());
+        assert_eq!(storage.player_session_count("first", "guild").unwrap(), 1);
+        assert_eq!(storage.player_session_count("second", "guild").unwrap(), 1);
+    }
+
+    #[test]
+    fn stories_of_guilds_with_the_same_name_are_cached_apart() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        storage
+            .save_story("first", ":: StoryTitle\nSame\n\n:: First\nFirst guild\n")
+            .unwrap();
+        storage
+            .save_story("second", ":: StoryTitle\nSame\n\n:: Second\nSecond guild\n")
+            .unwrap();
+        let first = storage.find_guild_story("first", "Same").unwrap().unwrap();
+        let second = storage.find_guild_story("second", "Same").unwrap().unwrap();
+
+        // The second time, the stories come from the cache.
+        for _ in 0..2 {
+            let story = storage.get_story(first).unwrap();
+            assert!(story.get_passage("First").is_some());
+            let story = storage.get_story(second).unwrap();
+            assert!(story.get_passage("Second").is_some());
+        }
+
+        storage.soft_delete_story(first).unwrap();
+        assert!(storage.get_story(first).is_err());
+        assert!(storage.get_story(second).is_ok());
+    }
+
+    #[test]
+    fn story_contents_are_read_from_the_cache() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let content = ":: StoryTitle\nCached\n\n:: Start [cw:dark]\nHello\n";
+        storage.save_story("guild", content).unwrap();
+        let story_id = storage
+            .find_guild_story("guild", "Cached")
+            .unwrap()
+            .unwrap();
+
+        for file in fs::read_dir(folder.path().join("stories")).unwrap() {
+            fs::remove_file(file.unwrap().path()).unwrap();
+        }
+        assert_eq!(storage.get_story_content(story_id).unwrap(), content);
+
+        storage.invalidate_cache();
+        assert!(storage.get_story_content(story_id).is_err());
+    }
+
+    #[test]
+    fn trashed_stories_do_not_count_in_the_storage() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let content = ":: StoryTitle\nKept\n\n:: Start\nHello\n";
+        storage.save_story("guild", content).unwrap();
+        storage
+            .save_story("guild", ":: StoryTitle\nTrashed\n\n:: Start\nHello\n")
+            .unwrap();
+        let trashed = storage
+            .find_guild_story("guild", "Trashed")
+            .unwrap()
+            .unwrap();
+
+        storage.soft_delete_story(trashed).unwrap();
+        assert_eq!(
+            storage.guild_storage_bytes("guild").unwrap(),
+            content.len() as u64
+        );
+    }
+
+    #[test]
+    fn vacuuming_gives_back_the_space_of_deleted_stories() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        storage
+            .save_story("guild", ":: StoryTitle\nKept\n\n:: Start\nHello\n")
+            .unwrap();
+        let titles: Vec<String> = (0..200)
+            .map(|index| format!("Deleted {index} {}", "x".repeat(200)))
+            .collect();
+        for title in &titles {
+            storage
+                .save_story(
+                    "guild",
+                    &format!(":: StoryTitle\n{title}\n\n:: Start\nHello\n"),
+                )
+                .unwrap();
+        }
+        for title in &titles {
+            let story_id = storage.find_guild_story("guild", title).unwrap().unwrap();
+            storage.delete_story(story_id).unwrap();
+        }
+
+        let (before, after) = storage.vacuum().unwrap();
+        assert!(after < before);
+        assert!(storage.find_guild_story("guild", "Kept").unwrap().is_some());
+    }
+
+    #[test]
+    fn only_the_last_completed_story_can_be_rated() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let game_state = session(&mut storage, "player");
+        let story_id = game_state.story_id;
+        assert!(storage.rate_story("player", "guild", story_id, 5).is_err());
+
+        storage.record_completion(&game_state).unwrap();
+        assert!(storage
+            .rate_story("player", "other guild", story_id, 5)
+            .is_err());
+        assert!(storage
+            .rate_story("other player", "guild", story_id, 5)
+            .is_err());
+        assert!(storage
+            .rate_story("player", "guild", story_id + 1, 5)
+            .is_err());
+        assert!(storage.average_rating(story_id).unwrap().is_none());
+
+        storage.rate_story("player", "guild", story_id, 4).unwrap();
+        let rating = storage.average_rating(story_id).unwrap().unwrap();
+        assert_eq!((rating.average, rating.count), (4.0, 1));
+    }
+
+    #[test]
+    fn visits_are_counted_per_passage_on_the_guild() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let mut game_state = session(&mut storage, "player");
+        let story_id = game_state.story_id;
+        storage.record_visit(&game_state).unwrap();
+        game_state.current_chapter = "Cave".to_string();
+        storage.record_visit(&game_state).unwrap();
+        storage.record_visit(&game_state).unwrap();
+        let mut other_player = session(&mut storage, "other player");
+        other_player.current_chapter = "Cave".to_string();
+        storage.record_visit(&other_player).unwrap();
+        let mut other_guild = session(&mut storage, "player");
+        other_guild.guild_id = "other guild".to_string();
+        storage.record_visit(&other_guild).unwrap();
+
+        assert_eq!(
+            storage.passage_visit_counts("guild", story_id).unwrap(),
+            [("Cave".to_string(), 3), ("Start".to_string(), 1)]
+        );
+        assert_eq!(
+            storage
+                .passage_visit_counts("other guild", story_id)
+                .unwrap(),
+            [("Start".to_string(), 1)]
+        );
+    }
+
+    /// Move the events of the player back in time, like a session left for that long.
+    fn age_events(storage: &Storage<PathBuf>, player_id: &str, age: Duration) {
+        storage
+            .connection
+            .execute(
+                "UPDATE events SET created_at = created_at - ?1 WHERE player_id = ?2",
+                (age.as_secs() as i64, player_id),
+            )
+            .unwrap();
+    }
+
+    fn dropoffs(storage: &mut Storage<PathBuf>, story_id: i64) -> Vec<(String, i64, i64)> {
+        storage
+            .dropoff_report(story_id)
+            .unwrap()
+            .into_iter()
+            .map(|dropoff| (dropoff.passage, dropoff.stopped, dropoff.idle))
+            .collect()
+    }
+
+    #[test]
+    fn stopped_and_idle_sessions_drop_off_at_their_passage() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let mut stopped = session(&mut storage, "stopped");
+        let story_id = stopped.story_id;
+        storage.record_play(&stopped).unwrap();
+        stopped.current_chapter = "Cave".to_string();
+        storage.record_visit(&stopped).unwrap();
+        storage.record_stop(&stopped, false).unwrap();
+        let mut cleared = session(&mut storage, "cleared");
+        cleared.current_chapter = "Cave".to_string();
+        storage.record_visit(&cleared).unwrap();
+        storage.record_stop(&cleared, true).unwrap();
+        let mut idle = session(&mut storage, "idle");
+        idle.current_chapter = "Lake".to_string();
+        storage.record_visit(&idle).unwrap();
+        age_events(
+            &storage,
+            "idle",
+            IDLE_SESSION_TIME + Duration::from_secs(60),
+        );
+
+        assert_eq!(
+            dropoffs(&mut storage, story_id),
+            [("Cave".to_string(), 2, 0), ("Lake".to_string(), 0, 1)]
+        );
+    }
+
+    #[test]
+    fn completed_and_recent_sessions_dont_drop_off() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let mut completed = session(&mut storage, "completed");
+        let story_id = completed.story_id;
+        completed.current_chapter = "End".to_string();
+        storage.record_visit(&completed).unwrap();
+        storage.record_completion(&completed).unwrap();
+        age_events(
+            &storage,
+            "completed",
+            IDLE_SESSION_TIME + Duration::from_secs(60),
+        );
+        let mut playing = session(&mut storage, "playing");
+        playing.current_chapter = "Cave".to_string();
+        storage.record_visit(&playing).unwrap();
+
+        assert!(dropoffs(&mut storage, story_id).is_empty());
+    }
+
+    #[test]
+    fn only_the_last_session_of_a_player_drops_off() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let mut game_state = session(&mut storage, "player");
+        let story_id = game_state.story_id;
+        game_state.current_chapter = "Cave".to_string();
+        storage.record_visit(&game_state).unwrap();
+        storage.record_stop(&game_state, false).unwrap();
+        // Started again, and stopped further.
+        game_state.current_chapter = "Lake".to_string();
+        storage.record_play(&game_state).unwrap();
+        storage.record_visit(&game_state).unwrap();
+        storage.record_stop(&game_state, false).unwrap();
+
+        assert_eq!(
+            dropoffs(&mut storage, story_id),
+            [("Lake".to_string(), 1, 0)]
+        );
+    }
+
+    /// Save a story of the guild, returning its id.
+    fn guild_story(storage: &mut Storage<PathBuf>, guild_id: &str, title: &str) -> i64 {
+        let content = format!(":: StoryTitle\n{title}\n\n:: Start\nHello\n");
+        storage.save_story(guild_id, &content).unwrap();
+        storage.find_guild_story(guild_id, title).unwrap().unwrap()
+    }
+
+    fn guild_session(player_id: &str, story_id: i64, steps: usize) -> GameState {
+        GameState {
+            history: vec!["Start".to_string(); steps],
+            ..GameState::new(
+                player_id.to_string(),
+                "guild".to_string(),
+                story_id,
+                "Start".to_string(),
+            )
+        }
+    }
+
+    #[test]
+    fn settings_are_set_and_overwritten() {
+        let folder = TempDir::new().unwrap();
+        let storage = storage(&folder);
+
+        storage.set_setting("guild", "delay", 500).unwrap();
+        assert_eq!(storage.get_setting("guild", "delay").unwrap(), Some(500));
+        storage.set_setting("guild", "delay", 1000).unwrap();
+        assert_eq!(storage.get_setting("guild", "delay").unwrap(), Some(1000));
+        // Settings are per guild.
+        assert_eq!(storage.get_setting::<i64>("other", "delay").unwrap(), None);
+    }
+
+    #[test]
+    fn missing_settings_are_not_set() {
+        let folder = TempDir::new().unwrap();
+        let storage = storage(&folder);
+
+        assert_eq!(
+            storage.get_setting::<bool>("guild", "missing").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn settings_that_cant_be_parsed_are_not_set() {
+        let folder = TempDir::new().unwrap();
+        let storage = storage(&folder);
+
+        storage.set_setting("guild", "delay", "soon").unwrap();
+        assert_eq!(storage.get_setting::<u64>("guild", "delay").unwrap(), None);
+        assert_eq!(
+            storage.get_setting::<String>("guild", "delay").unwrap(),
+            Some("soon".to_string())
+        );
+    }
+
+    #[test]
+    fn guilds_speak_the_default_language_until_they_choose_one() {
+        let folder = TempDir::new().unwrap();
+        let storage = storage(&folder);
+
+        assert_eq!(
+            storage.get_guild_language("guild").unwrap(),
+            DEFAULT_LANGUAGE
+        );
+        storage.set_guild_language("guild", "fr").unwrap();
+        assert_eq!(storage.get_guild_language("guild").unwrap(), "fr");
+        assert_eq!(
+            storage.get_guild_language("other").unwrap(),
+            DEFAULT_LANGUAGE
+        );
+    }
+
+    #[test]
+    fn updating_a_story_keeps_its_creation_date() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let story_id = guild_story(&mut storage, "guild", "Cave");
+        // Saved a while ago.
+        storage
+            .connection
+            .execute(
+                "UPDATE stories SET created_at = created_at - 3600, modified_at = modified_at - 3600",
+                [],
+            )
+            .unwrap();
+        let details = |storage: &Storage<PathBuf>| {
+            let stories = storage.list_guild_stories_detailed("guild", false).unwrap();
+            let [story] = stories.as_slice() else {
+                panic!("the guild should have a single story");
+            };
+            assert_eq!(story.id, story_id);
+            (story.created_at.unwrap(), story.modified_at.unwrap())
+        };
+        let (created_at, modified_at) = details(&storage);
+
+        let updated = ":: StoryTitle\nCave\n\n:: Start\nHello again\n";
+        assert!(matches!(
+            storage.save_story("guild", updated).unwrap(),
+            SaveStory::Update
+        ));
+        let (updated_created_at, updated_modified_at) = details(&storage);
+        assert_eq!(updated_created_at, created_at);
+        assert!(updated_modified_at > modified_at);
+    }
+
+    fn search(storage: &Storage<PathBuf>, query: &str, content: bool) -> Vec<(String, bool)> {
+        storage
+            .search_stories("guild", query, content)
+            .unwrap()
+            .into_iter()
+            .map(|result| (result.name, result.title_match))
+            .collect()
+    }
+
+    #[test]
+    fn titles_are_searched_ignoring_the_case() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        guild_story(&mut storage, "guild", "The Dark Cave");
+        guild_story(&mut storage, "guild", "Lake");
+        guild_story(&mut storage, "other", "Cave of the other guild");
+
+        assert_eq!(
+            search(&storage, "cAVE", false),
+            [("The Dark Cave".to_string(), true)]
+        );
+        assert!(search(&storage, "forest", true).is_empty());
+    }
+
+    #[test]
+    fn wildcards_of_the_query_are_searched_as_is() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        guild_story(&mut storage, "guild", "100% cave");
+        guild_story(&mut storage, "guild", "100 caves");
+        guild_story(&mut storage, "guild", "snake_case");
+        guild_story(&mut storage, "guild", "snakes case");
+
+        assert_eq!(
+            search(&storage, "0%", false),
+            [("100% cave".to_string(), true)]
+        );
+        assert_eq!(
+            search(&storage, "e_c", false),
+            [("snake_case".to_string(), true)]
+        );
+    }
+
+    #[test]
+    fn content_matches_come_after_title_matches() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        storage
+            .save_story(
+                "guild",
+                ":: StoryTitle\nLake\n\n:: Start\nA dragon, and another dragon.\n",
+            )
+            .unwrap();
+        storage
+            .save_story("guild", ":: StoryTitle\nForest\n\n:: Start\nA dragon.\n")
+            .unwrap();
+        guild_story(&mut storage, "guild", "Dragon");
+
+        assert_eq!(
+            search(&storage, "dragon", true),
+            [
+                ("Dragon".to_string(), true),
+                ("Lake".to_string(), false),
+                ("Forest".to_string(), false),
+            ]
+        );
+        // Without the content, only the titles are searched.
+        assert_eq!(
+            search(&storage, "dragon", false),
+            [("Dragon".to_string(), true)]
+        );
+    }
+
+    #[test]
+    fn trashed_stories_are_not_searched() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let story_id = guild_story(&mut storage, "guild", "Cave");
+        storage.soft_delete_story(story_id).unwrap();
+
+        assert!(search(&storage, "cave", true).is_empty());
+    }
+
+    fn sessions(storage: &mut Storage<PathBuf>, guild_id: &str) -> Vec<(String, String, String)> {
+        storage
+            .list_guild_sessions(guild_id)
+            .unwrap()
+            .into_iter()
+            .map(|session| (session.player_id, session.story_name, session.passage))
+            .collect()
+    }
+
+    #[test]
+    fn sessions_pending_under_autosave_are_listed() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        storage.enable_autosave();
+        let cave = guild_story(&mut storage, "guild", "Cave");
+        let mut second = guild_session("second", cave, 0);
+        second.current_chapter = "Lake".to_string();
+        storage.update_game_state(&second).unwrap();
+        storage
+            .update_game_state(&guild_session("first", cave, 0))
+            .unwrap();
+        assert_eq!(storage.pending_states.as_ref().unwrap().len(), 2);
+
+        assert_eq!(
+            sessions(&mut storage, "guild"),
+            [
+                ("first".to_string(), "Cave".to_string(), "Start".to_string()),
+                ("second".to_string(), "Cave".to_string(), "Lake".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn guilds_without_sessions_list_none() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let cave = guild_story(&mut storage, "guild", "Cave");
+        storage
+            .update_game_state(&guild_session("player", cave, 0))
+            .unwrap();
+
+        assert!(sessions(&mut storage, "other").is_empty());
+    }
+
+    fn recent(storage: &Storage<PathBuf>, player_id: &str, limit: usize) -> Vec<String> {
+        storage
+            .recent_stories(player_id, "guild", limit)
+            .unwrap()
+            .into_iter()
+            .map(|story| story.name)
+            .collect()
+    }
+
+    #[test]
+    fn recent_stories_are_the_last_played_first() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let cave = guild_story(&mut storage, "guild", "Cave");
+        let lake = guild_story(&mut storage, "guild", "Lake");
+        let forest = guild_story(&mut storage, "guild", "Forest");
+        for story_id in [cave, lake, forest, cave] {
+            storage
+                .record_play(&guild_session("player", story_id, 0))
+                .unwrap();
+        }
+        storage
+            .record_play(&guild_session("other", lake, 0))
+            .unwrap();
+
+        assert_eq!(recent(&storage, "player", 5), ["Cave", "Forest", "Lake"]);
+        assert_eq!(recent(&storage, "player", 2), ["Cave", "Forest"]);
+    }
+
+    #[test]
+    fn recent_stories_leave_out_trashed_stories() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let cave = guild_story(&mut storage, "guild", "Cave");
+        let lake = guild_story(&mut storage, "guild", "Lake");
+        for story_id in [cave, lake] {
+            storage
+                .record_play(&guild_session("player", story_id, 0))
+                .unwrap();
+        }
+        storage.soft_delete_story(lake).unwrap();
+
+        assert_eq!(recent(&storage, "player", 5), ["Cave"]);
+    }
+
+    #[test]
+    fn players_without_history_have_no_recent_stories() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        guild_story(&mut storage, "guild", "Cave");
+
+        assert!(recent(&storage, "player", 5).is_empty());
+    }
+
+    #[test]
+    fn analytics_count_the_plays_and_completions_of_each_story() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let cave = guild_story(&mut storage, "guild", "Cave");
+        let lake = guild_story(&mut storage, "guild", "Lake");
+        for (player_id, steps) in [("first", 2), ("second", 5)] {
+            let game_state = guild_session(player_id, cave, steps);
+            storage.record_play(&game_state).unwrap();
+            storage.record_completion(&game_state).unwrap();
+        }
+        storage
+            .record_play(&guild_session("first", cave, 0))
+            .unwrap();
+        storage
+            .record_play(&guild_session("third", lake, 0))
+            .unwrap();
+
+        assert_eq!(
+            storage.analytics_csv("guild", 0).unwrap(),
+            "story,plays,completions,average_steps,unique_players\nCave,3,2,3.5,2\nLake,1,0,,1\n"
+        );
+        assert_eq!(
+            storage.analytics_csv("other guild", 0).unwrap(),
+            "story,plays,completions,average_steps,unique_players\n"
+        );
+    }
+
+    #[test]
+    fn analytics_only_count_the_events_since_the_cutoff() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let cave = guild_story(&mut storage, "guild", "Cave");
+        storage.record_play(&guild_session("old", cave, 0)).unwrap();
+        age_events(&storage, "old", Duration::from_secs(3600));
+        storage.record_play(&guild_session("new", cave, 0)).unwrap();
+
+        assert_eq!(
+            storage.analytics_csv("guild", now() - 60).unwrap(),
+            "story,plays,completions,average_steps,unique_players\nCave,1,0,,1\n"
+        );
+    }
+
+    #[test]
+    fn analytics_leave_out_trashed_stories() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let cave = guild_story(&mut storage, "guild", "Cave");
+        storage
+            .record_play(&guild_session("player", cave, 0))
+            .unwrap();
+        storage.soft_delete_story(cave).unwrap();
+
+        assert_eq!(
+            storage.analytics_csv("guild", 0).unwrap(),
+            "story,plays,completions,average_steps,unique_players\n"
+        );
+    }
+
+    #[test]
+    fn analytics_quote_the_story_names() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let story = guild_story(&mut storage, "guild", "Cats, \"and\" dogs");
+        storage
+            .record_play(&guild_session("player", story, 0))
+            .unwrap();
+
+        assert_eq!(
+            storage.analytics_csv("guild", 0).unwrap(),
+            "story,plays,completions,average_steps,unique_players\n\"Cats, \"\"and\"\" dogs\",1,0,,1\n"
+        );
+    }
+
+    #[test]
+    fn history_survives_reopening_the_storage() {
+        let folder = TempDir::new().unwrap();
+        {
+            let mut storage = storage(&folder);
+            let mut game_state = session(&mut storage, "player");
+            game_state.history = vec!["Start".to_string()];
+            game_state.current_chapter = "Cave".to_string();
+            storage.update_game_state(&game_state).unwrap();
+        }
+
+        let mut storage = storage(&folder);
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.current_chapter, "Cave");
+        assert_eq!(saved.history, ["Start"]);
+    }
+
+    #[test]
+    fn history_past_the_last_checkpoint_is_lost_on_reopening() {
+        let folder = TempDir::new().unwrap();
+        {
+            let mut storage = storage(&folder);
+            let mut game_state = session(&mut storage, "player");
+            game_state.history = vec!["Start".to_string()];
+            game_state.current_chapter = "Cave".to_string();
+            storage.update_game_state(&game_state).unwrap();
+
+            game_state.history.push("Cave".to_string());
+            game_state.current_chapter = "Lake".to_string();
+            storage.keep_unsaved_game_state(&game_state);
+            let unsaved = storage.retrieve_game_state("player", "guild").unwrap();
+            assert_eq!(unsaved.history, ["Start", "Cave"]);
+        }
+
+        // The session resumes from the checkpoint, with the history it had then.
+        let mut storage = storage(&folder);
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.current_chapter, "Cave");
+        assert_eq!(saved.history, ["Start"]);
+    }
+
+    #[test]
+    fn saves_are_listed_and_loaded_back() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let mut game_state = session(&mut storage, "player");
+        game_state.current_chapter = "Cave".to_string();
+        game_state.history = vec!["Start".to_string()];
+        game_state
+            .variables
+            .insert("torch".to_string(), "lit".to_string());
+
+        assert!(!storage
+            .save_slot(&game_state, "before the lake", 3)
+            .unwrap());
+        assert!(!storage.save_slot(&game_state, "backup", 3).unwrap());
+
+        let slots: Vec<(String, String, String)> = storage
+            .save_slots("player", "guild")
+            .unwrap()
+            .into_iter()
+            .map(|slot| (slot.story_name, slot.name, slot.passage))
+            .collect();
+        assert_eq!(
+            slots,
+            [
+                ("Seed".to_string(), "backup".to_string(), "Cave".to_string()),
+                (
+                    "Seed".to_string(),
+                    "before the lake".to_string(),
+                    "Cave".to_string()
+                ),
+            ]
+        );
+        assert!(storage
+            .save_slots("other player", "guild")
+            .unwrap()
+            .is_empty());
+
+        let loaded = storage
+            .load_slot("player", "guild", None, "before the lake")
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.current_chapter, "Cave");
+        assert_eq!(loaded.history, ["Start"]);
+        assert_eq!(
+            loaded.variables.get("torch").map(String::as_str),
+            Some("lit")
+        );
+        assert!(storage
+            .load_slot("player", "guild", Some(game_state.story_id + 1), "backup")
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .load_slot("player", "guild", None, "missing")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn saving_under_the_same_name_replaces_the_save() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let mut game_state = session(&mut storage, "player");
+        storage.save_slot(&game_state, "slot", 1).unwrap();
+
+        game_state.current_chapter = "Cave".to_string();
+        assert!(storage.save_slot(&game_state, "slot", 1).unwrap());
+
+        let slots = storage.save_slots("player", "guild").unwrap();
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots[0].passage, "Cave");
+        let loaded = storage
+            .load_slot("player", "guild", Some(game_state.story_id), "slot")
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.current_chapter, "Cave");
+    }
+
+    #[test]
+    fn saves_are_limited_per_story() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let game_state = session(&mut storage, "player");
+        storage.save_slot(&game_state, "first", 2).unwrap();
+        storage.save_slot(&game_state, "second", 2).unwrap();
+
+        let error = storage.save_slot(&game_state, "third", 2).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(UserError::TooManySaves(2))
+        ));
+        assert_eq!(storage.save_slots("player", "guild").unwrap().len(), 2);
+        // Replacing a save doesn't need a free slot.
+        assert!(storage.save_slot(&game_state, "second", 2).unwrap());
+    }
+
+    fn is_not_writable(error: &anyhow::Error) -> bool {
+        matches!(error.downcast_ref(), Some(UserError::StorageNotWritable))
+    }
+
+    #[test]
+    fn permission_errors_are_told_apart() {
+        for kind in [ErrorKind::PermissionDenied, ErrorKind::ReadOnlyFilesystem] {
+            assert!(is_not_writable(&not_writable(kind.into())));
+        }
+        assert!(!is_not_writable(&not_writable(ErrorKind::NotFound.into())));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_folders_are_not_writable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let folder = TempDir::new().unwrap();
+        let storage = storage(&folder);
+        assert!(storage.check_writable().is_ok());
+
+        let stories_folder = folder.path().join("stories");
+        fs::set_permissions(&stories_folder, fs::Permissions::from_mode(0o555)).unwrap();
+        // Permissions don't apply to root, who can write anyway.
+        if fs::write(stories_folder.join("probe"), []).is_err() {
+            assert!(is_not_writable(&storage.check_writable().unwrap_err()));
+        }
+        fs::set_permissions(&stories_folder, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn storage_sums_the_stories_of_the_guild() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let first = ":: StoryTitle\nFirst\n\n:: Start\nHello\n";
+        let second = ":: StoryTitle\nSecond\n\n:: Start\nHello again\n";
+        storage.save_story("guild", first).unwrap();
+        storage.save_story("guild", second).unwrap();
+        storage
+            .save_story(
+                "other guild",
+                ":: StoryTitle\nOther\n\n:: Start\nElsewhere\n",
+            )
+            .unwrap();
+        assert_eq!(
+            storage.guild_storage_bytes("guild").unwrap(),
+            (first.len() + second.len()) as u64
+        );
+        assert_eq!(storage.guild_storage_bytes("empty guild").unwrap(), 0);
+
+        let filename: String = storage
+            .connection
+            .query_row(
+                "SELECT filename FROM stories WHERE name = 'Second'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        fs::remove_file(folder.path().join("stories").join(filename)).unwrap();
+        assert_eq!(
+            storage.guild_storage_bytes("guild").unwrap(),
+            first.len() as u64
+        );
+    }
+
+    /// The files of the stories folder.
+    fn story_files(folder: &TempDir) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(folder.path().join("stories"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn checking_a_story_writes_nothing() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let content = ":: StoryTitle\nKept\n\n:: Start\nHello\n";
+        storage.save_story("guild", content).unwrap();
+        let files = story_files(&folder);
+        let story_count = |storage: &Storage<PathBuf>| -> i64 {
+            storage
+                .connection
+                .query_row("SELECT COUNT(*) FROM stories", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        assert!(matches!(
+            storage
+                .check_story("guild", ":: StoryTitle\nNew\n\n:: Start\nHi\n")
+                .unwrap(),
+            SaveStory::New
+        ));
+        assert!(matches!(
+            storage
+                .check_story("guild", ":: StoryTitle\nKept\n\n:: Start\nChanged\n")
+                .unwrap(),
+            SaveStory::Update
+        ));
+
+        assert_eq!(story_count(&storage), 1);
+        assert!(storage.find_guild_story("guild", "New").unwrap().is_none());
+        assert_eq!(story_files(&folder), files);
+        let story_id = storage.find_guild_story("guild", "Kept").unwrap().unwrap();
+        storage.invalidate_cache();
+        assert_eq!(storage.get_story_content(story_id).unwrap(), content);
+    }
+
+    #[test]
+    fn trashed_stories_are_restored_or_purged_after_retention() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        storage
+            .save_story("guild", ":: StoryTitle\nCave\n\n:: Start\nDark\n")
+            .unwrap();
+        let story_id = storage.find_guild_story("guild", "Cave").unwrap().unwrap();
+
+        storage.soft_delete_story(story_id).unwrap();
+        assert!(storage.find_guild_story("guild", "Cave").unwrap().is_none());
+        let deleted = storage.deleted_stories("guild").unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(
+            (deleted[0].id, deleted[0].name.as_str()),
+            (story_id, "Cave")
+        );
+        // Stories deleted after the retention limit stay in the trash.
+        assert_eq!(storage.purge_trash(deleted[0].deleted_at).unwrap(), 0);
+
+        assert_eq!(storage.restore_story("guild", story_id).unwrap(), "Cave");
+        assert_eq!(
+            storage.find_guild_story("guild", "Cave").unwrap(),
+            Some(story_id)
+        );
+        assert!(storage.deleted_stories("guild").unwrap().is_empty());
+        assert!(storage.restore_story("guild", story_id).is_err());
+
+        storage.soft_delete_story(story_id).unwrap();
+        assert_eq!(storage.purge_trash(now() + 1).unwrap(), 1);
+        assert!(storage.deleted_stories("guild").unwrap().is_empty());
+        assert!(storage.restore_story("guild", story_id).is_err());
+        assert!(story_files(&folder).is_empty());
+    }
+
+    #[test]
+    fn purging_a_story_leaves_no_orphan_rows() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let story_id = guild_story(&mut storage, "guild", "Cave");
+        let game_state = guild_session("player", story_id, 1);
+        storage.update_game_state(&game_state).unwrap();
+        storage.record_play(&game_state).unwrap();
+        storage.record_completion(&game_state).unwrap();
+        storage.rate_story("player", "guild", story_id, 4).unwrap();
+        storage.save_slot(&game_state, "slot", 3).unwrap();
+        storage.set_start_passage(story_id, "Start").unwrap();
+        storage
+            .set_series_position("guild", "Saga", story_id, None)
+            .unwrap();
+        let report = BugReport {
+            player_id: "player".to_string(),
+            passage: "Start".to_string(),
+            broken_link: None,
+            description: Some("Too dark".to_string()),
+            created_at: now(),
+        };
+        storage.add_bug_report("guild", story_id, &report).unwrap();
+
+        storage.soft_delete_story(story_id).unwrap();
+        assert_eq!(storage.purge_trash(now() + 1).unwrap(), 1);
+
+        for table in [
+            "story_state",
+            "story_settings",
+            "bug_reports",
+            "events",
+            "ratings",
+            "series_membership",
+            "save_slots",
+        ] {
+            let count: i64 = storage
+                .connection
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {table} WHERE `story_id` = ?1"),
+                    [story_id],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(count, 0, "{table}");
+        }
+    }
+
+    #[test]
+    fn series_stories_follow_their_positions() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let mut story_ids = vec![];
+        for title in ["First", "Second", "Third"] {
+            storage
+                .save_story(
+                    "guild",
+                    &format!(":: StoryTitle\n{title}\n\n:: Start\nHello\n"),
+                )
+                .unwrap();
+            story_ids.push(storage.find_guild_story("guild", title).unwrap().unwrap());
+        }
+        // Positions can leave gaps, only their order matters.
+        storage
+            .set_series_position("guild", "Saga", story_ids[2], Some(30))
+            .unwrap();
+        storage
+            .set_series_position("guild", "Saga", story_ids[0], Some(1))
+            .unwrap();
+        storage
+            .set_series_position("guild", "Saga", story_ids[1], Some(7))
+            .unwrap();
+        let series_id = storage.find_series("guild", "Saga").unwrap().unwrap();
+        let names = |storage: &Storage<PathBuf>| -> Vec<String> {
+            storage
+                .series_stories(series_id)
+                .unwrap()
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect()
+        };
+        assert_eq!(names(&storage), ["First", "Second", "Third"]);
+
+        // Trashed stories leave the series without breaking it.
+        storage.soft_delete_story(story_ids[1]).unwrap();
+        assert_eq!(names(&storage), ["First", "Third"]);
+
+        storage
+            .set_series_position("guild", "Saga", story_ids[0], None)
+            .unwrap();
+        storage
+            .set_series_position("guild", "Saga", story_ids[2], None)
+            .unwrap();
+        storage
+            .set_series_position("guild", "Saga", story_ids[1], None)
+            .unwrap();
+        assert_eq!(storage.find_series("guild", "Saga").unwrap(), None);
+    }
 }
@@ -1,16 +1,37 @@
+use std::{collections::HashMap, fmt::Display, future::Future, str::FromStr, time::Duration};
+
 use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use serenity::{
-    builder::CreateComponents,
+    builder::{CreateComponents, CreateEmbed, CreateInteractionResponseData},
     model::prelude::interaction::{
         application_command::ApplicationCommandInteraction,
         message_component::MessageComponentInteraction, InteractionResponseType,
     },
+    model::prelude::{AttachmentType, EmojiId, GuildId, MessageId, ReactionType},
     prelude::Context,
 };
-use twee_v3::Passage;
+use twee_v3::{Passage, Story};
+use uuid::Uuid;
 
 use crate::{
-    interaction::{text_interaction, update_message_text},
+    clock::Clock,
+    error::{correlation_id, UserError, CORRELATION_ID},
+    interaction::{
+        branded, branding_length, error_interaction, string_option, text_interaction,
+        update_message_text, user_option, Branding, BRANDING, MAX_EMBEDS_LENGTH,
+    },
+    persistance::{now, BugReport, Storage, StoryDetails, StoryProgress},
+    random::Rng,
+    utils::{
+        apply_effects, choices, content_warnings, is_random_passage, is_save_point,
+        passage_timeout, passages_info, path_mismatch, print_variables, recommend, run_macros,
+        sanitize_display, split_emoji, story_ifid, story_warnings, strip_link_macros,
+        truncate_chars, truncate_label, uses_variables, Choice, StoryStats,
+        MAX_BUTTON_LABEL_LENGTH, MAX_OPTION_LABEL_LENGTH,
+    },
     Handler,
 };
 
@@ -18,12 +39,136 @@ pub const START_STORY_MENU: &str = "start_story_menu";
 pub const PICK_NEXT_PASSAGE: &str = "pick_next_passage";
 pub const PICK_NEXT_PASSAGE_BUTTON: &str = "pick_next_passage_button";
 pub const THE_END: &str = "the_end";
+pub const THE_END_IN_PLACE: &str = "the_end_in_place";
+pub const ACKNOWLEDGE_WARNING: &str = "acknowledge_warning";
+pub const PICK_RANDOM_PASSAGE: &str = "pick_random_passage";
+/// Followed by the id of the story, for the buttons of `/recent`.
+pub const PLAY_RECENT_STORY_BUTTON: &str = "play_recent_story_button";
+/// Followed by the ids of the series and of the story, like `2:7`, for the buttons of `/series`.
+pub const PLAY_SERIES_STORY_BUTTON: &str = "play_series_story_button";
+pub const JUMP_TO_PASSAGE_MENU: &str = "jump_to_passage_menu";
+/// Shows the current passage of the session of the player, like `/play` does.
+pub const RESUME_SESSION_BUTTON: &str = "resume_session_button";
+/// Followed by the id of the story and the stars, like `3:5`, for the buttons shown at the end.
+pub const RATE_STORY_BUTTON: &str = "rate_story_button";
+/// Shows the variables of the player, on the passages of stories keeping some.
+pub const SHOW_INVENTORY_BUTTON: &str = "show_inventory_button";
+/// Prefixes the custom ids of the components of `/preview`, so they don't affect any session.
+pub const PREVIEW_PREFIX: &str = "preview:";
+
+/// How deep `(display:)` can include passages including other passages.
+const MAX_INCLUDE_DEPTH: usize = 5;
+
+/// How many stories `/recent` lists, a button for each fitting in a single action row.
+const MAX_RECENT_STORIES: usize = 5;
+
+/// How many passages `/demo` shows at most, so it ends while the interaction can still be edited.
+const MAX_DEMO_PASSAGES: usize = 50;
+
+/// How long `/demo` shows each passage, on guilds without a delay between passages.
+const DEFAULT_DEMO_DELAY: Duration = Duration::from_secs(4);
+
+/// How many sessions `/sessions` lists at most, to fit in a message.
+const MAX_LISTED_SESSIONS: usize = 40;
+
+/// How many saves of each story players can keep, unless `[saves] max_per_story` is set.
+const DEFAULT_MAX_SAVE_SLOTS: usize = 3;
+
+/// The longest name of a save, as allowed by `/save`.
+const MAX_SAVE_NAME_LENGTH: usize = 32;
+
+/// How many variables the inventory lists at most, to fit in a message.
+const MAX_INVENTORY_ITEMS: usize = 40;
+
+/// What the warnings of the whole story are acknowledged as, the StoryData never being shown.
+const STORY_WARNINGS: &str = "StoryData";
+const STORY_UPDATED: &str = "This story was updated; restarting from the beginning.";
+const TIMED_OUT: &str = "Time is up, the choice was made for you.";
+const PREVIEW: &str = "Preview, the choices do nothing.";
+
+/// Discord allows up to 5 buttons per action row, 5 action rows per message, and 25 options
+/// per select menu.
+const MAX_BUTTONS_PER_ROW: usize = 5;
+const MAX_ACTION_ROWS: usize = 5;
+
+/// Discord rejects embeds with a description longer than this, in characters.
+const MAX_EMBED_DESCRIPTION_LENGTH: usize = 4096;
+
+/// How much of a passage too long for a message is shown before its file, see
+/// [`RenderedPassage::attachment`].
+const PASSAGE_EXCERPT_LENGTH: usize = 1000;
+const PASSAGE_IN_FILE: &str = "This passage is too long for a message, read all of it in the file.";
+const MAX_MENU_OPTIONS: usize = 25;
+/// How long the token of an interaction can be used to answer it.
+const INTERACTION_TOKEN_LIFETIME: Duration = Duration::from_secs(15 * 60);
+/// The time left to show the passage after a timeout, before the interaction expires.
+const TIMEOUT_MARGIN: Duration = Duration::from_secs(30);
+
+/// How the links of a passage are presented, chosen per guild.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChoiceLayout {
+    /// A button for a single link, a menu for more.
+    #[default]
+    Auto,
+    /// A grid of buttons, a menu when there are too many links to fit.
+    Buttons,
+    /// A menu, whatever the number of links.
+    Menu,
+}
+
+impl FromStr for ChoiceLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(ChoiceLayout::Auto),
+            "buttons" => Ok(ChoiceLayout::Buttons),
+            "menu" => Ok(ChoiceLayout::Menu),
+            other => Err(anyhow!("Unknown choice layout {other}")),
+        }
+    }
+}
+
+impl Display for ChoiceLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ChoiceLayout::Auto => "auto",
+            ChoiceLayout::Buttons => "buttons",
+            ChoiceLayout::Menu => "menu",
+        };
+        f.write_str(name)
+    }
+}
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GameState {
     pub player_id: String,
     pub guild_id: String,
     pub story_id: i64,
     pub current_chapter: String,
+    /// The story variables of the player, set by `(set:)` and printed by `(print: $variable)`.
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    /// The passages visited before the current one, oldest first. Saved along the rest of the
+    /// session in its blob, so it survives restarts like the current passage does.
+    #[serde(default)]
+    pub history: Vec<String>,
+    /// When the story was modified, as known by the session, to reconcile it with updates.
+    #[serde(default)]
+    pub story_version: Option<i64>,
+    /// Whether every passage replaces the previous one in the same message, instead of being
+    /// sent in a new one. Chosen by the guild when the session starts.
+    #[serde(default)]
+    pub single_message: bool,
+    /// The target of the last link chosen that leads to a missing passage, for `/reportbug`.
+    #[serde(default)]
+    pub broken_link: Option<String>,
+    /// How many times the player moved, to tell whether they moved since a passage was shown.
+    #[serde(default)]
+    pub turn: u64,
+    /// Tells the session apart from the previous sessions of the player, whose turns it repeats.
+    #[serde(default)]
+    pub session_id: String,
 }
 
 impl GameState {
@@ -38,40 +183,94 @@ impl GameState {
             guild_id,
             story_id,
             current_chapter,
+            variables: HashMap::new(),
+            history: vec![],
+            story_version: None,
+            single_message: false,
+            broken_link: None,
+            turn: 0,
+            session_id: Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Move to the chapter, remembering the current one in the history.
+    pub fn move_to(&mut self, chapter: &str) {
+        self.turn += 1;
+        if self.current_chapter != chapter {
+            let previous = std::mem::replace(&mut self.current_chapter, chapter.to_string());
+            self.history.push(previous);
         }
     }
 }
 
-pub async fn stop_story_interaction(
+/// What is needed to continue a session in another guild, or for another player.
+#[derive(Serialize, Deserialize)]
+struct ShareCode {
+    ifid: String,
+    passage: String,
+    #[serde(default)]
+    variables: HashMap<String, String>,
+    #[serde(default)]
+    history: Vec<String>,
+}
+
+impl ShareCode {
+    fn encode(&self) -> Result<String> {
+        Ok(STANDARD_NO_PAD.encode(serde_json::to_string(self)?))
+    }
+
+    fn decode(code: &str) -> Result<Self, UserError> {
+        let json = STANDARD_NO_PAD
+            .decode(code.trim().trim_end_matches('='))
+            .map_err(|_| UserError::InvalidShareCode)?;
+        serde_json::from_slice(&json).map_err(|_| UserError::InvalidShareCode)
+    }
+}
+
+pub async fn export_session_interaction(
     handler: &Handler,
     ctx: &Context,
     command: &ApplicationCommandInteraction,
 ) {
-    if stop_story_interaction_inner(handler, ctx, command)
-        .await
-        .is_err()
-    {
-        println!("Error!");
-        text_interaction("Error while playing the story", ctx, command).await;
+    if let Err(error) = export_session_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
     }
 }
 
-async fn stop_story_interaction_inner(
+async fn export_session_interaction_inner(
     handler: &Handler,
     ctx: &Context,
     command: &ApplicationCommandInteraction,
 ) -> Result<()> {
-    let storage = handler.storage.lock().await;
     let player_id = command.user.id.to_string();
-    let guild_id = command
-        .guild_id
-        .ok_or_else(|| anyhow!("No guild id"))?
-        .to_string();
-    storage.clear_game_state(&player_id, &guild_id)?;
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+
+    let mut storage = handler.storage.lock().await;
+    let game_state = storage
+        .retrieve_game_state(&player_id, &guild_id)
+        .map_err(no_session)?;
+    let content = storage.get_story_content(game_state.story_id)?;
     drop(storage);
 
+    let ifid = story_ifid(&content).ok_or(UserError::StoryNotShareable)?;
+    let code = ShareCode {
+        ifid,
+        passage: game_state.current_chapter,
+        variables: game_state.variables,
+        history: game_state.history,
+    }
+    .encode()?;
+
     text_interaction(
-        "Current story stopped, start again with the `/play` command",
+        format!("Continue this story with `/importsession`, using the code:\n```{code}```"),
         ctx,
         command,
     )
@@ -80,121 +279,407 @@ async fn stop_story_interaction_inner(
     Ok(())
 }
 
-pub async fn play_story_interaction(
+pub async fn import_session_interaction(
     handler: &Handler,
     ctx: &Context,
     command: &ApplicationCommandInteraction,
 ) {
-    if play_story_interaction_inner(handler, ctx, command)
-        .await
-        .is_err()
-    {
-        println!("Error!");
-        text_interaction("Error while playing the story", ctx, command).await;
+    if let Err(error) = import_session_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
     }
 }
 
-async fn play_story_interaction_inner(
+async fn import_session_interaction_inner(
     handler: &Handler,
     ctx: &Context,
     command: &ApplicationCommandInteraction,
 ) -> Result<()> {
-    let database = handler.storage.lock().await;
     let player_id = command.user.id.to_string();
-    let guild_id = command
-        .guild_id
-        .ok_or_else(|| anyhow!("No guild id"))?
-        .to_string();
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let code = string_option(command, "code").ok_or(UserError::InvalidShareCode)?;
+    let share_code = ShareCode::decode(code)?;
 
-    let game_state_result = database.retrieve_game_state(&player_id, &guild_id);
-    drop(database);
+    let mut storage = handler.storage.lock().await;
+    let story_id = storage
+        .find_story_by_ifid(&guild_id, &share_code.ifid.to_uppercase())?
+        .ok_or(UserError::StoryNotInGuild)?;
+    let story = storage.get_story(story_id)?;
+    if story.get_passage(&share_code.passage).is_none() {
+        // The code comes from another version of the story.
+        return Err(UserError::InvalidShareCode.into());
+    }
 
-    match game_state_result {
-        Ok(game_state) => continue_game(&game_state, handler, ctx, command).await?,
-        Err(_) => start_new_game(handler, ctx, command).await?,
+    let game_state = GameState {
+        variables: share_code.variables,
+        history: share_code.history,
+        story_version: storage.story_modified_at(story_id)?,
+        single_message: storage.get_guild_single_message(&guild_id)?,
+        ..GameState::new(player_id, guild_id, story_id, share_code.passage)
+    };
+    start_new_session(&mut storage, &game_state, max_sessions(handler))?;
+    drop(storage);
+
+    continue_game(game_state, handler, ctx, command, None).await
+}
+
+pub async fn save_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = save_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
     }
+}
+
+async fn save_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let player_id = command.user.id.to_string();
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let name = save_name(command)?;
+    let max_slots = handler
+        .config()
+        .get_parsed_path(&["saves", "max_per_story"])
+        .unwrap_or(DEFAULT_MAX_SAVE_SLOTS)
+        .max(1);
+
+    let mut storage = handler.storage.lock().await;
+    let game_state = storage
+        .retrieve_game_state(&player_id, &guild_id)
+        .map_err(no_session)?;
+    let replaced = storage.save_slot(&game_state, &name, max_slots)?;
+    drop(storage);
+
+    let text = if replaced {
+        format!("Replaced your save `{name}`, load it with `/load {name}`.")
+    } else {
+        format!("Saved as `{name}`, load it with `/load {name}`.")
+    };
+    text_interaction(text, ctx, command).await;
 
     Ok(())
 }
 
-async fn continue_game(
-    game_state: &GameState,
+/// The name of the save, shown as given.
+fn save_name(command: &ApplicationCommandInteraction) -> Result<String, UserError> {
+    let name = string_option(command, "name").unwrap_or_default();
+    let name = sanitize_display(name, MAX_SAVE_NAME_LENGTH);
+    if name.is_empty() {
+        return Err(UserError::InvalidSaveName);
+    }
+    Ok(name)
+}
+
+pub async fn load_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = load_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn load_interaction_inner(
     handler: &Handler,
     ctx: &Context,
     command: &ApplicationCommandInteraction,
 ) -> Result<()> {
-    println!("Continuing game");
+    let player_id = command.user.id.to_string();
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let name = save_name(command)?;
 
-    let mut database = handler.storage.lock().await;
-    let story = database.get_story(game_state.story_id)?;
-    drop(database);
+    let mut storage = handler.storage.lock().await;
+    let current = storage.retrieve_game_state(&player_id, &guild_id).ok();
+    let story_id = match string_option(command, "story") {
+        Some(story) => Some(
+            storage
+                .find_guild_story(&guild_id, story)?
+                .ok_or(UserError::UnknownStory)?,
+        ),
+        None => current.as_ref().map(|current| current.story_id),
+    };
+    let saved = storage
+        .load_slot(&player_id, &guild_id, story_id, &name)?
+        .ok_or_else(|| UserError::UnknownSave(name.clone()))?;
 
-    let passage = story
-        .get_passage(&game_state.current_chapter)
-        .ok_or_else(|| anyhow!("Couldn't retrieve passage"))?;
+    let game_state = GameState {
+        single_message: storage.get_guild_single_message(&guild_id)?,
+        // A timeout scheduled on the current passage must not move the loaded session.
+        turn: current.map_or(0, |current| current.turn).max(saved.turn) + 1,
+        ..saved
+    };
+    start_new_session(&mut storage, &game_state, max_sessions(handler))?;
+    drop(storage);
 
-    let mut passage_content = String::new();
-    for node in passage.nodes() {
-        match node {
-            twee_v3::ContentNode::Text(text) => passage_content.push_str(text),
-            twee_v3::ContentNode::Link { text, target: _ } => {
-                passage_content.push_str(&format!("`{text}`"))
-            }
-        };
+    let footer = format!("Loaded your save {name}.");
+    continue_game(game_state, handler, ctx, command, Some(&footer)).await
+}
+
+pub async fn saves_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = saves_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
     }
+}
 
-    command
-        .create_interaction_response(&ctx.http, |response| {
-            response
-                .kind(InteractionResponseType::ChannelMessageWithSource)
-                .interaction_response_data(|message| {
-                    message
-                        .embed(|embed| embed.title(passage.title()).description(passage_content))
-                        .components(|components| add_story_components(components, &passage))
-                        .ephemeral(true)
+async fn saves_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let player_id = command.user.id.to_string();
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+
+    let slots = handler
+        .storage
+        .lock()
+        .await
+        .save_slots(&player_id, &guild_id)?;
+    let text = if slots.is_empty() {
+        "You have no saves on this server, save the story you are playing with `/save`.".to_string()
+    } else {
+        slots
+            .iter()
+            .map(|slot| {
+                format!(
+                    "`{}` of `{}`, on `{}`, <t:{}:R>",
+                    slot.name, slot.story_name, slot.passage, slot.saved_at
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    text_interaction(text, ctx, command).await;
+
+    Ok(())
+}
+
+pub async fn goto_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = goto_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn goto_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let player_id = command.user.id.to_string();
+    let name = string_option(command, "story").ok_or(UserError::UnknownStory)?;
+    let passage = string_option(command, "passage").unwrap_or_default().trim();
+
+    let game_state = {
+        let mut storage = handler.storage.lock().await;
+        go_to_passage(
+            &mut storage,
+            player_id,
+            guild_id,
+            name,
+            passage,
+            max_sessions(handler),
+        )?
+    };
+
+    continue_game(game_state, handler, ctx, command, None).await
+}
+
+/// Start a session of the story at the passage, for `/goto`.
+fn go_to_passage(
+    storage: &mut Storage<String>,
+    player_id: String,
+    guild_id: String,
+    name: &str,
+    passage: &str,
+    max_sessions: usize,
+) -> Result<GameState> {
+    let story_id = storage
+        .find_guild_story(&guild_id, name)?
+        .ok_or(UserError::UnknownStory)?;
+    let story = storage.get_story(story_id)?;
+    if story.get_passage(passage).is_none() {
+        return Err(UserError::UnknownPassage(passage.to_string()).into());
+    }
+    // Replaces the session of the administrator, if any. It isn't counted as a play, to keep
+    // testing out of the analytics.
+    let game_state = new_game_state(
+        storage,
+        &story,
+        player_id,
+        guild_id,
+        story_id,
+        passage.to_string(),
+    )?;
+    start_new_session(storage, &game_state, max_sessions)?;
+    Ok(game_state)
+}
+
+pub async fn demo_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = demo_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+/// Show the passages of the path recorded with `/setpath` one after the other, in the same
+/// message, outside of any session.
+async fn demo_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let name = string_option(command, "story").ok_or(UserError::UnknownStory)?;
+
+    let mut storage = handler.storage.lock().await;
+    let story_id = storage
+        .find_guild_story(&guild_id, name)?
+        .ok_or(UserError::UnknownStory)?;
+    let path = storage
+        .get_canonical_path(story_id)?
+        .filter(|path| !path.is_empty())
+        .ok_or(UserError::NoCanonicalPath)?;
+    let story = storage.get_story(story_id)?;
+    if let Some(reason) = path_mismatch(&story, &path) {
+        return Err(UserError::PathMismatch(reason).into());
+    }
+    let delay = Some(storage.get_guild_delay(&guild_id)?)
+        .filter(|delay| !delay.is_zero())
+        .unwrap_or(DEFAULT_DEMO_DELAY);
+    drop(storage);
+
+    // The variables set along the path show in the passages, as they would for a player.
+    let mut game_state = GameState::new(String::new(), guild_id, story_id, path[0].clone());
+    let shown = path.len().min(MAX_DEMO_PASSAGES);
+    for (index, title) in path[..shown].iter().enumerate() {
+        let passage = story
+            .get_passage(title)
+            .ok_or_else(|| UserError::UnknownPassage(title.clone()))?;
+        if index > 0 {
+            game_state.move_to(title);
+        }
+        run_passage_macros(&story, &passage, &mut game_state);
+
+        // Passages go by too fast for files, so the demo only shows the start of the long ones.
+        let content = passage_content(&story, &passage, &game_state);
+        let mut embed = CreateEmbed::default();
+        branded(&mut embed);
+        embed
+            .title(passage.title())
+            .description(truncate_label(&content, MAX_EMBED_DESCRIPTION_LENGTH))
+            .footer(|footer| footer.text(format!("Demo, passage {} of {shown}", index + 1)));
+        if index == 0 {
+            command
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|message| {
+                            message.set_embed(embed).ephemeral(true)
+                        })
                 })
-        })
-        .await?;
+                .await?;
+        } else {
+            tokio::time::sleep(delay).await;
+            command
+                .edit_original_interaction_response(&ctx.http, |response| response.set_embed(embed))
+                .await?;
+        }
+    }
 
     Ok(())
 }
 
-async fn start_new_game(
+pub async fn jump_to_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = jump_to_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+/// Move the player to the passage matching the query, or let them pick one when several match.
+async fn jump_to_interaction_inner(
     handler: &Handler,
     ctx: &Context,
     command: &ApplicationCommandInteraction,
 ) -> Result<()> {
-    let guild_id = command
-        .guild_id
-        .ok_or_else(|| anyhow!("No guild id"))?
-        .to_string();
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let player_id = command.user.id.to_string();
+    let query = string_option(command, "query").unwrap_or_default().trim();
 
-    println!("Starting new game");
-    let storage = handler.storage.lock().await;
-    let stories = storage.list_guild_stories(&guild_id)?;
+    let mut storage = handler.storage.lock().await;
+    let mut game_state = storage
+        .retrieve_game_state(&player_id, &guild_id)
+        .map_err(no_session)?;
+    if !storage.get_free_navigation(game_state.story_id)? {
+        return Err(UserError::NoFreeNavigation.into());
+    }
+    let content = storage.get_story_content(game_state.story_id)?;
+    let matches = matching_passages(&content, query);
 
-    if stories.is_empty() {
-        println!("There are no stories");
-        text_interaction("There are no stories", ctx, command).await;
-        println!("Returning");
-        return Ok(());
+    if let [(_, title)] = matches.as_slice() {
+        let story = storage.get_story(game_state.story_id)?;
+        let passage = story
+            .get_passage(title)
+            .ok_or_else(|| UserError::UnknownPassage(title.clone()))?;
+        game_state.move_to(title);
+        run_passage_macros(&story, &passage, &mut game_state);
+        storage.record_visit(&game_state)?;
+        save_progress(&mut storage, &game_state)?;
+        drop(storage);
+
+        return continue_game(game_state, handler, ctx, command, None).await;
+    }
+    drop(storage);
+    if matches.is_empty() {
+        return Err(UserError::NoMatchingPassage(query.to_string()).into());
     }
-    let text = "Please select a story to start playing";
 
+    let text = if matches.len() > MAX_MENU_OPTIONS {
+        format!(
+            "{} passages match, here are the first {MAX_MENU_OPTIONS}",
+            matches.len()
+        )
+    } else {
+        "Pick the passage to jump to".to_string()
+    };
     command
         .create_interaction_response(&ctx.http, |response| {
             response
                 .kind(InteractionResponseType::ChannelMessageWithSource)
                 .interaction_response_data(|message| {
                     message
-                        .embed(|embed| embed.title("Let's go").description(text))
+                        .embed(|embed| branded(embed).title("Jump to").description(text))
                         .components(|components| {
                             components.create_action_row(|row| {
                                 row.create_select_menu(|menu| {
-                                    menu.custom_id(START_STORY_MENU).options(|mut options| {
-                                        for (story_id, story_name) in stories {
+                                    menu.custom_id(JUMP_TO_PASSAGE_MENU).options(|mut options| {
+                                        for (index, title) in matches.iter().take(MAX_MENU_OPTIONS)
+                                        {
                                             options = options.create_option(|create_option| {
-                                                create_option.label(story_name).value(story_id)
+                                                create_option
+                                                    .label(truncate_label(
+                                                        title,
+                                                        MAX_OPTION_LABEL_LENGTH,
+                                                    ))
+                                                    .value(index)
                                             });
                                         }
                                         options
@@ -210,160 +695,458 @@ async fn start_new_game(
     Ok(())
 }
 
-pub async fn actual_start(
+/// Jump to the passage picked in the menu of `/jumpto`.
+pub async fn jump_to_passage(
     handler: &Handler,
     ctx: &Context,
     message_component: &MessageComponentInteraction,
 ) -> Result<()> {
-    let story_id = message_component
+    let index = message_component
         .data
         .values
         .first()
-        .ok_or_else(|| anyhow!("No id selected"))
-        .and_then(|id| id.parse::<i64>().map_err(Into::into))?;
-
+        .and_then(|index| index.parse::<usize>().ok())
+        .ok_or(UserError::InvalidSelection)?;
+    let player_id = message_component.user.id.to_string();
     let guild_id = message_component
         .guild_id
-        .ok_or_else(|| anyhow!("No guild id"))?
+        .ok_or(UserError::NoGuild)?
         .to_string();
 
     let mut storage = handler.storage.lock().await;
-    let story = storage.get_story(story_id)?;
-    drop(storage);
-
-    let start = story
-        .start()
-        .ok_or_else(|| anyhow!("Story without start"))?;
-    let player_id = message_component.user.id.to_string();
-    let game_state = GameState::new(player_id, guild_id, story_id, start.title().to_string());
-    {
-        let storage = handler.storage.lock().await;
-        storage.update_game_state(&game_state)?;
+    let game_state = storage
+        .retrieve_game_state(&player_id, &guild_id)
+        .map_err(no_session)?;
+    if !storage.get_free_navigation(game_state.story_id)? {
+        return Err(UserError::NoFreeNavigation.into());
     }
+    let content = storage.get_story_content(game_state.story_id)?;
+    drop(storage);
+    let title = passages_info(&content)
+        .into_iter()
+        .nth(index)
+        .ok_or(UserError::InvalidSelection)?
+        .title;
 
-    update_message_text(
-        "Let's go",
-        format!(
-            "Your story `{story_name}` is starting!",
-            story_name = story.title().unwrap()
-        ),
-        ctx,
-        message_component,
-    )
-    .await?;
-
-    let passage = story
-        .get_passage(&game_state.current_chapter)
-        .ok_or_else(|| anyhow!("Couldn't retrieve passage"))?;
+    next_chapter(handler, ctx, message_component, &title).await
+}
 
-    let mut passage_content = String::new();
-    for node in passage.nodes() {
-        match node {
-            twee_v3::ContentNode::Text(text) => passage_content.push_str(text),
-            twee_v3::ContentNode::Link { text, target: _ } => {
-                passage_content.push_str(&format!("`{text}`"))
-            }
-        };
+/// The passages whose title contains the query, ignoring case, with their index in
+/// [`passages_info`] as menus refer to them by index. A title equal to the query is the only
+/// match.
+fn matching_passages(content: &str, query: &str) -> Vec<(usize, String)> {
+    let query = query.to_lowercase();
+    let matches: Vec<(usize, String)> = passages_info(content)
+        .into_iter()
+        .map(|info| info.title)
+        .enumerate()
+        .filter(|(_, title)| title.to_lowercase().contains(&query))
+        .collect();
+    match matches
+        .iter()
+        .position(|(_, title)| title.to_lowercase() == query)
+    {
+        Some(exact) => vec![matches[exact].clone()],
+        None => matches,
     }
-
-    message_component
-        .create_followup_message(&ctx.http, |message| {
-            message
-                .embed(|embed| embed.title(passage.title()).description(passage_content))
-                .components(|components| add_story_components(components, &passage))
-                .ephemeral(true)
-        })
-        .await?;
-
-    Ok(())
 }
 
-pub async fn next_chapter_from_menu(
+pub async fn stop_story_interaction(
     handler: &Handler,
     ctx: &Context,
-    message_component: &MessageComponentInteraction,
-) -> Result<()> {
-    let chapter_name = message_component
-        .data
-        .values
-        .first()
-        .ok_or_else(|| anyhow!("No chapter selected"))?;
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = stop_story_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn stop_story_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let mut storage = handler.storage.lock().await;
+    let player_id = command.user.id.to_string();
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    if let Ok(game_state) = storage.retrieve_game_state(&player_id, &guild_id) {
+        storage.record_stop(&game_state, false)?;
+    }
+    storage.clear_game_state(&player_id, &guild_id)?;
+    drop(storage);
+
+    text_interaction(
+        "Current story stopped, start again with the `/play` command",
+        ctx,
+        command,
+    )
+    .await;
 
-    next_chapter(handler, ctx, message_component, chapter_name).await
+    Ok(())
 }
 
-pub async fn next_chapter_from_button(
+pub async fn clear_session_interaction(
     handler: &Handler,
     ctx: &Context,
-    message_component: &MessageComponentInteraction,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = clear_session_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn clear_session_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
 ) -> Result<()> {
-    let chapter_name = &message_component.data.custom_id[PICK_NEXT_PASSAGE_BUTTON.len()..];
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let player_id = user_option(command, "player")
+        .ok_or(UserError::NoPlayerSession)?
+        .to_string();
+
+    let mut storage = handler.storage.lock().await;
+    let game_state =
+        storage
+            .retrieve_game_state(&player_id, &guild_id)
+            .map_err(|error| match error.downcast_ref::<rusqlite::Error>() {
+                Some(rusqlite::Error::QueryReturnedNoRows) => UserError::NoPlayerSession.into(),
+                _ => error,
+            })?;
+    storage.record_stop(&game_state, true)?;
+    storage.clear_game_state(&player_id, &guild_id)?;
+    drop(storage);
+
+    text_interaction(
+        format!("The session of <@{player_id}> was cleared"),
+        ctx,
+        command,
+    )
+    .await;
+
+    Ok(())
+}
 
-    next_chapter(handler, ctx, message_component, chapter_name).await
+pub async fn sessions_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = sessions_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
 }
 
-pub async fn next_chapter(
+async fn sessions_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let sessions = handler
+        .storage
+        .lock()
+        .await
+        .list_guild_sessions(&guild_id)?;
+
+    let text = if sessions.is_empty() {
+        "Nobody is playing a story on this server.".to_string()
+    } else {
+        let mut lines: Vec<String> = sessions
+            .iter()
+            .take(MAX_LISTED_SESSIONS)
+            .map(|session| {
+                format!(
+                    "<@{}> in `{}`, on `{}`",
+                    session.player_id, session.story_name, session.passage
+                )
+            })
+            .collect();
+        if sessions.len() > MAX_LISTED_SESSIONS {
+            lines.push(format!(
+                "And {} more.",
+                sessions.len() - MAX_LISTED_SESSIONS
+            ));
+        }
+        lines.join("\n")
+    };
+
+    text_interaction(text, ctx, command).await;
+
+    Ok(())
+}
+
+pub async fn recent_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = recent_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn recent_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let player_id = command.user.id.to_string();
+    let mut storage = handler.storage.lock().await;
+    let stories = storage.recent_stories(&player_id, &guild_id, MAX_RECENT_STORIES)?;
+    let current_story = storage
+        .retrieve_game_state(&player_id, &guild_id)
+        .ok()
+        .map(|game_state| game_state.story_id);
+    drop(storage);
+
+    if stories.is_empty() {
+        text_interaction(
+            "You didn't play any story here yet, start one with the `/play` command.",
+            ctx,
+            command,
+        )
+        .await;
+        return Ok(());
+    }
+
+    let description = stories
+        .iter()
+        .map(|story| format!("`{}`, <t:{}:R>", story.name, story.played_at))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let buttons: Vec<(String, String)> = stories
+        .iter()
+        .map(|story| {
+            let action = match current_story == Some(story.story_id) {
+                true => "Resume",
+                false => "Replay",
+            };
+            (
+                format!("{PLAY_RECENT_STORY_BUTTON}{}", story.story_id),
+                format!("{action} {}", story.name),
+            )
+        })
+        .collect();
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .embed(|embed| {
+                            branded(embed)
+                                .title("Recently played")
+                                .description(description)
+                        })
+                        .components(|components| add_button_rows(components, &buttons, &[]))
+                        .ephemeral(true)
+                })
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Resume the story of the button of `/recent` if it's the one being played, or start it again.
+pub async fn play_recent_story(
     handler: &Handler,
     ctx: &Context,
     message_component: &MessageComponentInteraction,
-    chapter_name: &str,
 ) -> Result<()> {
-    let mut database = handler.storage.lock().await;
+    let story_id = message_component
+        .data
+        .custom_id
+        .strip_prefix(PLAY_RECENT_STORY_BUTTON)
+        .and_then(|id| id.parse::<i64>().ok())
+        .ok_or(UserError::InvalidSelection)?;
+
     let player_id = message_component.user.id.to_string();
     let guild_id = message_component
         .guild_id
-        .ok_or_else(|| anyhow!("No guild id"))?
+        .ok_or(UserError::NoGuild)?
         .to_string();
+    let game_state = handler
+        .storage
+        .lock()
+        .await
+        .retrieve_game_state(&player_id, &guild_id);
 
-    let game_state = database.retrieve_game_state(&player_id, &guild_id)?;
-    let story = database.get_story(game_state.story_id)?;
-    drop(database);
+    match game_state {
+        Ok(game_state) if game_state.story_id == story_id => {
+            continue_game_from_component(game_state, handler, ctx, message_component).await
+        }
+        _ => start_story(handler, ctx, message_component, story_id).await,
+    }
+}
 
-    // Update the previous interaction to remove the menu.
-    message_component.defer(&ctx.http).await?;
-    message_component
-        .edit_original_interaction_response(&ctx.http, |response| response.components(|c| c))
-        .await?;
+pub async fn series_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = series_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
 
-    let passage = story
-        .get_passage(chapter_name)
-        .ok_or_else(|| anyhow!("Couldn't retrieve passage"))?;
+async fn series_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let player_id = command.user.id.to_string();
 
-    let mut passage_content = String::new();
-    for node in passage.nodes() {
-        match node {
-            twee_v3::ContentNode::Text(text) => passage_content.push_str(text),
-            twee_v3::ContentNode::Link { text, target: _ } => {
-                passage_content.push_str(&format!("`{text}`"))
-            }
+    let Some(name) = string_option(command, "name") else {
+        let series = handler.storage.lock().await.list_series(&guild_id)?;
+        let text = if series.is_empty() {
+            "There are no series on this server yet.".to_string()
+        } else {
+            series
+                .iter()
+                .map(|series| format!("`{}`, {} stories", series.name, series.story_count))
+                .collect::<Vec<_>>()
+                .join("\n")
         };
-    }
+        text_interaction(text, ctx, command).await;
+        return Ok(());
+    };
 
-    message_component
-        .create_followup_message(&ctx.http, |followup| {
-            followup
-                .allowed_mentions(|mentions| mentions.replied_user(true))
-                .embed(|embed| embed.title(passage.title()).description(passage_content))
-                .components(|components| add_story_components(components, &passage))
-                .ephemeral(true)
+    let storage = handler.storage.lock().await;
+    let series_id = storage
+        .find_series(&guild_id, name)?
+        .ok_or(UserError::UnknownSeries)?;
+    let stories = storage.series_stories(series_id)?;
+    let story_ids: Vec<i64> = stories.iter().map(|(story_id, _)| *story_id).collect();
+    let progress = storage.story_progress(&player_id, &guild_id, &story_ids)?;
+    drop(storage);
+
+    let unlocked = unlocked_series_stories(&progress);
+    let description = stories
+        .iter()
+        .zip(&progress)
+        .zip(&unlocked)
+        .map(|(((_, story_name), progress), unlocked)| {
+            let status = if progress.completed {
+                "✅"
+            } else if *unlocked {
+                "▶️"
+            } else {
+                "🔒"
+            };
+            format!("{status} `{story_name}`")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let buttons: Vec<(String, String)> = stories
+        .iter()
+        .zip(&unlocked)
+        .filter(|(_, unlocked)| **unlocked)
+        .map(|((story_id, story_name), _)| {
+            (
+                format!("{PLAY_SERIES_STORY_BUTTON}{series_id}:{story_id}"),
+                format!("Play {story_name}"),
+            )
+        })
+        .collect();
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .embed(|embed| branded(embed).title(name).description(description))
+                        .components(|components| add_button_rows(components, &buttons, &[]))
+                        .ephemeral(true)
+                })
         })
         .await?;
 
-    let database = handler.storage.lock().await;
+    Ok(())
+}
 
-    if passage.links().count() > 0 {
-        database.update_game_state(&GameState {
-            current_chapter: chapter_name.to_string(),
-            ..game_state
-        })?;
-    } else {
-        database.clear_game_state(&player_id, &guild_id)?;
+/// Which stories of a series the player can play: the first one, the ones following a finished
+/// story, and the ones they already started, like before the series was put together.
+fn unlocked_series_stories(progress: &[StoryProgress]) -> Vec<bool> {
+    progress
+        .iter()
+        .enumerate()
+        .map(|(index, story)| index == 0 || story.played || progress[index - 1].completed)
+        .collect()
+}
+
+/// Play the story of the button of `/series`, checking it's still unlocked, resuming it if it's
+/// the one being played.
+pub async fn play_series_story(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let (series_id, story_id) = message_component
+        .data
+        .custom_id
+        .strip_prefix(PLAY_SERIES_STORY_BUTTON)
+        .and_then(|ids| ids.split_once(':'))
+        .and_then(|(series_id, story_id)| {
+            Some((
+                series_id.parse::<i64>().ok()?,
+                story_id.parse::<i64>().ok()?,
+            ))
+        })
+        .ok_or(UserError::InvalidSelection)?;
+
+    let player_id = message_component.user.id.to_string();
+    let guild_id = message_component
+        .guild_id
+        .ok_or(UserError::NoGuild)?
+        .to_string();
+
+    let mut storage = handler.storage.lock().await;
+    let stories = storage.series_stories(series_id)?;
+    let story_ids: Vec<i64> = stories.iter().map(|(story_id, _)| *story_id).collect();
+    let progress = storage.story_progress(&player_id, &guild_id, &story_ids)?;
+    let game_state = storage.retrieve_game_state(&player_id, &guild_id);
+    drop(storage);
+
+    let index = story_ids
+        .iter()
+        .position(|id| *id == story_id)
+        .ok_or(UserError::InvalidSelection)?;
+    if !unlocked_series_stories(&progress)[index] {
+        let (_, previous) = &stories[index - 1];
+        return Err(UserError::SeriesStoryLocked(previous.clone()).into());
     }
 
-    Ok(())
+    match game_state {
+        Ok(game_state) if game_state.story_id == story_id => {
+            continue_game_from_component(game_state, handler, ctx, message_component).await
+        }
+        _ => start_story(handler, ctx, message_component, story_id).await,
+    }
 }
 
-pub async fn the_end(
+/// A button resuming the session of the player, handled by [`resume_session`].
+pub fn add_resume_button<'a>(
+    components: &'a mut CreateComponents,
+    story_name: &str,
+) -> &'a mut CreateComponents {
+    add_button_rows(
+        components,
+        &[(
+            RESUME_SESSION_BUTTON.to_string(),
+            format!("Resume {story_name}"),
+        )],
+        &[],
+    )
+}
+
+/// Show the current passage of the session of the player again. The session may have ended
+/// since the button was shown.
+pub async fn resume_session(
     handler: &Handler,
     ctx: &Context,
     message_component: &MessageComponentInteraction,
@@ -371,64 +1154,2673 @@ pub async fn the_end(
     let player_id = message_component.user.id.to_string();
     let guild_id = message_component
         .guild_id
-        .ok_or_else(|| anyhow!("No guild id"))?
+        .ok_or(UserError::NoGuild)?
         .to_string();
+    let game_state = handler
+        .storage
+        .lock()
+        .await
+        .retrieve_game_state(&player_id, &guild_id)
+        .map_err(no_session)?;
 
-    {
-        let database = handler.storage.lock().await;
-        database.clear_game_state(&player_id, &guild_id)?;
-    }
+    continue_game_from_component(game_state, handler, ctx, message_component).await
+}
 
-    message_component.defer(&ctx.http).await?;
-    message_component
-        .edit_original_interaction_response(&ctx.http, |response| response.components(|c| c))
-        .await?;
+/// Show the variables of the player to them alone, leaving the passage as it is.
+pub async fn show_inventory(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let player_id = message_component.user.id.to_string();
+    let guild_id = message_component
+        .guild_id
+        .ok_or(UserError::NoGuild)?
+        .to_string();
+    let game_state = handler
+        .storage
+        .lock()
+        .await
+        .retrieve_game_state(&player_id, &guild_id)
+        .map_err(no_session)?;
 
+    let description = inventory_description(&game_state.variables);
     message_component
-        .create_followup_message(&ctx.http, |followup| {
-            followup
-                .allowed_mentions(|mentions| mentions.replied_user(true))
-                .embed(|embed| {
-                    embed.title("The end").description(
-                        "That's it for now! To start a new session, use the `/play` command.",
-                    )
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| {
+                    data.embed(|embed| branded(embed).title("Inventory").description(description))
+                        .ephemeral(true)
                 })
-                .ephemeral(true)
         })
         .await?;
+    Ok(())
+}
+
+/// The variables as a list sorted by name. The ones set to `true`, like items the player holds,
+/// show by their name alone, and the ones set to `false` or nothing are left out.
+fn inventory_description(variables: &HashMap<String, String>) -> String {
+    let mut items: Vec<(&String, &String)> = variables
+        .iter()
+        .filter(|(_, value)| !["", "false"].contains(&value.as_str()))
+        .collect();
+    if items.is_empty() {
+        return "Your inventory is empty.".to_string();
+    }
+    items.sort();
+
+    let mut lines: Vec<String> = items
+        .iter()
+        .take(MAX_INVENTORY_ITEMS)
+        .map(|(name, value)| match value.as_str() {
+            "true" => format!("• **{name}**"),
+            _ => format!("• **{name}**: {value}"),
+        })
+        .collect();
+    if items.len() > MAX_INVENTORY_ITEMS {
+        lines.push(format!("And {} more.", items.len() - MAX_INVENTORY_ITEMS));
+    }
+    lines.join("\n")
+}
+
+pub async fn report_bug_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = report_bug_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn report_bug_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let player_id = command.user.id.to_string();
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let description = string_option(command, "description")
+        .map(str::trim)
+        .filter(|description| !description.is_empty())
+        .map(ToString::to_string);
+
+    let mut storage = handler.storage.lock().await;
+    let mut game_state = storage
+        .retrieve_game_state(&player_id, &guild_id)
+        .map_err(no_session)?;
+    let report = BugReport {
+        player_id,
+        passage: game_state.current_chapter.clone(),
+        broken_link: game_state.broken_link.take(),
+        description,
+        created_at: now(),
+    };
+    storage.add_bug_report(&guild_id, game_state.story_id, &report)?;
+    storage.update_game_state(&game_state)?;
+    drop(storage);
+
+    let text = match &report.broken_link {
+        Some(broken_link) => format!(
+            "Thanks! The authors will know that `{}` links to the missing passage `{broken_link}`.",
+            report.passage
+        ),
+        None => format!(
+            "Thanks! Your report about `{}` was sent to the authors.",
+            report.passage
+        ),
+    };
+    text_interaction(text, ctx, command).await;
 
     Ok(())
 }
 
-fn add_story_components<'a, 'b>(
-    components: &'a mut CreateComponents,
-    passage: &'b Passage<&'b str>,
-) -> &'a mut CreateComponents {
-    match passage.links().count() {
-        0 => components.create_action_row(|row| {
-            row.create_button(|create_button| create_button.custom_id(THE_END).label("The end"))
-        }),
-        1 => components.create_action_row(|row| {
-            let link = passage.links().next().expect("one link");
-            row.create_button(|create_button| {
-                create_button
-                    .custom_id(format!("{}{}", PICK_NEXT_PASSAGE_BUTTON, link.target))
-                    .label(link.text)
-            })
-        }),
-        _ => components.create_action_row(|row| {
-            row.create_select_menu(|menu| {
-                menu.custom_id(PICK_NEXT_PASSAGE)
-                    .placeholder("Next chapter")
-                    .options(|mut options| {
-                        for node in passage.links() {
-                            options = options.create_option(|create_option| {
-                                create_option.label(node.text).value(node.target)
-                            });
-                        }
-                        options
-                    })
-            })
-        }),
+pub async fn play_story_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = play_story_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+async fn play_story_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let mut database = handler.storage.lock().await;
+    let player_id = command.user.id.to_string();
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+
+    let game_state_result = database.retrieve_game_state(&player_id, &guild_id);
+    drop(database);
+
+    match game_state_result {
+        Ok(game_state) => continue_game(game_state, handler, ctx, command, None).await?,
+        Err(_) => start_new_game(handler, ctx, command).await?,
+    }
+
+    Ok(())
+}
+
+/// Show the current passage of the session, with the footer unless the story was updated since.
+async fn continue_game(
+    game_state: GameState,
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    footer: Option<&str>,
+) -> Result<()> {
+    println!("[{}] Continuing game", correlation_id());
+
+    let emojis = guild_emojis(ctx, &game_state.guild_id);
+    let continued = continued_session(handler, game_state, footer, emojis).await?;
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| continued.add_to(message))
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Like [`continue_game`], for the buttons showing the session again.
+async fn continue_game_from_component(
+    game_state: GameState,
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    println!("[{}] Continuing game", correlation_id());
+
+    let emojis = guild_emojis(ctx, &game_state.guild_id);
+    let continued = continued_session(handler, game_state, None, emojis).await?;
+    message_component
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| continued.add_to(message))
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// What showing the session again displays: the content warnings of its current passage, or the
+/// passage itself.
+enum Continued {
+    Warnings(Vec<String>),
+    Passage(RenderedPassage),
+}
+
+impl Continued {
+    fn add_to<'a, 'b>(
+        &self,
+        message: &'b mut CreateInteractionResponseData<'a>,
+    ) -> &'b mut CreateInteractionResponseData<'a> {
+        match self {
+            Continued::Warnings(warnings) => message
+                .embed(|embed| {
+                    branded(embed)
+                        .title("Content warning")
+                        .description(warning_description(warnings))
+                })
+                .components(add_warning_components),
+            Continued::Passage(rendered) => {
+                if let Some(file) = rendered.attachment() {
+                    message.add_file(file);
+                }
+                message
+                    .set_embed(rendered.embed())
+                    .components(|components| rendered.add_components(components))
+            }
+        }
+        .ephemeral(true)
+    }
+}
+
+/// Render the current passage of the session, after reconciling it with its story. Unlike
+/// [`next_chapter`], the player doesn't move: no turn is taken, no visit recorded, and neither the
+/// macros nor the timeout of the passage run again.
+async fn continued_session(
+    handler: &Handler,
+    mut game_state: GameState,
+    footer: Option<&str>,
+    emojis: Vec<EmojiId>,
+) -> Result<Continued> {
+    let mut database = handler.storage.lock().await;
+    let story = database.get_story(game_state.story_id)?;
+    let restarted = reconcile(&mut database, &story, &mut game_state)? == Reconciled::Restarted;
+    let warnings =
+        unacknowledged_warnings(&mut database, &game_state, &game_state.current_chapter)?;
+    if !warnings.is_empty() {
+        return Ok(Continued::Warnings(warnings));
+    }
+
+    let passage = story
+        .get_passage(&game_state.current_chapter)
+        .ok_or_else(|| anyhow!("Couldn't retrieve passage"))?;
+    let options = components_options(&mut database, &game_state, passage.title())?;
+    drop(database);
+
+    let footer = if restarted {
+        Some(STORY_UPDATED)
+    } else {
+        footer
+    };
+    Ok(Continued::Passage(render(
+        &story,
+        &passage,
+        &game_state,
+        options,
+        footer,
+        emojis,
+    )))
+}
+
+pub async fn surprise_interaction(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    if let Err(error) = surprise_interaction_inner(handler, ctx, command).await {
+        error_interaction(&error, ctx, command).await;
+    }
+}
+
+/// Start a story picked by [`recommend`] for the player.
+async fn surprise_interaction_inner(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let player_id = command.user.id.to_string();
+
+    let storage = handler.storage.lock().await;
+    let stories = storage.list_guild_stories_detailed(&guild_id, true)?;
+    if stories.is_empty() {
+        return Err(UserError::NoStories.into());
+    }
+    let played_at: HashMap<i64, i64> = storage
+        .recent_stories(&player_id, &guild_id, stories.len())?
+        .into_iter()
+        .map(|story| (story.story_id, story.played_at))
+        .collect();
+    let stats: Vec<StoryStats> = stories
+        .iter()
+        .map(|story| StoryStats {
+            story_id: story.id,
+            rating: story.rating.as_ref().map(|rating| rating.average),
+            played_at: played_at.get(&story.id).copied(),
+        })
+        .collect();
+    let story_id = recommend(&stats, now(), handler.rng.as_ref()).ok_or(UserError::NoStories)?;
+    let story = stories
+        .iter()
+        .find(|story| story.id == story_id)
+        .ok_or(UserError::UnknownStory)?;
+    let footer = format!("Surprise! {}", now_playing(story));
+    drop(storage);
+
+    start_story_from_command(handler, ctx, command, story_id, &footer).await
+}
+
+/// Start the story right away, showing its first passage as the response to the command.
+async fn start_story_from_command(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    story_id: i64,
+    footer: &str,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+    let player_id = command.user.id.to_string();
+
+    let mut storage = handler.storage.lock().await;
+    let story = storage.get_story(story_id)?;
+    let start = start_passage(&storage, story_id, &story)?;
+    let game_state = new_game_state(&storage, &story, player_id, guild_id, story_id, start)?;
+    start_new_session(&mut storage, &game_state, max_sessions(handler))?;
+    storage.record_play(&game_state)?;
+    storage.record_visit(&game_state)?;
+    drop(storage);
+
+    continue_game(game_state, handler, ctx, command, Some(footer)).await
+}
+
+/// Like `You are playing The Cave by Jane`, where the story would otherwise be unnamed.
+fn now_playing(story: &StoryDetails) -> String {
+    match &story.author {
+        Some(author) => format!("You are playing {} by {author}", story.name),
+        None => format!("You are playing {}", story.name),
+    }
+}
+
+async fn start_new_game(
+    handler: &Handler,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let guild_id = command.guild_id.ok_or(UserError::NoGuild)?.to_string();
+
+    println!("[{}] Starting new game", correlation_id());
+    let storage = handler.storage.lock().await;
+    let stories = storage.list_guild_stories_detailed(&guild_id, true)?;
+    drop(storage);
+
+    let stories = match story_choice(stories)? {
+        StoryChoice::Only(story) => {
+            return start_story_from_command(handler, ctx, command, story.id, &now_playing(&story))
+                .await;
+        }
+        StoryChoice::Menu(stories) => stories,
+    };
+    let text = "Please select a story to start playing";
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .embed(|embed| branded(embed).title("Let's go").description(text))
+                        .components(|components| {
+                            components.create_action_row(|row| {
+                                row.create_select_menu(|menu| {
+                                    menu.custom_id(START_STORY_MENU).options(|mut options| {
+                                        for story in stories {
+                                            options = options.create_option(|create_option| {
+                                                create_option
+                                                    .label(truncate_label(
+                                                        &story.name,
+                                                        MAX_OPTION_LABEL_LENGTH,
+                                                    ))
+                                                    .value(story.id);
+                                                let description = story_description(&story);
+                                                if !description.is_empty() {
+                                                    create_option.description(truncate_label(
+                                                        &description,
+                                                        MAX_OPTION_LABEL_LENGTH,
+                                                    ));
+                                                }
+                                                create_option
+                                            });
+                                        }
+                                        options
+                                    })
+                                })
+                            })
+                        })
+                        .ephemeral(true)
+                })
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// How `/play` starts a session when the player has none.
+enum StoryChoice {
+    /// Nothing to pick from, the story starts right away.
+    Only(StoryDetails),
+    /// A menu of the stories.
+    Menu(Vec<StoryDetails>),
+}
+
+fn story_choice(mut stories: Vec<StoryDetails>) -> Result<StoryChoice> {
+    match stories.len() {
+        0 => Err(UserError::NoStories.into()),
+        1 => Ok(StoryChoice::Only(stories.remove(0))),
+        _ => Ok(StoryChoice::Menu(stories)),
+    }
+}
+
+/// Who wrote the story and how it's rated, like `by Jane · ★ 4.2 (12 ratings)`, for the picker.
+fn story_description(story: &StoryDetails) -> String {
+    let mut parts = vec![];
+    if let Some(author) = &story.author {
+        parts.push(format!("by {author}"));
+    }
+    if let Some(rating) = &story.rating {
+        parts.push(rating.to_string());
+    }
+    parts.join(" · ")
+}
+
+pub async fn actual_start(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let story_id = message_component
+        .data
+        .values
+        .first()
+        .ok_or(UserError::InvalidSelection)
+        .and_then(|id| id.parse::<i64>().map_err(|_| UserError::InvalidSelection))?;
+
+    start_story(handler, ctx, message_component, story_id).await
+}
+
+/// How many stories a player can play at once on a guild, `[sessions] max_per_player`.
+fn max_sessions(handler: &Handler) -> usize {
+    handler
+        .config()
+        .get_parsed_path(&["sessions", "max_per_player"])
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// Save the new session, unless the player already plays as many stories on the guild as
+/// allowed. The session it replaces doesn't count, see [`Storage::can_start_session`].
+fn start_new_session(
+    storage: &mut Storage<String>,
+    game_state: &GameState,
+    max_sessions: usize,
+) -> Result<()> {
+    if !storage.can_start_session(&game_state.player_id, &game_state.guild_id, max_sessions)? {
+        return Err(UserError::TooManySessions(max_sessions).into());
+    }
+    storage.start_session(game_state)
+}
+
+/// A new session of the story starting at the passage, with its `(set:)` run, not saved yet.
+fn new_game_state(
+    storage: &Storage<String>,
+    story: &Story<String>,
+    player_id: String,
+    guild_id: String,
+    story_id: i64,
+    passage: String,
+) -> Result<GameState> {
+    let mut game_state = GameState {
+        story_version: storage.story_modified_at(story_id)?,
+        single_message: storage.get_guild_single_message(&guild_id)?,
+        ..GameState::new(player_id, guild_id, story_id, passage)
+    };
+    if let Some(passage) = story.get_passage(&game_state.current_chapter) {
+        run_passage_macros(story, &passage, &mut game_state);
+    }
+    Ok(game_state)
+}
+
+async fn start_story(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+    story_id: i64,
+) -> Result<()> {
+    let guild_id = message_component
+        .guild_id
+        .ok_or(UserError::NoGuild)?
+        .to_string();
+
+    let player_id = message_component.user.id.to_string();
+
+    let mut storage = handler.storage.lock().await;
+    let story = storage.get_story(story_id)?;
+    let start = start_passage(&storage, story_id, &story)?;
+    let author = storage.story_author(story_id)?;
+    drop(storage);
+
+    let (game_state, warnings) = {
+        let mut storage = handler.storage.lock().await;
+        let game_state = new_game_state(&storage, &story, player_id, guild_id, story_id, start)?;
+        start_new_session(&mut storage, &game_state, max_sessions(handler))?;
+        storage.record_play(&game_state)?;
+        storage.record_visit(&game_state)?;
+        let warnings =
+            unacknowledged_warnings(&mut storage, &game_state, &game_state.current_chapter)?;
+        (game_state, warnings)
+    };
+
+    update_message_text(
+        "Let's go",
+        match (story.title(), author) {
+            (Some(story_name), Some(author)) => {
+                format!("Your story `{story_name}` by {author} is starting!")
+            }
+            (Some(story_name), None) => format!("Your story `{story_name}` is starting!"),
+            (None, _) => "Your story is starting!".to_string(),
+        },
+        ctx,
+        message_component,
+    )
+    .await?;
+
+    if !warnings.is_empty() {
+        send_warning(ctx, message_component, &warnings, false).await?;
+        return Ok(());
+    }
+
+    let passage = story
+        .get_passage(&game_state.current_chapter)
+        .ok_or_else(|| anyhow!("Couldn't retrieve passage"))?;
+    let options = components_options(
+        &mut *handler.storage.lock().await,
+        &game_state,
+        passage.title(),
+    )?;
+    let emojis = guild_emojis(ctx, &game_state.guild_id);
+    let rendered = render(&story, &passage, &game_state, options, None, emojis);
+
+    let message = message_component
+        .create_followup_message(&ctx.http, |message| {
+            if let Some(file) = rendered.attachment() {
+                message.add_file(file);
+            }
+            message
+                .set_embed(rendered.embed())
+                .components(|components| rendered.add_components(components))
+                .ephemeral(true)
+        })
+        .await?;
+
+    let mut storage = handler.storage.lock().await;
+    schedule_timeout(
+        handler,
+        ctx,
+        message_component,
+        &mut storage,
+        &game_state,
+        &passage,
+        Some(message.id),
+    )?;
+
+    Ok(())
+}
+
+pub async fn next_chapter_from_menu(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let (turn, index) = message_component
+        .data
+        .values
+        .first()
+        .and_then(|value| parse_link_value(value))
+        .ok_or(UserError::InvalidSelection)?;
+
+    let destination = Destination::Link { turn, index };
+    go_to_chapter(handler, ctx, message_component, destination, None).await
+}
+
+pub async fn next_chapter_from_button(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let (turn, index) = message_component
+        .data
+        .custom_id
+        .strip_prefix(PICK_NEXT_PASSAGE_BUTTON)
+        .and_then(parse_link_value)
+        .ok_or(UserError::InvalidSelection)?;
+
+    let destination = Destination::Link { turn, index };
+    go_to_chapter(handler, ctx, message_component, destination, None).await
+}
+
+pub async fn next_chapter_at_random(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let turn = message_component
+        .data
+        .custom_id
+        .strip_prefix(PICK_RANDOM_PASSAGE)
+        .and_then(|turn| turn.parse().ok())
+        .ok_or(UserError::InvalidSelection)?;
+
+    let destination = Destination::RandomLink { turn };
+    go_to_chapter(handler, ctx, message_component, destination, None).await
+}
+
+/// One of the links, picked at random.
+fn pick_random_link<'a, T>(links: &'a [T], rng: &dyn Rng) -> Option<&'a T> {
+    if links.is_empty() {
+        return None;
+    }
+    Some(&links[rng.below(links.len())])
+}
+
+/// How components refer to the link at the index of the passage shown at the turn. Links are
+/// referred to by their index, as custom ids and values are too short for long passage titles,
+/// and the turn tells apart the links of the passages shown in other messages.
+fn link_value(turn: u64, index: usize) -> String {
+    format!("{turn}:{index}")
+}
+
+/// The turn and the index of the link of a component, see [`link_value`].
+fn parse_link_value(value: &str) -> Option<(u64, usize)> {
+    let (turn, index) = value.split_once(':')?;
+    Some((turn.parse().ok()?, index.parse().ok()?))
+}
+
+/// Where [`go_to_chapter`] takes the player.
+enum Destination<'a> {
+    /// The passage itself, like after acknowledging its warnings.
+    Passage(&'a str),
+    /// The link at the index in the passage shown at the turn, see [`link_value`].
+    Link { turn: u64, index: usize },
+    /// One of the links of the passage shown at the turn the player can take, picked at random.
+    RandomLink { turn: u64 },
+    /// The first link of the current passage the player can take.
+    FirstLink,
+}
+
+/// The passage the destination leads to from the current passage of the player, and the
+/// `(effects:)` of the link leading there. `None` when there isn't any link left to take.
+fn destination_target(
+    story: &Story<String>,
+    game_state: &GameState,
+    destination: &Destination,
+    rng: &dyn Rng,
+) -> Result<Option<(String, Option<String>)>> {
+    match destination {
+        Destination::Passage(passage) => return Ok(Some((passage.to_string(), None))),
+        // The components of a previous passage, clicked in an older message.
+        Destination::Link { turn, .. } | Destination::RandomLink { turn }
+            if *turn != game_state.turn =>
+        {
+            return Err(UserError::InvalidSelection.into())
+        }
+        _ => {}
+    }
+    let passage = story
+        .get_passage(&game_state.current_chapter)
+        .ok_or(UserError::InvalidSelection)?;
+    let choices = choices(&passage);
+    // The choice may have been shown before its requirement stopped holding, in another message.
+    let mut available = choices
+        .iter()
+        .enumerate()
+        .filter(|(_, choice)| choice.available(&game_state.variables));
+    let choice = match destination {
+        Destination::Passage(_) => unreachable!("Passages aren't links"),
+        Destination::Link {
+            index: link_index, ..
+        } => Some(
+            available
+                .find(|(index, _)| index == link_index)
+                .map(|(_, choice)| choice)
+                .ok_or(UserError::InvalidSelection)?,
+        ),
+        Destination::RandomLink { .. } => {
+            let links: Vec<&Choice> = available.map(|(_, choice)| choice).collect();
+            Some(*pick_random_link(&links, rng).ok_or(UserError::InvalidSelection)?)
+        }
+        Destination::FirstLink => available.next().map(|(_, choice)| choice),
+    };
+    Ok(choice.map(|choice| {
+        let effects = choice.effects.map(ToString::to_string);
+        (choice.target.to_string(), effects)
+    }))
+}
+
+pub async fn next_chapter(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+    chapter_name: &str,
+) -> Result<()> {
+    let destination = Destination::Passage(chapter_name);
+    go_to_chapter(handler, ctx, message_component, destination, None).await
+}
+
+/// A move of the player, saved by [`claim_move`] before it's shown.
+struct Move {
+    story: Story<String>,
+    /// The session as the passage is shown: moved to it, before the `(set:)` of the passage.
+    shown: GameState,
+    /// The session as it was saved, after the `(set:)` of the passage.
+    game_state: GameState,
+    /// The warnings to acknowledge before the passage is revealed.
+    warnings: Vec<String>,
+    /// Whether the session restarted, the story being updated.
+    restarted: bool,
+}
+
+/// Move the player to the passage of the destination, after applying the `(effects:)` of the link
+/// leading to it. The link is resolved, its effects applied and the move saved at once, before
+/// anything is shown, so two clicks, or a click and a timeout, can't both move the player from
+/// the same passage. `None` when nothing is to be done, after a timeout on a passage the player
+/// left.
+fn claim_move(
+    storage: &mut Storage<String>,
+    player_id: &str,
+    guild_id: &str,
+    destination: &Destination,
+    timed_out: Option<&TimedOut>,
+    rng: &dyn Rng,
+) -> Result<Option<Move>> {
+    let mut game_state = storage
+        .retrieve_game_state(player_id, guild_id)
+        .map_err(no_session)?;
+    if timed_out.is_some_and(|timed_out| !timed_out.is_for(&game_state)) {
+        return Ok(None);
+    }
+    let story = storage.get_story(game_state.story_id)?;
+    let reconciled = reconcile(storage, &story, &mut game_state)?;
+    let mut restarted = reconciled == Reconciled::Restarted;
+    // The link taken is gone when the session restarted.
+    let (mut chapter_name, mut effects) = if restarted {
+        (game_state.current_chapter.clone(), None)
+    } else {
+        let Some(target) = destination_target(&story, &game_state, destination, rng)? else {
+            return Ok(None);
+        };
+        target
+    };
+    // The update removed the passage the link leads to, like it could have the current one.
+    if reconciled == Reconciled::Updated && story.get_passage(&chapter_name).is_none() {
+        restart(storage, &story, &mut game_state)?;
+        restarted = true;
+        (chapter_name, effects) = (game_state.current_chapter.clone(), None);
+    }
+    let Some(passage) = story.get_passage(&chapter_name) else {
+        game_state.broken_link = Some(chapter_name);
+        storage.update_game_state(&game_state)?;
+        return Err(UserError::BrokenLink.into());
+    };
+    if let Some(effects) = effects {
+        apply_effects(&effects, &mut game_state.variables);
+    }
+    let warnings = unacknowledged_warnings(storage, &game_state, &chapter_name)?;
+
+    game_state.move_to(&chapter_name);
+    let shown = game_state.clone();
+    if !warnings.is_empty() {
+        // The passage is revealed, and visited, once the warnings are acknowledged.
+        save_progress(storage, &game_state)?;
+    } else {
+        run_passage_macros(&story, &passage, &mut game_state);
+        storage.record_visit(&game_state)?;
+        // Without any choice left to take, the story ends like at a passage without links.
+        if has_available_choice(&passage, &game_state.variables) {
+            save_progress(storage, &game_state)?;
+        } else {
+            storage.record_completion(&game_state)?;
+            storage.clear_game_state(player_id, guild_id)?;
+        }
+    }
+
+    Ok(Some(Move {
+        story,
+        shown,
+        game_state,
+        warnings,
+        restarted,
+    }))
+}
+
+/// Show the passage of the destination to the player, once [`claim_move`] moved them there.
+/// With `timed_out`, the player didn't choose in time so the interaction was answered already, and
+/// nothing is done if they moved since.
+async fn go_to_chapter(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+    destination: Destination<'_>,
+    timed_out: Option<&TimedOut>,
+) -> Result<()> {
+    let player_id = message_component.user.id.to_string();
+    let guild_id = message_component
+        .guild_id
+        .ok_or(UserError::NoGuild)?
+        .to_string();
+
+    // Seeded by the interaction, so a retried interaction follows the same random link.
+    let rng = handler.rng.for_interaction(message_component.id.0);
+    let mut database = handler.storage.lock().await;
+    let Some(moved) = claim_move(
+        &mut database,
+        &player_id,
+        &guild_id,
+        &destination,
+        timed_out,
+        &rng,
+    )?
+    else {
+        return Ok(());
+    };
+    let Move {
+        story,
+        shown,
+        game_state,
+        warnings,
+        restarted,
+    } = moved;
+    let chapter_name = shown.current_chapter.as_str();
+    let options = components_options(&mut database, &shown, chapter_name)?;
+    let delay = database.get_guild_delay(&guild_id)?;
+    drop(database);
+
+    // The passage the player timed out on, when it isn't the original response.
+    let timed_out_message = timed_out.and_then(|timed_out| timed_out.message);
+    if timed_out.is_none() {
+        message_component.defer(&ctx.http).await?;
+    }
+    let remove_components = async {
+        // A single message is only replaced after the delay, so its choices are removed first.
+        if shown.single_message && delay.is_zero() {
+            return Ok(());
+        }
+        // Update the previous interaction to remove the menu.
+        match timed_out_message {
+            Some(message) => {
+                message_component
+                    .edit_followup_message(&ctx.http, message, |followup| {
+                        followup.components(|c| c)
+                    })
+                    .await?;
+            }
+            None => {
+                message_component
+                    .edit_original_interaction_response(&ctx.http, |response| {
+                        response.components(|c| c)
+                    })
+                    .await?;
+            }
+        }
+        Ok(())
+    };
+    // Deferred, the interaction can be answered for 15 minutes, far more than the delay.
+    remove_then_wait(handler.clock.as_ref(), delay, remove_components).await?;
+
+    if !warnings.is_empty() {
+        send_warning(ctx, message_component, &warnings, shown.single_message).await?;
+        return Ok(());
+    }
+
+    let passage = story
+        .get_passage(chapter_name)
+        .ok_or_else(|| anyhow!("Couldn't retrieve passage"))?;
+    let footer = if restarted {
+        Some(STORY_UPDATED)
+    } else {
+        timed_out.map(|_| TIMED_OUT)
+    };
+    let emojis = guild_emojis(ctx, &shown.guild_id);
+    let rendered = render(&story, &passage, &shown, options, footer, emojis);
+    // The message showing the passage, when it isn't the original response.
+    let message = if shown.single_message {
+        // Each click is a new interaction, so its token is always fresh enough to edit.
+        match timed_out_message {
+            Some(message) => {
+                message_component
+                    .edit_followup_message(&ctx.http, message, |followup| {
+                        remove_attachments(&mut followup.0);
+                        followup
+                            .set_embed(rendered.embed())
+                            .components(|components| rendered.add_components(components))
+                    })
+                    .await?;
+            }
+            None => {
+                message_component
+                    .edit_original_interaction_response(&ctx.http, |response| {
+                        remove_attachments(&mut response.0);
+                        response
+                            .set_embed(rendered.embed())
+                            .components(|components| rendered.add_components(components))
+                    })
+                    .await?;
+            }
+        }
+        // Files can't be added when editing, so the passage follows.
+        if let Some(file) = rendered.attachment() {
+            message_component
+                .create_followup_message(&ctx.http, |followup| {
+                    followup.add_file(file).ephemeral(true)
+                })
+                .await?;
+        }
+        timed_out_message
+    } else {
+        let message = message_component
+            .create_followup_message(&ctx.http, |followup| {
+                if let Some(file) = rendered.attachment() {
+                    followup.add_file(file);
+                }
+                followup
+                    .allowed_mentions(|mentions| mentions.replied_user(true))
+                    .set_embed(rendered.embed())
+                    .components(|components| rendered.add_components(components))
+                    .ephemeral(true)
+            })
+            .await?;
+        Some(message.id)
+    };
+
+    let mut database = handler.storage.lock().await;
+    schedule_timeout(
+        handler,
+        ctx,
+        message_component,
+        &mut database,
+        &game_state,
+        &passage,
+        message,
+    )?;
+
+    Ok(())
+}
+
+/// Remove the components of the previous passage, so they can't be clicked while waiting for the
+/// delay of the guild, then wait for it.
+async fn remove_then_wait(
+    clock: &dyn Clock,
+    delay: Duration,
+    remove_components: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    remove_components.await?;
+    if !delay.is_zero() {
+        clock.sleep(delay).await;
+    }
+    Ok(())
+}
+
+pub async fn acknowledge_warning(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let player_id = message_component.user.id.to_string();
+    let guild_id = message_component
+        .guild_id
+        .ok_or(UserError::NoGuild)?
+        .to_string();
+
+    let mut database = handler.storage.lock().await;
+    let game_state = database
+        .retrieve_game_state(&player_id, &guild_id)
+        .map_err(no_session)?;
+    database.acknowledge_warning(&player_id, &guild_id, STORY_WARNINGS)?;
+    database.acknowledge_warning(&player_id, &guild_id, &game_state.current_chapter)?;
+    drop(database);
+
+    next_chapter(handler, ctx, message_component, &game_state.current_chapter).await
+}
+
+pub async fn the_end(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let player_id = message_component.user.id.to_string();
+    let guild_id = message_component
+        .guild_id
+        .ok_or(UserError::NoGuild)?
+        .to_string();
+
+    let rated_story = {
+        let mut database = handler.storage.lock().await;
+        database.clear_game_state(&player_id, &guild_id)?;
+        database.last_completed_story(&player_id, &guild_id)?
+    };
+    let rating_buttons: Vec<(String, String)> = match rated_story {
+        Some(story_id) => (1..=5)
+            .map(|stars| {
+                (
+                    format!("{RATE_STORY_BUTTON}{story_id}:{stars}"),
+                    format!("⭐ {stars}"),
+                )
+            })
+            .collect(),
+        None => vec![],
+    };
+
+    let single_message = message_component.data.custom_id == THE_END_IN_PLACE;
+    message_component.defer(&ctx.http).await?;
+    if single_message {
+        message_component
+            .edit_original_interaction_response(&ctx.http, |response| {
+                response
+                    .embed(the_end_embed)
+                    .components(|components| add_button_rows(components, &rating_buttons, &[]))
+            })
+            .await?;
+    } else {
+        message_component
+            .edit_original_interaction_response(&ctx.http, |response| response.components(|c| c))
+            .await?;
+        message_component
+            .create_followup_message(&ctx.http, |followup| {
+                followup
+                    .allowed_mentions(|mentions| mentions.replied_user(true))
+                    .embed(the_end_embed)
+                    .components(|components| add_button_rows(components, &rating_buttons, &[]))
+                    .ephemeral(true)
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Rate the story with the stars of the button shown by [`the_end`], replacing the previous
+/// rating of the player.
+pub async fn rate_story(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    let (story_id, stars) = message_component
+        .data
+        .custom_id
+        .strip_prefix(RATE_STORY_BUTTON)
+        .and_then(|rating| rating.split_once(':'))
+        .and_then(|(story_id, stars)| Some((story_id.parse::<i64>().ok()?, stars.parse().ok()?)))
+        .filter(|(_, stars)| (1..=5).contains(stars))
+        .ok_or(UserError::InvalidSelection)?;
+
+    let player_id = message_component.user.id.to_string();
+    let guild_id = message_component
+        .guild_id
+        .ok_or(UserError::NoGuild)?
+        .to_string();
+    let rating = {
+        let storage = handler.storage.lock().await;
+        storage.rate_story(&player_id, &guild_id, story_id, stars)?;
+        storage.average_rating(story_id)?
+    };
+
+    let mut text = format!("Thanks for giving this story {stars} ⭐!");
+    if let Some(rating) = rating {
+        text.push_str(&format!(" It's now rated {rating}."));
+    }
+    text.push_str(" To start a new session, use the `/play` command.");
+    update_message_text("The end", text, ctx, message_component).await
+}
+
+/// The embed and components of a passage, and its file when it's too long for the embed.
+pub type PreviewedPassage = (
+    CreateEmbed,
+    CreateComponents,
+    Option<AttachmentType<'static>>,
+);
+
+/// The passage of the story as a player of the guild would see it, with components that don't
+/// affect any session. Nothing is saved.
+pub fn preview_passage(
+    storage: &mut Storage<String>,
+    guild_id: &str,
+    story_id: i64,
+    passage: &str,
+    emojis: Vec<EmojiId>,
+) -> Result<PreviewedPassage> {
+    let story = storage.get_story(story_id)?;
+    let passage = story
+        .get_passage(passage)
+        .ok_or_else(|| UserError::UnknownPassage(passage.to_string()))?;
+    let game_state = GameState::new(
+        String::new(),
+        guild_id.to_string(),
+        story_id,
+        passage.title().to_string(),
+    );
+    let options = ComponentsOptions {
+        preview: true,
+        ..components_options(storage, &game_state, passage.title())?
+    };
+
+    let rendered = render(
+        &story,
+        &passage,
+        &game_state,
+        options,
+        Some(PREVIEW),
+        emojis,
+    );
+    let mut components = CreateComponents::default();
+    rendered.add_components(&mut components);
+    Ok((rendered.embed(), components, rendered.attachment()))
+}
+
+/// Answer a click on the components of `/preview`.
+pub async fn preview_choice(
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+) -> Result<()> {
+    message_component
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| {
+                    data.content("This is only a preview, start the story with `/play` to play it.")
+                        .ephemeral(true)
+                })
+        })
+        .await?;
+    Ok(())
+}
+
+/// A passage shown with a timeout, see [`schedule_timeout`].
+struct TimedOut {
+    /// The session the passage was shown in.
+    session_id: String,
+    story_id: i64,
+    /// The turn of the session when the passage was shown.
+    turn: u64,
+    /// The message showing the passage, when it isn't the original response of the interaction.
+    message: Option<MessageId>,
+}
+
+impl TimedOut {
+    /// Whether the player is still on the passage, in the same session.
+    fn is_for(&self, game_state: &GameState) -> bool {
+        self.session_id == game_state.session_id
+            && self.story_id == game_state.story_id
+            && self.turn == game_state.turn
+    }
+}
+
+/// Whether an interaction created at `created_at` can still be answered after waiting `wait` from
+/// `now`, with some time left to show the passage. Both are unix timestamps.
+fn answerable_after(created_at: i64, now: i64, wait: Duration) -> bool {
+    let deadline = created_at + INTERACTION_TOKEN_LIFETIME.as_secs() as i64;
+    now + (wait + TIMEOUT_MARGIN).as_secs() as i64 <= deadline
+}
+
+/// Move the player to the first link of the passage they can take if it has a timeout and they
+/// don't choose in time. The passage shown after a timeout is sent with the same interaction,
+/// so it only times out in turn while the token of the interaction is still valid.
+fn schedule_timeout(
+    handler: &Handler,
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+    storage: &mut Storage<String>,
+    game_state: &GameState,
+    passage: &Passage<&str>,
+    message: Option<MessageId>,
+) -> Result<()> {
+    let content = storage.get_story_content(game_state.story_id)?;
+    let Some(timeout) = passage_timeout(&content, passage.title()) else {
+        return Ok(());
+    };
+    if !has_available_choice(passage, &game_state.variables) {
+        return Ok(());
+    }
+    let delay = storage.get_guild_delay(&game_state.guild_id)?;
+    let created_at = message_component.id.created_at().unix_timestamp();
+    if !answerable_after(created_at, now(), timeout + delay) {
+        println!(
+            "[{}] No timeout on {}, the interaction would expire first",
+            correlation_id(),
+            passage.title()
+        );
+        return Ok(());
+    }
+
+    let timed_out = TimedOut {
+        session_id: game_state.session_id.clone(),
+        story_id: game_state.story_id,
+        turn: game_state.turn,
+        message,
+    };
+    let handler = handler.clone();
+    let ctx = ctx.clone();
+    let message_component = message_component.clone();
+    let branding = Branding::new(&handler.config());
+    tokio::spawn(BRANDING.scope(
+        branding,
+        CORRELATION_ID.scope(correlation_id(), async move {
+            tokio::time::sleep(timeout).await;
+            if let Err(error) = go_to_chapter(
+                &handler,
+                &ctx,
+                &message_component,
+                Destination::FirstLink,
+                Some(&timed_out),
+            )
+            .await
+            {
+                println!(
+                    "[{}] Cannot move on after a timeout: {error:?}",
+                    correlation_id()
+                );
+            }
+        }),
+    ));
+    Ok(())
+}
+
+fn the_end_embed(embed: &mut CreateEmbed) -> &mut CreateEmbed {
+    branded(embed)
+        .title("The end")
+        .description("That's it for now! To start a new session, use the `/play` command.")
+}
+
+/// Save the game state if its passage is a save point, or only keep it in memory until the player
+/// reaches the next checkpoint.
+fn save_progress(storage: &mut Storage<String>, game_state: &GameState) -> Result<()> {
+    let content = storage.get_story_content(game_state.story_id)?;
+    if is_save_point(&content, &game_state.current_chapter) {
+        storage.update_game_state(game_state)
+    } else {
+        storage.keep_unsaved_game_state(game_state);
+        Ok(())
+    }
+}
+
+/// The passage new sessions of the story start from: the one set with `/setstart` if it still
+/// exists, or the start of the story.
+pub fn start_passage(
+    storage: &Storage<String>,
+    story_id: i64,
+    story: &Story<String>,
+) -> Result<String> {
+    if let Some(start) = storage.get_start_passage(story_id)? {
+        if story.get_passage(&start).is_some() {
+            return Ok(start);
+        }
+    }
+    let start = story
+        .start()
+        .ok_or_else(|| anyhow!("Story without start"))?;
+    Ok(start.title().to_string())
+}
+
+/// Make new sessions of the story start from the passage, with `/setstart`.
+pub fn choose_start_passage(
+    storage: &Storage<String>,
+    story_id: i64,
+    story: &Story<String>,
+    passage: &str,
+) -> Result<()> {
+    if story.get_passage(passage).is_none() {
+        return Err(UserError::UnknownPassage(passage.to_string()).into());
+    }
+    storage.set_start_passage(story_id, passage)
+}
+
+/// How [`reconcile`] left the session.
+#[derive(PartialEq)]
+enum Reconciled {
+    /// The story wasn't updated since the session last saw it.
+    Unchanged,
+    /// The story was updated, but the current passage of the session still exists.
+    Updated,
+    /// The current passage of the session doesn't exist anymore, so it restarted.
+    Restarted,
+}
+
+/// Check whether the story was updated since the session last saw it. When its current passage
+/// doesn't exist anymore, the session restarts from the beginning.
+fn reconcile(
+    storage: &mut Storage<String>,
+    story: &Story<String>,
+    game_state: &mut GameState,
+) -> Result<Reconciled> {
+    let story_version = storage.story_modified_at(game_state.story_id)?;
+    if game_state.story_version == story_version {
+        return Ok(Reconciled::Unchanged);
+    }
+    game_state.story_version = story_version;
+
+    let reconciled = if story.get_passage(&game_state.current_chapter).is_none() {
+        restart(storage, story, game_state)?;
+        Reconciled::Restarted
+    } else {
+        Reconciled::Updated
+    };
+    storage.update_game_state(game_state)?;
+
+    Ok(reconciled)
+}
+
+/// Move the session back to the start of the story, forgetting its history and variables.
+fn restart(
+    storage: &Storage<String>,
+    story: &Story<String>,
+    game_state: &mut GameState,
+) -> Result<()> {
+    game_state.current_chapter = start_passage(storage, game_state.story_id, story)?;
+    game_state.history.clear();
+    game_state.variables.clear();
+    Ok(())
+}
+
+/// A missing game state means the player is not playing anymore, for example after a `/stop`.
+fn no_session(error: anyhow::Error) -> anyhow::Error {
+    match error.downcast_ref::<rusqlite::Error>() {
+        Some(rusqlite::Error::QueryReturnedNoRows) => UserError::NoSession.into(),
+        _ => error,
+    }
+}
+
+/// The content warnings the player did not acknowledge yet in this session: the ones of the whole
+/// story, whatever passage the session is at, and the ones of the passage.
+fn unacknowledged_warnings(
+    storage: &mut Storage<String>,
+    game_state: &GameState,
+    passage: &str,
+) -> Result<Vec<String>> {
+    let content = storage.get_story_content(game_state.story_id)?;
+    let (player_id, guild_id) = (&game_state.player_id, &game_state.guild_id);
+
+    let mut warnings = vec![];
+    if !storage.is_warning_acknowledged(player_id, guild_id, STORY_WARNINGS)? {
+        warnings = story_warnings(&content);
+    }
+    if !storage.is_warning_acknowledged(player_id, guild_id, passage)? {
+        for warning in content_warnings(&content, passage) {
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+    }
+    Ok(warnings)
+}
+
+async fn send_warning(
+    ctx: &Context,
+    message_component: &MessageComponentInteraction,
+    warnings: &[String],
+    single_message: bool,
+) -> Result<()> {
+    let mut embed = CreateEmbed::default();
+    branded(&mut embed);
+    embed
+        .title("Content warning")
+        .description(warning_description(warnings));
+
+    if single_message {
+        message_component
+            .edit_original_interaction_response(&ctx.http, |response| {
+                response.set_embed(embed).components(add_warning_components)
+            })
+            .await?;
+    } else {
+        message_component
+            .create_followup_message(&ctx.http, |followup| {
+                followup
+                    .set_embed(embed)
+                    .components(add_warning_components)
+                    .ephemeral(true)
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+fn warning_description(warnings: &[String]) -> String {
+    let warnings = warnings
+        .iter()
+        .map(|warning| format!("`{warning}`"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("The next passage contains: {warnings}.")
+}
+
+fn add_warning_components(components: &mut CreateComponents) -> &mut CreateComponents {
+    components.create_action_row(|row| {
+        row.create_button(|create_button| {
+            create_button
+                .custom_id(ACKNOWLEDGE_WARNING)
+                .label("I understand, continue")
+        })
+    })
+}
+
+fn passage_content(
+    story: &Story<String>,
+    passage: &Passage<&str>,
+    game_state: &GameState,
+) -> String {
+    // The `(set:)` of the passage show in its text, but are only kept by `run_passage_macros`.
+    let mut variables = game_state.variables.clone();
+    render_passage(story, passage, &mut variables)
+}
+
+/// Update the variables of the session with the `(set:)` of the passage it reached.
+fn run_passage_macros(story: &Story<String>, passage: &Passage<&str>, game_state: &mut GameState) {
+    render_passage(story, passage, &mut game_state.variables);
+}
+
+fn render_passage(
+    story: &Story<String>,
+    passage: &Passage<&str>,
+    variables: &mut HashMap<String, String>,
+) -> String {
+    let mut passage_content = String::new();
+    let mut includes = vec![passage.title().to_string()];
+    let nodes = passage.nodes();
+    for (index, node) in nodes.iter().enumerate() {
+        match node {
+            twee_v3::ContentNode::Text(text) => {
+                let text = text_before(nodes, index, text);
+                render_text(story, text, variables, &mut includes, &mut passage_content)
+            }
+            twee_v3::ContentNode::Link { text, target } => {
+                let text = Choice::new(text, target).text;
+                passage_content.push_str(&format!("`{text}`"))
+            }
+        };
+    }
+    passage_content
+}
+
+/// Render the text of a passage, running its macros and inlining the passages included with
+/// `(display: "Passage")`. `includes` are the passages being rendered, so a passage including
+/// itself is caught.
+fn render_text(
+    story: &Story<String>,
+    text: &str,
+    variables: &mut HashMap<String, String>,
+    includes: &mut Vec<String>,
+    output: &mut String,
+) {
+    const DISPLAY: &str = "(display:";
+
+    let text = run_macros(text, variables);
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find(DISPLAY) {
+        let Some(end) = rest[start..].find(')').map(|end| start + end) else {
+            break;
+        };
+        output.push_str(&print_variables(&rest[..start], variables));
+
+        let name = rest[start + DISPLAY.len()..end]
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'');
+        match story.get_passage(name) {
+            Some(included)
+                if includes.len() <= MAX_INCLUDE_DEPTH && !includes.iter().any(|i| i == name) =>
+            {
+                includes.push(name.to_string());
+                let nodes = included.nodes();
+                for (index, node) in nodes.iter().enumerate() {
+                    match node {
+                        // Included links are only text, the choices are the ones of the passage.
+                        twee_v3::ContentNode::Text(text) => {
+                            let text = text_before(nodes, index, text);
+                            render_text(story, text, variables, includes, output)
+                        }
+                        twee_v3::ContentNode::Link { text, target } => {
+                            output.push_str(Choice::new(text, target).text)
+                        }
+                    }
+                }
+                includes.pop();
+            }
+            _ => println!(
+                "[{}] Couldn't include passage {name} in {}",
+                correlation_id(),
+                includes[0]
+            ),
+        }
+
+        rest = &rest[end + 1..];
+    }
+    output.push_str(&print_variables(rest, variables));
+}
+
+fn has_available_choice(passage: &Passage<&str>, variables: &HashMap<String, String>) -> bool {
+    choices(passage)
+        .iter()
+        .any(|choice| choice.available(variables))
+}
+
+/// The text of the node at the index, without the macros of the link following it.
+fn text_before<'a>(nodes: &[twee_v3::ContentNode<&str>], index: usize, text: &'a str) -> &'a str {
+    match nodes.get(index + 1) {
+        Some(twee_v3::ContentNode::Link { .. }) => strip_link_macros(text),
+        _ => text,
+    }
+}
+
+/// The labels of the links of the passage, see [`Choice::label`]. Links sharing the same label are
+/// numbered, like `Continue (1)` and `Continue (2)`, so the player can tell them apart.
+fn choice_labels(passage: &Passage<&str>) -> Vec<String> {
+    let texts: Vec<String> = choices(passage).iter().map(Choice::label).collect();
+    texts
+        .iter()
+        .enumerate()
+        .map(|(index, text)| {
+            if texts.iter().filter(|other| *other == text).count() > 1 {
+                let number = texts[..index].iter().filter(|other| *other == text).count() + 1;
+                format!("{text} ({number})")
+            } else {
+                text.to_string()
+            }
+        })
+        .collect()
+}
+
+/// How the links of a passage should be presented.
+#[derive(Clone, Copy)]
+struct ComponentsOptions {
+    /// The layout chosen by the guild.
+    layout: ChoiceLayout,
+    /// Whether the passage is tagged to follow one of its links at random.
+    random: bool,
+    /// Whether the session shows every passage in the same message.
+    single_message: bool,
+    /// Whether the passage is shown by `/preview`, outside of any session.
+    preview: bool,
+    /// Whether the story keeps variables, for the inventory button.
+    inventory: bool,
+}
+
+fn components_options(
+    storage: &mut Storage<String>,
+    game_state: &GameState,
+    passage: &str,
+) -> Result<ComponentsOptions> {
+    let layout = storage.get_guild_layout(&game_state.guild_id)?;
+    let content = storage.get_story_content(game_state.story_id)?;
+    Ok(ComponentsOptions {
+        layout,
+        random: is_random_passage(&content, passage),
+        single_message: game_state.single_message,
+        preview: false,
+        inventory: uses_variables(&content),
+    })
+}
+
+/// A passage as shown to the player, apart from the Discord builders it's turned into.
+struct RenderedPassage {
+    title: String,
+    body: String,
+    footer: Option<String>,
+    choices: RenderedChoices,
+    /// Whether to show the inventory button along the choices.
+    inventory: bool,
+    /// The custom emoji the components can show, see [`guild_emojis`].
+    emojis: Vec<EmojiId>,
+}
+
+/// How the player moves on from a rendered passage.
+enum RenderedChoices {
+    /// Buttons, as `(custom_id, label)`.
+    Buttons(Vec<(String, String)>),
+    /// A select menu, its options valued by the turn and the index of their link.
+    Menu {
+        custom_id: String,
+        options: Vec<MenuChoice>,
+    },
+}
+
+struct MenuChoice {
+    /// The link in the passage, see [`link_value`].
+    value: String,
+    label: String,
+    /// The full text of the choice, when the label is shorter.
+    description: Option<String>,
+}
+
+/// Render the passage of the session: its text with the variables of the player, and the choices
+/// they can take presented the way the options say.
+fn render(
+    story: &Story<String>,
+    passage: &Passage<&str>,
+    game_state: &GameState,
+    options: ComponentsOptions,
+    footer: Option<&str>,
+    emojis: Vec<EmojiId>,
+) -> RenderedPassage {
+    // The requirements of the choices see the `(set:)` of the passage.
+    let mut variables = game_state.variables.clone();
+    let body = render_passage(story, passage, &mut variables);
+    RenderedPassage {
+        title: passage.title().to_string(),
+        body,
+        footer: footer.map(ToString::to_string),
+        choices: rendered_choices(passage, &variables, game_state.turn, options),
+        // The session is over at the end, with nothing left to show.
+        inventory: options.inventory
+            && !options.preview
+            && has_available_choice(passage, &variables),
+        emojis,
+    }
+}
+
+/// Choices whose `(requires:)` doesn't hold are left out, and a passage without any choice left
+/// ends like one without links. The choices refer to the passage shown at the turn.
+fn rendered_choices(
+    passage: &Passage<&str>,
+    variables: &HashMap<String, String>,
+    turn: u64,
+    options: ComponentsOptions,
+) -> RenderedChoices {
+    let prefix = if options.preview { PREVIEW_PREFIX } else { "" };
+    let choices = choices(passage);
+    // The labels are numbered among all the choices, so they don't change with the variables.
+    let available: Vec<(usize, String, &Choice)> = choice_labels(passage)
+        .into_iter()
+        .zip(&choices)
+        .enumerate()
+        .filter(|(_, (_, choice))| choice.available(variables))
+        .map(|(index, (label, choice))| (index, label, choice))
+        .collect();
+    let link_count = available.len();
+    if link_count == 0 {
+        // The session is over by the time the button is clicked, so it tells how to end.
+        let custom_id = if options.single_message {
+            THE_END_IN_PLACE
+        } else {
+            THE_END
+        };
+        return RenderedChoices::Buttons(vec![(
+            format!("{prefix}{custom_id}"),
+            "The end".to_string(),
+        )]);
+    }
+    if options.random {
+        return RenderedChoices::Buttons(vec![(
+            format!("{prefix}{PICK_RANDOM_PASSAGE}{turn}"),
+            "Continue".to_string(),
+        )]);
+    }
+
+    let use_buttons = match options.layout {
+        ChoiceLayout::Auto => link_count == 1,
+        ChoiceLayout::Buttons => link_count <= MAX_BUTTONS_PER_ROW * MAX_ACTION_ROWS,
+        ChoiceLayout::Menu => false,
+    };
+
+    if use_buttons {
+        RenderedChoices::Buttons(
+            available
+                .into_iter()
+                .map(|(index, label, _)| {
+                    let value = link_value(turn, index);
+                    (format!("{prefix}{PICK_NEXT_PASSAGE_BUTTON}{value}"), label)
+                })
+                .collect(),
+        )
+    } else {
+        RenderedChoices::Menu {
+            custom_id: format!("{prefix}{PICK_NEXT_PASSAGE}"),
+            options: available
+                .into_iter()
+                .map(|(index, label, choice)| MenuChoice {
+                    value: link_value(turn, index),
+                    // The full text of shortened choices, for screen readers too.
+                    description: (label != choice.text).then(|| choice.text.to_string()),
+                    label,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl RenderedPassage {
+    /// Whether the passage fits in an embed, counting what Discord counts: the title, the text,
+    /// the footer and the author of the branding.
+    fn fits_in_embed(&self) -> bool {
+        let body_length = self.body.chars().count();
+        let length = self.title.chars().count()
+            + body_length
+            + self
+                .footer
+                .as_ref()
+                .map_or(0, |footer| footer.chars().count())
+            + branding_length();
+        body_length <= MAX_EMBED_DESCRIPTION_LENGTH && length <= MAX_EMBEDS_LENGTH
+    }
+
+    /// The embed of the passage, only showing the start of the passages too long for it, with a
+    /// note telling to read the [`Self::attachment`].
+    fn embed(&self) -> CreateEmbed {
+        let mut embed = CreateEmbed::default();
+        branded(&mut embed);
+        if self.fits_in_embed() {
+            embed.title(&self.title).description(&self.body);
+        } else {
+            let excerpt = truncate_label(&self.body, PASSAGE_EXCERPT_LENGTH);
+            embed
+                .title(&self.title)
+                .description(format!("{excerpt}\n\n*{PASSAGE_IN_FILE}*"));
+        }
+        if let Some(footer) = &self.footer {
+            embed.footer(|create_footer| create_footer.text(footer));
+        }
+        embed
+    }
+
+    /// The whole passage as a text file, when it doesn't fit in an embed. The choices stay in the
+    /// components of the message.
+    fn attachment(&self) -> Option<AttachmentType<'static>> {
+        if self.fits_in_embed() {
+            return None;
+        }
+        let name: String = self
+            .title
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        Some(AttachmentType::Bytes {
+            data: format!("{}\n\n{}\n", self.title, self.body)
+                .into_bytes()
+                .into(),
+            filename: format!("{}.txt", truncate_chars(&name, 64)),
+        })
+    }
+
+    fn add_components<'a>(&self, components: &'a mut CreateComponents) -> &'a mut CreateComponents {
+        match &self.choices {
+            RenderedChoices::Buttons(buttons) => add_button_rows(components, buttons, &self.emojis),
+            RenderedChoices::Menu { custom_id, options } => {
+                add_choice_menu(components, custom_id, options, &self.emojis)
+            }
+        };
+        if self.inventory {
+            // In a row of its own, so it's never mistaken for a choice.
+            add_button_rows(
+                components,
+                &[(
+                    SHOW_INVENTORY_BUTTON.to_string(),
+                    "🎒 Inventory".to_string(),
+                )],
+                &[],
+            );
+        }
+        components
+    }
+}
+
+/// Remove the files of the message being edited, like the one of a previous passage too long for
+/// the message, which would stay otherwise. The edit builders of serenity can't do it, so the
+/// field is set on their content.
+fn remove_attachments(message: &mut HashMap<&'static str, Value>) {
+    message.insert("attachments", json!([]));
+}
+
+/// Add the buttons, as `(custom_id, label)`, wrapping to a new action row every
+/// [`MAX_BUTTONS_PER_ROW`] buttons. The buttons that don't fit in the rows left are dropped, with a
+/// warning in the logs, as Discord would reject the whole message. Only the custom `emojis` are
+/// shown as the emoji of a button.
+fn add_button_rows<'a>(
+    components: &'a mut CreateComponents,
+    buttons: &[(String, String)],
+    emojis: &[EmojiId],
+) -> &'a mut CreateComponents {
+    let capacity = MAX_ACTION_ROWS.saturating_sub(components.0.len()) * MAX_BUTTONS_PER_ROW;
+    if buttons.len() > capacity {
+        println!(
+            "[{}] Only {capacity} of {} buttons fit in the message",
+            correlation_id(),
+            buttons.len()
+        );
+    }
+
+    for row_buttons in buttons[..buttons.len().min(capacity)].chunks(MAX_BUTTONS_PER_ROW) {
+        components.create_action_row(|row| {
+            for (custom_id, label) in row_buttons {
+                row.create_button(|create_button| {
+                    create_button.custom_id(custom_id);
+                    let (emoji, label) = split_label_emoji(label, emojis);
+                    if let Some(emoji) = emoji {
+                        create_button.emoji(emoji);
+                    }
+                    // Discord rejects empty labels, but not buttons with only an emoji.
+                    if !label.is_empty() {
+                        create_button.label(truncate_label(label, MAX_BUTTON_LABEL_LENGTH));
+                    }
+                    create_button
+                });
+            }
+            row
+        });
+    }
+    components
+}
+
+/// The emoji starting the label of a choice, to show it as the emoji of its component, and the
+/// rest of the label. Emoji Discord wouldn't understand stay in the label, like custom ones
+/// missing from `emojis`.
+fn split_label_emoji<'a>(label: &'a str, emojis: &[EmojiId]) -> (Option<ReactionType>, &'a str) {
+    match split_emoji(label) {
+        (Some(emoji), rest) => match ReactionType::try_from(emoji) {
+            Ok(ReactionType::Custom { id, .. }) if !emojis.contains(&id) => (None, label),
+            Ok(emoji) => (Some(emoji), rest),
+            Err(_) => (None, label),
+        },
+        (None, label) => (None, label),
+    }
+}
+
+/// The custom emoji of the guild, the only ones the bot is sure to be able to show in its
+/// components: Discord rejects the message with the emoji of a server the bot isn't in.
+pub fn guild_emojis(ctx: &Context, guild_id: &str) -> Vec<EmojiId> {
+    guild_id
+        .parse()
+        .ok()
+        .and_then(|guild_id| {
+            ctx.cache.guild_field(GuildId(guild_id), |guild| {
+                guild.emojis.keys().copied().collect()
+            })
+        })
+        .unwrap_or_default()
+}
+
+/// The choices in a select menu, the ones past [`MAX_MENU_OPTIONS`] being dropped with a warning
+/// in the logs.
+fn add_choice_menu<'a>(
+    components: &'a mut CreateComponents,
+    custom_id: &str,
+    choices: &[MenuChoice],
+    emojis: &[EmojiId],
+) -> &'a mut CreateComponents {
+    if components.0.len() >= MAX_ACTION_ROWS {
+        println!("[{}] No action row left for the menu", correlation_id());
+        return components;
+    }
+    if choices.len() > MAX_MENU_OPTIONS {
+        println!(
+            "[{}] Only {MAX_MENU_OPTIONS} of {} choices fit in the menu",
+            correlation_id(),
+            choices.len()
+        );
+    }
+
+    components.create_action_row(|row| {
+        row.create_select_menu(|menu| {
+            menu.custom_id(custom_id)
+                .placeholder("Next chapter")
+                .options(|mut options| {
+                    for choice in choices.iter().take(MAX_MENU_OPTIONS) {
+                        options = options.create_option(|create_option| {
+                            // Options need a label, so an emoji alone stays in the label.
+                            let option_label = match split_label_emoji(&choice.label, emojis) {
+                                (Some(emoji), rest) if !rest.is_empty() => {
+                                    create_option.emoji(emoji);
+                                    rest
+                                }
+                                _ => choice.label.as_str(),
+                            };
+                            create_option
+                                .label(truncate_label(option_label, MAX_OPTION_LABEL_LENGTH))
+                                .value(&choice.value);
+                            if let Some(description) = &choice.description {
+                                create_option.description(truncate_label(
+                                    description,
+                                    MAX_OPTION_LABEL_LENGTH,
+                                ));
+                            }
+                            create_option
+                        });
+                    }
+                    options
+                })
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        sync::{Arc, Mutex},
+    };
+
+    use tempfile::TempDir;
+
+    use crate::random::{SeededRng, ThreadRng};
+
+    use super::*;
+
+    /// The longest custom id Discord accepts.
+    const MAX_CUSTOM_ID_LENGTH: usize = 100;
+
+    fn story(passages: &str) -> Story<String> {
+        let content =
+            format!(":: StoryTitle\nStory\n\n:: StoryData\n{{\"start\": \"Start\"}}\n\n{passages}");
+        Story::try_from(content).unwrap()
+    }
+
+    fn options(layout: ChoiceLayout) -> ComponentsOptions {
+        ComponentsOptions {
+            layout,
+            random: false,
+            single_message: false,
+            preview: false,
+            inventory: false,
+        }
+    }
+
+    fn game_state(passage: &str, turn: u64) -> GameState {
+        GameState {
+            turn,
+            ..GameState::new(
+                "player".to_string(),
+                "guild".to_string(),
+                1,
+                passage.to_string(),
+            )
+        }
+    }
+
+    fn is_invalid_selection(error: &anyhow::Error) -> bool {
+        matches!(error.downcast_ref(), Some(UserError::InvalidSelection))
+    }
+
+    fn rendered(passages: &str) -> RenderedPassage {
+        let story = story(passages);
+        let passage = story.get_passage("Start").unwrap();
+        render(
+            &story,
+            &passage,
+            &game_state("Start", 0),
+            options(ChoiceLayout::Buttons),
+            None,
+            vec![],
+        )
+    }
+
+    #[test]
+    fn passages_too_long_for_the_embed_are_sent_as_a_file() {
+        let body = "It was a dark and stormy night. ".repeat(200);
+        let rendered = rendered(&format!(":: Start\n{body}\n[[End]]\n\n:: End\nThe end\n"));
+
+        let Some(AttachmentType::Bytes { data, filename }) = rendered.attachment() else {
+            panic!("Expected a file");
+        };
+        assert_eq!(filename, "Start.txt");
+        let text = String::from_utf8(data.into_owned()).unwrap();
+        assert!(text.starts_with("Start\n\nIt was a dark and stormy night."));
+        assert!(text.contains(body.trim()));
+        let description = rendered.embed().0["description"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(description.ends_with(&format!("*{PASSAGE_IN_FILE}*")));
+        assert!(description.chars().count() < PASSAGE_EXCERPT_LENGTH + PASSAGE_IN_FILE.len() + 10);
+    }
+
+    #[test]
+    fn passages_fitting_in_the_embed_have_no_file() {
+        let rendered = rendered(":: Start\nA short night.\n[[End]]\n\n:: End\nThe end\n");
+
+        assert!(rendered.attachment().is_none());
+        let embed = rendered.embed();
+        assert!(embed.0["description"]
+            .as_str()
+            .unwrap()
+            .starts_with("A short night."));
+    }
+
+    #[test]
+    fn edited_messages_lose_their_files() {
+        let mut message = HashMap::from([("attachments", json!([{ "id": 1 }]))]);
+        remove_attachments(&mut message);
+        assert_eq!(message["attachments"], json!([]));
+    }
+
+    fn progress(played: bool, completed: bool) -> StoryProgress {
+        StoryProgress { played, completed }
+    }
+
+    fn story_details(id: i64) -> StoryDetails {
+        StoryDetails {
+            id,
+            name: format!("Story {id}"),
+            author: None,
+            rating: None,
+            created_at: None,
+            modified_at: None,
+        }
+    }
+
+    #[test]
+    fn a_single_story_starts_without_a_menu() {
+        let choice = story_choice(vec![story_details(1)]).unwrap();
+        assert!(matches!(choice, StoryChoice::Only(story) if story.id == 1));
+    }
+
+    #[test]
+    fn several_stories_are_picked_from_a_menu() {
+        let choice = story_choice(vec![story_details(1), story_details(2)]).unwrap();
+        let StoryChoice::Menu(stories) = choice else {
+            panic!("the stories should be in a menu");
+        };
+        let ids: Vec<i64> = stories.iter().map(|story| story.id).collect();
+        assert_eq!(ids, [1, 2]);
+    }
+
+    #[test]
+    fn no_stories_can_be_played_without_any() {
+        let error = story_choice(vec![]).err().unwrap();
+        assert!(matches!(error.downcast_ref(), Some(UserError::NoStories)));
+    }
+
+    #[test]
+    fn series_unlock_a_story_at_a_time() {
+        let unplayed = progress(false, false);
+        let completed = progress(true, true);
+
+        assert_eq!(
+            unlocked_series_stories(&[unplayed, progress(false, false)]),
+            [true, false]
+        );
+        assert_eq!(
+            unlocked_series_stories(&[completed, progress(false, false), progress(false, false)]),
+            [true, true, false]
+        );
+        assert!(unlocked_series_stories(&[]).is_empty());
+    }
+
+    #[test]
+    fn started_stories_of_a_series_stay_unlocked() {
+        // Played before the series was put together, or before a story was added before it.
+        let stories = [
+            progress(false, false),
+            progress(false, false),
+            progress(true, false),
+            progress(false, false),
+        ];
+
+        assert_eq!(
+            unlocked_series_stories(&stories),
+            [true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn passages_render_their_text_and_the_choices_the_player_can_take() {
+        let story = story(concat!(
+            ":: Start\n(set: $gold to 5)You have (print: $gold) gold.\n",
+            "(requires: $gold >= 10)[[Buy the sword->Shop]]\n[[Leave]]\n[[Stay]]\n\n",
+            ":: Shop\nS\n\n:: Leave\nL\n\n:: Stay\nS\n"
+        ));
+        let passage = story.get_passage("Start").unwrap();
+        let rendered = render(
+            &story,
+            &passage,
+            &game_state("Start", 2),
+            options(ChoiceLayout::Buttons),
+            Some("Page 1"),
+            vec![],
+        );
+
+        assert_eq!(rendered.title, "Start");
+        assert!(rendered.body.contains("You have 5 gold."));
+        assert_eq!(rendered.footer.as_deref(), Some("Page 1"));
+        let RenderedChoices::Buttons(buttons) = rendered.choices else {
+            panic!("the choices should be buttons");
+        };
+        // The choices keep their index among all the links of the passage.
+        assert_eq!(
+            buttons,
+            [
+                (
+                    format!("{PICK_NEXT_PASSAGE_BUTTON}2:1"),
+                    "Leave".to_string()
+                ),
+                (format!("{PICK_NEXT_PASSAGE_BUTTON}2:2"), "Stay".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn passages_render_a_menu_for_several_choices_by_default() {
+        let story = story(":: Start\n[[Left]]\n[[Right]]\n\n:: Left\nL\n\n:: Right\nR\n");
+        let passage = story.get_passage("Start").unwrap();
+        let rendered = render(
+            &story,
+            &passage,
+            &game_state("Start", 1),
+            options(ChoiceLayout::Auto),
+            None,
+            vec![],
+        );
+
+        let RenderedChoices::Menu { custom_id, options } = rendered.choices else {
+            panic!("the choices should be a menu");
+        };
+        assert_eq!(custom_id, PICK_NEXT_PASSAGE);
+        let values: Vec<(&str, &str)> = options
+            .iter()
+            .map(|option| (option.value.as_str(), option.label.as_str()))
+            .collect();
+        assert_eq!(values, [("1:0", "Left"), ("1:1", "Right")]);
+    }
+
+    #[test]
+    fn passages_without_choices_render_the_end() {
+        let rendered = rendered(":: Start\nThe end.\n");
+
+        assert!(!rendered.inventory);
+        let RenderedChoices::Buttons(buttons) = rendered.choices else {
+            panic!("the end should be a button");
+        };
+        assert_eq!(buttons, [(THE_END.to_string(), "The end".to_string())]);
+    }
+
+    #[test]
+    fn emoji_starting_links_are_shown_as_the_emoji_of_their_button() {
+        let story = story(concat!(
+            ":: Start\n[[🗡️ Fight->End]]\n[[<:sword:600404340292059257> Parry->End]]\n",
+            "[[<:shield:700> Block->End]]\n[[✓ Done->End]]\n[[End]]\n\n:: End\nThe end\n"
+        ));
+        let passage = story.get_passage("Start").unwrap();
+        let rendered = render(
+            &story,
+            &passage,
+            &game_state("Start", 0),
+            options(ChoiceLayout::Buttons),
+            None,
+            vec![EmojiId(600404340292059257)],
+        );
+        let mut components = CreateComponents::default();
+        rendered.add_components(&mut components);
+
+        let buttons = components.0[0]["components"].as_array().unwrap();
+        let shown: Vec<(&str, Option<&str>)> = buttons
+            .iter()
+            .map(|button| {
+                (
+                    button["label"].as_str().unwrap(),
+                    button["emoji"]["name"].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            shown,
+            [
+                ("Fight", Some("🗡️")),
+                ("Parry", Some("sword")),
+                // Emoji of other servers would be rejected.
+                ("<:shield:700> Block", None),
+                ("✓ Done", None),
+                ("End", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_to_long_titles_fit_in_custom_ids() {
+        let title = "A very long passage title ".repeat(8);
+        let story = story(&format!(
+            ":: Start\n[[Go->{title}]]\n[[Stay]]\n\n:: {title}\nFar\n\n:: Stay\nHere\n"
+        ));
+        let passage = story.get_passage("Start").unwrap();
+
+        let RenderedChoices::Buttons(buttons) = rendered_choices(
+            &passage,
+            &HashMap::new(),
+            12,
+            options(ChoiceLayout::Buttons),
+        ) else {
+            panic!("Expected buttons");
+        };
+        let custom_ids: Vec<&str> = buttons.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(
+            custom_ids,
+            [
+                "pick_next_passage_button12:0",
+                "pick_next_passage_button12:1"
+            ]
+        );
+        assert!(custom_ids.iter().all(|id| id.len() <= MAX_CUSTOM_ID_LENGTH));
+
+        let RenderedChoices::Menu { options, .. } =
+            rendered_choices(&passage, &HashMap::new(), 12, options(ChoiceLayout::Menu))
+        else {
+            panic!("Expected a menu");
+        };
+        assert_eq!(options[0].value, "12:0");
+        let destination = Destination::Link { turn: 12, index: 0 };
+        let target = destination_target(
+            &story,
+            &game_state("Start", 12),
+            &destination,
+            &SeededRng::new(0),
+        );
+        assert_eq!(target.unwrap(), Some((title, None)));
+    }
+
+    #[test]
+    fn link_values_are_parsed() {
+        assert_eq!(parse_link_value(&link_value(3, 1)), Some((3, 1)));
+        assert_eq!(parse_link_value("1"), None);
+        assert_eq!(parse_link_value("a:1"), None);
+        assert_eq!(parse_link_value("1:-1"), None);
+    }
+
+    #[test]
+    fn links_of_previous_passages_are_rejected() {
+        let story = story(":: Start\n[[Left]]\n[[Right]]\n\n:: Left\nL\n\n:: Right\nR\n");
+        let rng = SeededRng::new(0);
+        let game_state = game_state("Start", 4);
+
+        let stale = Destination::Link { turn: 3, index: 1 };
+        let error = destination_target(&story, &game_state, &stale, &rng).unwrap_err();
+        assert!(is_invalid_selection(&error));
+        let stale = Destination::RandomLink { turn: 3 };
+        let error = destination_target(&story, &game_state, &stale, &rng).unwrap_err();
+        assert!(is_invalid_selection(&error));
+
+        let current = Destination::Link { turn: 4, index: 1 };
+        let target = destination_target(&story, &game_state, &current, &rng).unwrap();
+        assert_eq!(target, Some(("Right".to_string(), None)));
+    }
+
+    /// A passage timing out to its first link, the one giving gold.
+    const TIMED_STORY: &str =
+        ":: Start\n(effects: $gold += 5)[[Wait]]\n[[Run]]\n\n:: Wait\nW\n[[Start]]\n\n:: Run\nR\n[[Start]]\n";
+
+    fn storage(folder: &TempDir) -> Storage<String> {
+        let path = folder.path().to_str().unwrap().to_string();
+        Storage::new(path, Duration::from_secs(60)).unwrap()
+    }
+
+    /// Save the story in the guild, and start a new session of the player on it.
+    fn start(storage: &mut Storage<String>, passages: &str) -> GameState {
+        let content =
+            format!(":: StoryTitle\nStory\n\n:: StoryData\n{{\"start\": \"Start\"}}\n\n{passages}");
+        storage.save_story("guild", &content).unwrap();
+        let story_id = storage.find_guild_story("guild", "Story").unwrap().unwrap();
+        let game_state = GameState {
+            story_version: storage.story_modified_at(story_id).unwrap(),
+            ..GameState::new(
+                "player".to_string(),
+                "guild".to_string(),
+                story_id,
+                "Start".to_string(),
+            )
+        };
+        storage.update_game_state(&game_state).unwrap();
+        game_state
+    }
+
+    fn claim(
+        storage: &mut Storage<String>,
+        destination: Destination,
+        timed_out: Option<&TimedOut>,
+    ) -> Result<Option<Move>> {
+        let rng = SeededRng::new(0);
+        claim_move(storage, "player", "guild", &destination, timed_out, &rng)
+    }
+
+    fn timed_out(game_state: &GameState) -> TimedOut {
+        TimedOut {
+            session_id: game_state.session_id.clone(),
+            story_id: game_state.story_id,
+            turn: game_state.turn,
+            message: None,
+        }
+    }
+
+    #[test]
+    fn new_sessions_replace_the_one_of_the_player() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let game_state = start(&mut storage, TIMED_STORY);
+
+        let restarted = GameState {
+            current_chapter: "Run".to_string(),
+            ..game_state
+        };
+        start_new_session(&mut storage, &restarted, 1).unwrap();
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.current_chapter, "Run");
+        assert_eq!(storage.player_session_count("player", "guild").unwrap(), 1);
+    }
+
+    #[test]
+    fn new_sessions_are_refused_over_the_cap() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let game_state = start(&mut storage, TIMED_STORY);
+
+        let error = start_new_session(&mut storage, &game_state, 0).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(UserError::TooManySessions(0))
+        ));
+    }
+
+    #[test]
+    fn sessions_start_from_the_chosen_passage() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let story_id = start(&mut storage, TIMED_STORY).story_id;
+        let story = storage.get_story(story_id).unwrap();
+        assert_eq!(start_passage(&storage, story_id, &story).unwrap(), "Start");
+
+        choose_start_passage(&storage, story_id, &story, "Run").unwrap();
+        assert_eq!(start_passage(&storage, story_id, &story).unwrap(), "Run");
+    }
+
+    #[test]
+    fn unknown_start_passages_are_rejected() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let story_id = start(&mut storage, TIMED_STORY).story_id;
+        let story = storage.get_story(story_id).unwrap();
+
+        let error = choose_start_passage(&storage, story_id, &story, "Nowhere").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(UserError::UnknownPassage(passage)) if passage == "Nowhere"
+        ));
+        assert_eq!(start_passage(&storage, story_id, &story).unwrap(), "Start");
+    }
+
+    #[test]
+    fn sessions_start_from_the_start_when_the_chosen_passage_was_removed() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let story_id = start(&mut storage, TIMED_STORY).story_id;
+        let story = storage.get_story(story_id).unwrap();
+        choose_start_passage(&storage, story_id, &story, "Run").unwrap();
+
+        start(&mut storage, ":: Start\nS\n[[Wait]]\n\n:: Wait\nW\n");
+        let story = storage.get_story(story_id).unwrap();
+        assert_eq!(start_passage(&storage, story_id, &story).unwrap(), "Start");
+    }
+
+    #[test]
+    fn goto_starts_a_session_at_the_passage() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        start(&mut storage, TIMED_STORY);
+
+        let game_state = go_to_passage(
+            &mut storage,
+            "admin".to_string(),
+            "guild".to_string(),
+            "Story",
+            "Run",
+            1,
+        )
+        .unwrap();
+        assert_eq!(game_state.current_chapter, "Run");
+
+        let saved = storage.retrieve_game_state("admin", "guild").unwrap();
+        assert_eq!(saved.current_chapter, "Run");
+        assert_eq!(saved.turn, 0);
+    }
+
+    #[test]
+    fn goto_rejects_unknown_passages() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        start(&mut storage, TIMED_STORY);
+
+        let error = go_to_passage(
+            &mut storage,
+            "admin".to_string(),
+            "guild".to_string(),
+            "Story",
+            "Nowhere",
+            1,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(UserError::UnknownPassage(passage)) if passage == "Nowhere"
+        ));
+        assert!(storage.retrieve_game_state("admin", "guild").is_err());
+    }
+
+    #[test]
+    fn timeouts_take_the_first_available_link() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let game_state = start(&mut storage, TIMED_STORY);
+
+        let timed_out = timed_out(&game_state);
+        let moved = claim(&mut storage, Destination::FirstLink, Some(&timed_out)).unwrap();
+        assert_eq!(moved.unwrap().shown.current_chapter, "Wait");
+
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.current_chapter, "Wait");
+        assert_eq!(saved.turn, 1);
+        assert_eq!(saved.variables["gold"], "5");
+    }
+
+    #[test]
+    fn choices_cancel_the_timeout() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let game_state = start(&mut storage, TIMED_STORY);
+        let timed_out = timed_out(&game_state);
+
+        let run = Destination::Link { turn: 0, index: 1 };
+        assert!(claim(&mut storage, run, None).unwrap().is_some());
+        let moved = claim(&mut storage, Destination::FirstLink, Some(&timed_out)).unwrap();
+        assert!(moved.is_none());
+
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.current_chapter, "Run");
+        assert_eq!(saved.turn, 1);
+        assert!(saved.variables.is_empty());
+    }
+
+    #[test]
+    fn choices_are_taken_once() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        start(&mut storage, TIMED_STORY);
+
+        let wait = Destination::Link { turn: 0, index: 0 };
+        assert!(claim(&mut storage, wait, None).unwrap().is_some());
+        let again = Destination::Link { turn: 0, index: 0 };
+        let error = claim(&mut storage, again, None).err().unwrap();
+        assert!(is_invalid_selection(&error));
+
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.turn, 1);
+        assert_eq!(saved.variables["gold"], "5");
+    }
+
+    #[test]
+    fn timeouts_of_previous_sessions_are_ignored() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let previous = start(&mut storage, TIMED_STORY);
+        let timed_out = timed_out(&previous);
+        start(&mut storage, TIMED_STORY);
+
+        let moved = claim(&mut storage, Destination::FirstLink, Some(&timed_out)).unwrap();
+        assert!(moved.is_none());
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.current_chapter, "Start");
+    }
+
+    #[test]
+    fn timeouts_only_run_while_the_interaction_can_be_answered() {
+        let created_at = 1_000_000;
+        let timeout = Duration::from_secs(10 * 60);
+
+        assert!(answerable_after(created_at, created_at, timeout));
+        assert!(answerable_after(created_at, created_at + 4 * 60, timeout));
+        assert!(!answerable_after(created_at, created_at + 5 * 60, timeout));
+    }
+
+    /// A clock only noting what it waits for, in the steps shared with the test.
+    struct StepClock(Arc<Mutex<Vec<String>>>);
+
+    impl Clock for StepClock {
+        fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("sleep {}ms", duration.as_millis()));
+            Box::pin(async {})
+        }
+    }
+
+    #[tokio::test]
+    async fn components_are_removed_before_the_delay() {
+        let steps = Arc::new(Mutex::new(vec![]));
+        let clock = StepClock(steps.clone());
+
+        let remove_components = async {
+            steps.lock().unwrap().push("remove".to_string());
+            Ok(())
+        };
+        remove_then_wait(&clock, Duration::from_millis(1500), remove_components)
+            .await
+            .unwrap();
+
+        assert_eq!(*steps.lock().unwrap(), ["remove", "sleep 1500ms"]);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_waited_for_without_delay() {
+        let steps = Arc::new(Mutex::new(vec![]));
+        let clock = StepClock(steps.clone());
+
+        remove_then_wait(&clock, Duration::ZERO, async { Ok(()) })
+            .await
+            .unwrap();
+
+        assert!(steps.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn links_to_passages_removed_by_an_update_restart_the_session() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        start(
+            &mut storage,
+            ":: Start\n[[Cave]]\n\n:: Cave\nDark\n[[Start]]\n",
+        );
+        let game_state = GameState {
+            variables: HashMap::from([("torch".to_string(), "true".to_string())]),
+            // Saved before the update.
+            story_version: Some(0),
+            ..storage.retrieve_game_state("player", "guild").unwrap()
+        };
+        storage.update_game_state(&game_state).unwrap();
+        start(
+            &mut storage,
+            ":: Start\n[[Cave]]\n\n:: Forest\nGreen\n[[Start]]\n",
+        );
+        storage.update_game_state(&game_state).unwrap();
+
+        let cave = Destination::Link { turn: 0, index: 0 };
+        let moved = claim(&mut storage, cave, None).unwrap().unwrap();
+        assert!(moved.restarted);
+        assert_eq!(moved.shown.current_chapter, "Start");
+
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.current_chapter, "Start");
+        assert!(saved.variables.is_empty());
+        assert!(saved.broken_link.is_none());
+    }
+
+    #[test]
+    fn links_to_missing_passages_are_broken() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        start(&mut storage, ":: Start\n[[Cave]]\n");
+
+        let cave = Destination::Link { turn: 0, index: 0 };
+        let error = claim(&mut storage, cave, None).err().unwrap();
+        assert!(matches!(error.downcast_ref(), Some(UserError::BrokenLink)));
+        let saved = storage.retrieve_game_state("player", "guild").unwrap();
+        assert_eq!(saved.broken_link.as_deref(), Some("Cave"));
+    }
+
+    #[test]
+    fn random_links_depend_on_the_seed() {
+        let links = ["North", "South", "East", "West"];
+        let picks = |seed| -> Vec<&str> {
+            let rng = SeededRng::new(seed);
+            (0..8)
+                .map(|_| *pick_random_link(&links, &rng).unwrap())
+                .collect()
+        };
+
+        assert_eq!(picks(7), picks(7));
+        assert!((0..16).any(|seed| picks(seed) != picks(7)));
+        let rng = SeededRng::new(7);
+        assert_eq!(pick_random_link::<&str>(&[], &rng), None);
+    }
+
+    #[test]
+    fn interactions_pick_the_same_random_link() {
+        let links = ["North", "South", "East", "West"];
+        let pick = |rng: &dyn Rng, interaction| {
+            let rng = rng.for_interaction(interaction);
+            (0..8)
+                .map(|_| *pick_random_link(&links, &rng).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let unseeded = ThreadRng;
+        assert_eq!(pick(&unseeded, 42), pick(&unseeded, 42));
+        // Seeded, the interactions pick the same links on every run.
+        let (seeded, rerun) = (SeededRng::new(7), SeededRng::new(7));
+        assert_eq!(pick(&seeded, 42), pick(&rerun, 43));
+    }
+
+    /// Save a story warning about spiders as a whole, and about the dark in the cave.
+    fn warned_story(storage: &mut Storage<String>) -> i64 {
+        let content = ":: StoryTitle\nWarned\n\n:: StoryData\n{\"start\": \"Start\", \"warnings\": [\"spiders\"]}\n\n:: Start\n[[Cave]]\n\n:: Cave [cw:dark cw:spiders]\nDark\n[[Start]]\n";
+        storage.save_story("guild", content).unwrap();
+        storage
+            .find_guild_story("guild", "Warned")
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn story_warnings_come_first_whatever_the_first_passage() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let story_id = warned_story(&mut storage);
+        // Like after `/setstart Cave`, or `/goto Cave`.
+        let game_state = GameState::new(
+            "player".to_string(),
+            "guild".to_string(),
+            story_id,
+            "Cave".to_string(),
+        );
+        storage.start_session(&game_state).unwrap();
+
+        let warnings = unacknowledged_warnings(&mut storage, &game_state, "Cave").unwrap();
+        assert_eq!(warnings, ["spiders", "dark"]);
+        let warnings = unacknowledged_warnings(&mut storage, &game_state, "Start").unwrap();
+        assert_eq!(warnings, ["spiders"]);
+
+        storage
+            .acknowledge_warning("player", "guild", STORY_WARNINGS)
+            .unwrap();
+        let warnings = unacknowledged_warnings(&mut storage, &game_state, "Cave").unwrap();
+        assert_eq!(warnings, ["dark", "spiders"]);
+        let warnings = unacknowledged_warnings(&mut storage, &game_state, "Start").unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn new_sessions_show_the_story_warnings_again() {
+        let folder = TempDir::new().unwrap();
+        let mut storage = storage(&folder);
+        let story_id = warned_story(&mut storage);
+        let game_state = GameState::new(
+            "player".to_string(),
+            "guild".to_string(),
+            story_id,
+            "Start".to_string(),
+        );
+        storage.start_session(&game_state).unwrap();
+        storage
+            .acknowledge_warning("player", "guild", STORY_WARNINGS)
+            .unwrap();
+        assert!(unacknowledged_warnings(&mut storage, &game_state, "Start")
+            .unwrap()
+            .is_empty());
+
+        storage.start_session(&game_state).unwrap();
+        let warnings = unacknowledged_warnings(&mut storage, &game_state, "Start").unwrap();
+        assert_eq!(warnings, ["spiders"]);
     }
 }
@@ -0,0 +1,46 @@
+use std::sync::Mutex;
+
+use rand::{rngs::StdRng, Rng as _, SeedableRng};
+
+/// The randomness of the stories, like the links followed at random.
+pub trait Rng: Send + Sync {
+    /// A number in `0..n`, `n` being greater than 0.
+    fn below(&self, n: usize) -> usize;
+
+    /// The randomness of an interaction. Real randomness is seeded by the id of the interaction, so
+    /// it picks the same numbers when it's retried, while seeded randomness stays the same on every
+    /// run.
+    fn for_interaction(&self, interaction_id: u64) -> SeededRng;
+}
+
+/// Real randomness, from the thread local generator.
+pub struct ThreadRng;
+
+impl Rng for ThreadRng {
+    fn below(&self, n: usize) -> usize {
+        rand::thread_rng().gen_range(0..n)
+    }
+
+    fn for_interaction(&self, interaction_id: u64) -> SeededRng {
+        SeededRng::new(interaction_id)
+    }
+}
+
+/// Deterministic randomness: the same seed always gives the same numbers, in the same order.
+pub struct SeededRng(Mutex<StdRng>);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self(Mutex::new(StdRng::seed_from_u64(seed)))
+    }
+}
+
+impl Rng for SeededRng {
+    fn below(&self, n: usize) -> usize {
+        self.0.lock().expect("rng lock poisoned").gen_range(0..n)
+    }
+
+    fn for_interaction(&self, _interaction_id: u64) -> SeededRng {
+        SeededRng::new(self.0.lock().expect("rng lock poisoned").gen())
+    }
+}
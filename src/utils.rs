@@ -1,10 +1,2045 @@
-use twee_v3::Story;
+use std::{collections::HashMap, fmt::Display, time::Duration};
+
+use twee_v3::{ContentNode, Passage, Story};
+
+use crate::random::Rng;
+
+/// Tags prefixed with this mark a passage as needing a content warning, e.g. `cw:violence`.
+pub const CONTENT_WARNING_TAG: &str = "cw:";
+
+/// Passages tagged with this are intended endings of the story.
+pub const ENDING_TAG: &str = "ending";
+
+/// Passages tagged with this continue to one of their links picked at random.
+pub const RANDOM_TAG: &str = "random";
+
+/// When a story tags passages with this, the progress of the players is only saved when they
+/// reach one, and sessions resume from the last one they reached.
+pub const CHECKPOINT_TAG: &str = "checkpoint";
+
+/// Tags prefixed with this move the player to the first link of the passage if they don't choose
+/// within that many seconds, e.g. `timeout:30`.
+pub const TIMEOUT_TAG_PREFIX: &str = "timeout:";
+
+/// The longest timeout of a passage, in seconds.
+const MAX_PASSAGE_TIMEOUT: u64 = 10 * 60;
+
+/// The stars of the stories nobody rated yet, for `/surprise`.
+const UNRATED_STARS: f64 = 3.0;
+
+/// How many days after being played a story is half as likely to be recommended again as one
+/// never played.
+const RECOMMENDATION_HALF_DAYS: f64 = 7.0;
+
+/// The passages holding the metadata of the story, rather than a part of it.
+const STORY_TITLE: &str = "StoryTitle";
+const STORY_DATA: &str = "StoryData";
+/// Credits the author of the story, unless the `StoryData` has an `author`.
+const AUTHOR: &str = "Author";
+
+/// Discord rejects select menu options with a label longer than this, in characters.
+pub const MAX_OPTION_LABEL_LENGTH: usize = 100;
+
+/// Discord rejects buttons with a label longer than this, in characters.
+pub const MAX_BUTTON_LABEL_LENGTH: usize = 80;
+
+/// Discord shows at most 25 choices in a message: 5 rows of 5 buttons, or the options of a
+/// select menu. The links past them can't be taken.
+pub const MAX_CHOICES: usize = 25;
+
+/// What twee_v3 doesn't expose about a passage: its title and tags, as found in its header.
+pub struct PassageInfo {
+    pub title: String,
+    pub tags: Vec<String>,
+}
 
 pub fn story_title(story: &str) -> Option<String> {
-    let story = Story::try_from(story);
-    if story.is_err() {
+    let story = Story::try_from(story).ok()?;
+    story.title().map(ToString::to_string)
+}
+
+/// Written right before a link, with only whitespace in between, these macros apply to it, see
+/// [`Choice`].
+const REQUIRES: &str = "(requires:";
+const EFFECTS: &str = "(effects:";
+
+/// A link of a passage. Authors can give it a short label for its button or menu option with a
+/// `|label` suffix, like `[[Open the heavy wooden door->Cellar|Open the door]]`.
+///
+/// Macros right before the link can make it cost something, like
+/// `(requires: $gold >= 10)(effects: $gold -= 10, $sword to true)[[Buy the sword->Shop]]`:
+/// * `(requires: condition)` only offers the choice when the condition holds, written like the
+///   ones of `(if:)`.
+/// * `(effects: ...)` changes the variables when the choice is taken, see [`apply_effects`].
+pub struct Choice<'a> {
+    pub text: &'a str,
+    pub target: &'a str,
+    label: Option<&'a str>,
+    requires: Option<&'a str>,
+    pub effects: Option<&'a str>,
+}
+
+impl<'a> Choice<'a> {
+    pub fn new(text: &'a str, target: &'a str) -> Self {
+        // twee_v3 splits links at the pipe first, leaving the arrow in the text. An arrow without
+        // something on both sides, like in `[[<- Back|Hall]]`, is only part of the text.
+        let arrow = text
+            .rsplit_once("->")
+            .or_else(|| {
+                text.split_once("<-")
+                    .map(|(link_target, text)| (text, link_target))
+            })
+            .map(|(text, link_target)| (text.trim(), link_target.trim()))
+            .filter(|(text, link_target)| !text.is_empty() && !link_target.is_empty());
+        if let Some((text, link_target)) = arrow {
+            return Self {
+                text,
+                target: link_target,
+                label: Some(target),
+                requires: None,
+                effects: None,
+            };
+        }
+        Self {
+            text,
+            target,
+            label: None,
+            requires: None,
+            effects: None,
+        }
+    }
+
+    /// Whether the player can take the choice, its `(requires:)` holding.
+    pub fn available(&self, variables: &HashMap<String, String>) -> bool {
+        self.requires
+            .is_none_or(|condition| condition_holds(condition, variables))
+    }
+
+    /// The label given by the author, or else the text without markdown, cut at a word boundary
+    /// to fit in a button.
+    pub fn label(&self) -> String {
+        if let Some(label) = self.label {
+            return label.trim().to_string();
+        }
+
+        let text: String = self
+            .text
+            .chars()
+            .filter(|c| !matches!(c, '*' | '_' | '~' | '`'))
+            .collect();
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.chars().count() <= MAX_BUTTON_LABEL_LENGTH {
+            return text;
+        }
+        let cut = truncate_chars(&text, MAX_BUTTON_LABEL_LENGTH - 1);
+        match cut.rsplit_once(' ') {
+            Some((words, _)) if !words.is_empty() => format!("{words}…"),
+            _ => format!("{cut}…"),
+        }
+    }
+}
+
+/// The links of the passage, with the macros before them, see [`Choice`].
+pub fn choices<'a>(passage: &'a Passage<&'a str>) -> Vec<Choice<'a>> {
+    let mut choices = vec![];
+    let mut preceding = "";
+    for node in passage.nodes() {
+        match node {
+            ContentNode::Text(text) => preceding = text,
+            ContentNode::Link { text, target } => {
+                let (_, requires, effects) = split_link_macros(preceding);
+                choices.push(Choice {
+                    requires,
+                    effects,
+                    ..Choice::new(text, target)
+                });
+                preceding = "";
+            }
+        }
+    }
+    choices
+}
+
+/// The text before a link, without the macros applying to the link, which aren't shown.
+pub fn strip_link_macros(text: &str) -> &str {
+    split_link_macros(text).0
+}
+
+/// Split the `(requires:)` and `(effects:)` ending the text from it, returning the text and their
+/// arguments. Only the last of each is kept.
+fn split_link_macros(text: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (mut requires, mut effects) = (None, None);
+    let mut rest = text;
+    loop {
+        let trimmed = rest.trim_end();
+        let found = [REQUIRES, EFFECTS].into_iter().find_map(|name| {
+            let start = trimmed.rfind(name)?;
+            let arguments = &trimmed[start + name.len()..];
+            let end = macro_end(arguments)?;
+            (end == arguments.len() - 1).then_some((name, start, &arguments[..end]))
+        });
+        let Some((name, start, arguments)) = found else {
+            return (rest, requires, effects);
+        };
+        let kept = if name == REQUIRES {
+            &mut requires
+        } else {
+            &mut effects
+        };
+        kept.get_or_insert(arguments.trim());
+        rest = &trimmed[..start];
+    }
+}
+
+/// Apply the effects of a choice, like `$gold -= 10, $sword to true`. `to` sets the variable like
+/// `(set:)` does, while `+=` and `-=` add to and subtract from its number, an unset variable
+/// counting as 0. Malformed effects, or arithmetic on what isn't a number, are skipped.
+pub fn apply_effects(effects: &str, variables: &mut HashMap<String, String>) {
+    for effect in split_arguments(effects) {
+        let effect = effect.trim();
+        if let Some((name, value)) = effect.split_once(" to ") {
+            if let Some(name) = name.trim().strip_prefix('$') {
+                variables.insert(name.to_string(), unquote(value.trim()).to_string());
+            }
+            continue;
+        }
+        for (operator, sign) in [(" += ", 1.0), (" -= ", -1.0)] {
+            let Some((name, value)) = effect.split_once(operator) else {
+                continue;
+            };
+            let Some(name) = name.trim().strip_prefix('$') else {
+                continue;
+            };
+            if let (Some(current), Ok(value)) = (
+                number_value(variables, name),
+                unquote(value.trim()).parse::<f64>(),
+            ) {
+                variables.insert(name.to_string(), (current + sign * value).to_string());
+            }
+        }
+    }
+}
+
+/// The value of the variable as a number, an unset variable counting as 0.
+fn number_value(variables: &HashMap<String, String>, name: &str) -> Option<f64> {
+    match variables.get(name) {
+        Some(value) => value.trim().parse().ok(),
+        None => Some(0.0),
+    }
+}
+
+/// Split the arguments of a macro at the commas, ignoring the ones in quotes.
+fn split_arguments(arguments: &str) -> Vec<&str> {
+    let mut split = vec![];
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in arguments.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, ',') => {
+                split.push(&arguments[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&arguments[start..]);
+    split
+}
+
+/// List the passages of a twee story by looking at their headers, `:: Title [tags] {metadata}`.
+/// The special `StoryTitle` and `StoryData` passages are skipped.
+pub fn passages_info(story: &str) -> Vec<PassageInfo> {
+    story
+        .lines()
+        .filter_map(|line| line.strip_prefix("::"))
+        .filter_map(|header| {
+            let (title, rest) = split_unescaped(header, &['[', '{']);
+            let title = unescape(title.trim());
+            if title.is_empty() || [STORY_TITLE, STORY_DATA, AUTHOR].contains(&title.as_str()) {
+                return None;
+            }
+            let tags = match rest.strip_prefix('[') {
+                Some(rest) => {
+                    let (tags, _) = split_unescaped(rest, &[']']);
+                    tags.split_whitespace().map(unescape).collect()
+                }
+                None => vec![],
+            };
+            Some(PassageInfo { title, tags })
+        })
+        .collect()
+}
+
+/// The passages without any outgoing link, with whether they are tagged as an ending.
+/// The untagged ones are probably dead ends.
+pub fn ending_passages(story: &str) -> Vec<(String, bool)> {
+    let Ok(parsed) = Story::try_from(story) else {
+        return vec![];
+    };
+    passages_info(story)
+        .into_iter()
+        .filter(|info| {
+            parsed
+                .get_passage(&info.title)
+                .is_some_and(|passage| passage.links().next().is_none())
+        })
+        .map(|info| {
+            let tagged = info.tags.iter().any(|tag| tag == ENDING_TAG);
+            (info.title, tagged)
+        })
+        .collect()
+}
+
+/// Whether the story keeps variables, with `(set:)` or the `(effects:)` of its links.
+pub fn uses_variables(story: &str) -> bool {
+    ["(set:", EFFECTS].iter().any(|name| story.contains(name))
+}
+
+/// Whether the passage is tagged to continue to one of its links at random.
+pub fn is_random_passage(story: &str, passage: &str) -> bool {
+    passages_info(story)
+        .iter()
+        .any(|info| info.title == passage && info.tags.iter().any(|tag| tag == RANDOM_TAG))
+}
+
+/// How long the player has to choose on the passage, from its `timeout:<seconds>` tag, capped to
+/// 10 minutes so the passage can still be shown to the player before their interaction expires,
+/// 15 minutes after their choice.
+pub fn passage_timeout(story: &str, passage: &str) -> Option<Duration> {
+    passages_info(story)
+        .into_iter()
+        .find(|info| info.title == passage)?
+        .tags
+        .iter()
+        .find_map(|tag| tag.strip_prefix(TIMEOUT_TAG_PREFIX)?.parse::<u64>().ok())
+        .filter(|seconds| *seconds > 0)
+        .map(|seconds| Duration::from_secs(seconds.min(MAX_PASSAGE_TIMEOUT)))
+}
+
+/// Whether reaching the passage saves the progress of the player: always, unless the story has
+/// passages tagged as checkpoints and this isn't one of them.
+pub fn is_save_point(story: &str, passage: &str) -> bool {
+    let passages = passages_info(story);
+    let is_checkpoint = |info: &PassageInfo| info.tags.iter().any(|tag| tag == CHECKPOINT_TAG);
+
+    !passages.iter().any(is_checkpoint)
+        || passages
+            .iter()
+            .any(|info| info.title == passage && is_checkpoint(info))
+}
+
+/// The content warnings of a passage, from its `cw:` tags.
+pub fn content_warnings(story: &str, passage: &str) -> Vec<String> {
+    passages_info(story)
+        .into_iter()
+        .filter(|info| info.title == passage)
+        .flat_map(|info| info.tags)
+        .filter_map(|tag| {
+            tag.strip_prefix(CONTENT_WARNING_TAG)
+                .map(ToString::to_string)
+        })
+        .collect()
+}
+
+/// The content warnings of the whole story, from the `warnings` of its StoryData.
+pub fn story_warnings(story: &str) -> Vec<String> {
+    story_data(story)
+        .and_then(|story_data| story_data.get("warnings")?.as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|warning| warning.as_str().map(ToString::to_string))
+        .collect()
+}
+
+/// The IFID of the story, as found in its StoryData.
+pub fn story_ifid(story: &str) -> Option<String> {
+    story_data(story)?
+        .get("ifid")
+        .and_then(|ifid| ifid.as_str())
+        .map(|ifid| ifid.to_uppercase())
+}
+
+/// The author of the story, from the `author` of its `StoryData`, or else the first line of its
+/// `Author` passage.
+pub fn story_author(story: &str) -> Option<String> {
+    if let Some(author) = story_data(story)
+        .as_ref()
+        .and_then(|story_data| story_data.get("author"))
+        .and_then(|author| author.as_str())
+        .map(str::trim)
+        .filter(|author| !author.is_empty())
+    {
+        return Some(author.to_string());
+    }
+
+    let parsed = Story::try_from(story).ok()?;
+    let passage = parsed.get_passage(AUTHOR)?;
+    let text: String = passage
+        .nodes()
+        .iter()
+        .map(|node| match node {
+            ContentNode::Text(text) | ContentNode::Link { text, .. } => *text,
+        })
+        .collect();
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(ToString::to_string)
+}
+
+/// The passage graph of a story, as a Graphviz DOT digraph: a node per passage and an edge per
+/// distinct link. The start passage is drawn bold, and links to missing passages dashed.
+pub fn story_to_dot(story: &str) -> String {
+    let graph = StoryGraph::new(story);
+
+    let mut dot = String::from("digraph story {\n");
+    for (index, title) in graph.nodes.iter().enumerate() {
+        let title = title.replace('\\', "\\\\").replace('"', "\\\"");
+        let style = if Some(index) == graph.start {
+            " style=bold"
+        } else if index >= graph.passage_count {
+            " style=dashed"
+        } else {
+            ""
+        };
+        dot.push_str(&format!("    n{index} [label=\"{title}\"{style}];\n"));
+    }
+    for (from, to) in &graph.edges {
+        dot.push_str(&format!("    n{from} -> n{to};\n"));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// The passage graph of a story, as a Mermaid flowchart, like [`story_to_dot`].
+pub fn story_to_mermaid(story: &str) -> String {
+    let graph = StoryGraph::new(story);
+
+    let mut mermaid = String::from("flowchart TD\n");
+    for (index, title) in graph.nodes.iter().enumerate() {
+        let title = title.replace('"', "#quot;");
+        mermaid.push_str(&format!("    n{index}[\"{title}\"]\n"));
+    }
+    for (from, to) in &graph.edges {
+        mermaid.push_str(&format!("    n{from} --> n{to}\n"));
+    }
+    if let Some(start) = graph.start {
+        mermaid.push_str(&format!("    style n{start} stroke-width:4px\n"));
+    }
+    for index in graph.passage_count..graph.nodes.len() {
+        mermaid.push_str(&format!("    style n{index} stroke-dasharray: 5 5\n"));
+    }
+    mermaid
+}
+
+/// Nodes are the passages, in the order of the story, followed by the missing link targets.
+struct StoryGraph {
+    nodes: Vec<String>,
+    passage_count: usize,
+    edges: Vec<(usize, usize)>,
+    start: Option<usize>,
+}
+
+impl StoryGraph {
+    fn new(story: &str) -> Self {
+        let parsed = Story::try_from(story).ok();
+        let mut nodes: Vec<String> = passages_info(story)
+            .into_iter()
+            .map(|info| info.title)
+            .collect();
+        let passage_count = nodes.len();
+        let start = parsed
+            .as_ref()
+            .and_then(|parsed| parsed.start())
+            .and_then(|start| nodes.iter().position(|title| title == start.title()));
+
+        let mut edges = vec![];
+        if let Some(parsed) = &parsed {
+            for from in 0..passage_count {
+                let Some(passage) = parsed.get_passage(&nodes[from]) else {
+                    continue;
+                };
+                for choice in choices(&passage) {
+                    let to = match nodes.iter().position(|title| title == choice.target) {
+                        Some(to) => to,
+                        None => {
+                            nodes.push(choice.target.to_string());
+                            nodes.len() - 1
+                        }
+                    };
+                    if !edges.contains(&(from, to)) {
+                        edges.push((from, to));
+                    }
+                }
+            }
+        }
+
+        Self {
+            nodes,
+            passage_count,
+            edges,
+            start,
+        }
+    }
+}
+
+impl StoryGraph {
+    /// The targets of the links of each passage, in the order of the story.
+    fn links(&self) -> Vec<(&str, Vec<&str>)> {
+        self.nodes[..self.passage_count]
+            .iter()
+            .enumerate()
+            .map(|(from, title)| {
+                let targets = self
+                    .edges
+                    .iter()
+                    .filter(|(edge_from, _)| *edge_from == from)
+                    .map(|(_, to)| self.nodes[*to].as_str())
+                    .collect();
+                (title.as_str(), targets)
+            })
+            .collect()
+    }
+}
+
+/// The links of the passage, as `(text, target)`, or `None` if the story has no such passage.
+pub fn outgoing_links(story: &str, title: &str) -> Option<Vec<(String, String)>> {
+    let parsed = Story::try_from(story).ok()?;
+    let passage = parsed.get_passage(title)?;
+    Some(
+        choices(&passage)
+            .iter()
+            .map(|choice| (choice.text.to_string(), choice.target.to_string()))
+            .collect(),
+    )
+}
+
+/// The links of the story leading to the passage, as `(passage, text)`, in the order of the story.
+pub fn incoming_links(story: &str, title: &str) -> Vec<(String, String)> {
+    let Ok(parsed) = Story::try_from(story) else {
+        return vec![];
+    };
+    passages_info(story)
+        .into_iter()
+        .filter_map(|info| {
+            parsed
+                .get_passage(&info.title)
+                .map(|passage| (info.title, passage))
+        })
+        .flat_map(|(from, passage)| {
+            choices(&passage)
+                .iter()
+                .filter(|choice| choice.target == title)
+                .map(|choice| (from.clone(), choice.text.to_string()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// What changed between two versions of a story, see [`diff_stories`].
+pub struct StoryDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// The passages in both versions whose links lead elsewhere.
+    pub relinked: Vec<Relink>,
+}
+
+pub struct Relink {
+    pub passage: String,
+    pub added_links: Vec<String>,
+    pub removed_links: Vec<String>,
+}
+
+impl StoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.relinked.is_empty()
+    }
+
+    /// The changes, a line each, with at most `max_lines` of them.
+    pub fn summary(&self, max_lines: usize) -> String {
+        let list = |titles: &[String]| {
+            titles
+                .iter()
+                .map(|title| format!("`{title}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let changes: Vec<String> = self
+            .added
+            .iter()
+            .map(|title| format!("➕ `{title}` was added."))
+            .chain(
+                self.removed
+                    .iter()
+                    .map(|title| format!("➖ `{title}` was removed.")),
+            )
+            .chain(self.relinked.iter().map(|relink| {
+                let mut changes = vec![];
+                if !relink.added_links.is_empty() {
+                    changes.push(format!("now links to {}", list(&relink.added_links)));
+                }
+                if !relink.removed_links.is_empty() {
+                    changes.push(format!(
+                        "no longer links to {}",
+                        list(&relink.removed_links)
+                    ));
+                }
+                format!("🔀 `{}` {}.", relink.passage, changes.join(" and "))
+            }))
+            .collect();
+        let mut lines: Vec<String> = changes.iter().take(max_lines).cloned().collect();
+        if changes.len() > max_lines {
+            lines.push(format!("And {} more.", changes.len() - max_lines));
+        }
+        lines.join("\n")
+    }
+}
+
+/// What `/surprise` knows of a story to recommend it.
+pub struct StoryStats {
+    pub story_id: i64,
+    /// The average stars of the story, if anyone rated it.
+    pub rating: Option<f64>,
+    /// When the player last played the story, as a Unix timestamp.
+    pub played_at: Option<i64>,
+}
+
+/// Pick a story at random, the better rated ones being more likely, and the ones the player played
+/// lately less likely. Stories played right now are only picked when they're all the player has.
+pub fn recommend(stories: &[StoryStats], now: i64, rng: &dyn Rng) -> Option<i64> {
+    let weights: Vec<usize> = stories
+        .iter()
+        .map(|story| {
+            let stars = story.rating.unwrap_or(UNRATED_STARS);
+            let freshness = match story.played_at {
+                Some(played_at) => {
+                    let days = (now - played_at).max(0) as f64 / (24.0 * 60.0 * 60.0);
+                    days / (days + RECOMMENDATION_HALF_DAYS)
+                }
+                None => 1.0,
+            };
+            (stars * freshness * 100.0).round() as usize
+        })
+        .collect();
+    let total: usize = weights.iter().sum();
+    if total == 0 {
+        return stories
+            .get(rng.below(stories.len().max(1)))
+            .map(|story| story.story_id);
+    }
+
+    let mut pick = rng.below(total);
+    stories
+        .iter()
+        .zip(weights)
+        .find(|(_, weight)| {
+            if pick < *weight {
+                true
+            } else {
+                pick -= weight;
+                false
+            }
+        })
+        .map(|(story, _)| story.story_id)
+}
+
+/// Why the path can't be followed in the story anymore, like a passage that was removed or a
+/// link that changed, if it can't.
+pub fn path_mismatch(story: &Story<String>, path: &[String]) -> Option<String> {
+    let mut previous: Option<Passage<&str>> = None;
+    for title in path {
+        let Some(passage) = story.get_passage(title) else {
+            return Some(format!("There is no passage named `{title}` anymore."));
+        };
+        if let Some(previous) = &previous {
+            if !choices(previous)
+                .iter()
+                .any(|choice| choice.target == title)
+            {
+                return Some(format!("`{}` doesn't link to `{title}`.", previous.title()));
+            }
+        }
+        previous = Some(passage);
+    }
+    None
+}
+
+/// Compare the passages of two versions of a story, and where the links of the passages they share
+/// lead.
+pub fn diff_stories(old: &str, new: &str) -> StoryDiff {
+    let (old_graph, new_graph) = (StoryGraph::new(old), StoryGraph::new(new));
+    let (old_links, new_links) = (old_graph.links(), new_graph.links());
+    let old_targets: HashMap<&str, &Vec<&str>> = old_links
+        .iter()
+        .map(|(title, targets)| (*title, targets))
+        .collect();
+    let new_targets: HashMap<&str, &Vec<&str>> = new_links
+        .iter()
+        .map(|(title, targets)| (*title, targets))
+        .collect();
+    let missing = |from: &[&str], to: &[&str]| -> Vec<String> {
+        let mut missing: Vec<String> = vec![];
+        for target in from {
+            if !to.contains(target) && !missing.iter().any(|known| known == target) {
+                missing.push(target.to_string());
+            }
+        }
+        missing
+    };
+
+    let mut diff = StoryDiff {
+        added: vec![],
+        removed: vec![],
+        relinked: vec![],
+    };
+    for (title, targets) in &new_links {
+        match old_targets.get(title) {
+            None if !diff.added.iter().any(|added| added == title) => {
+                diff.added.push(title.to_string())
+            }
+            None => {}
+            Some(old_targets) => {
+                let added_links = missing(targets, old_targets);
+                let removed_links = missing(old_targets, targets);
+                let changed = !added_links.is_empty() || !removed_links.is_empty();
+                let known = diff.relinked.iter().any(|relink| relink.passage == *title);
+                if changed && !known {
+                    diff.relinked.push(Relink {
+                        passage: title.to_string(),
+                        added_links,
+                        removed_links,
+                    });
+                }
+            }
+        }
+    }
+    for (title, _) in &old_links {
+        if !new_targets.contains_key(title) && !diff.removed.iter().any(|removed| removed == title)
+        {
+            diff.removed.push(title.to_string());
+        }
+    }
+    diff
+}
+
+/// How well a story is expected to play, see [`validate_story`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Grade {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Display for Grade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Grade::Pass => "pass",
+            Grade::Warn => "warn",
+            Grade::Fail => "fail",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What [`validate_story`] found wrong with a story.
+pub struct StoryReport {
+    /// Problems that prevent the story from being played.
+    pub errors: Vec<String>,
+    /// Problems the players may run into.
+    pub warnings: Vec<String>,
+}
+
+impl StoryReport {
+    pub fn grade(&self) -> Grade {
+        if !self.errors.is_empty() {
+            Grade::Fail
+        } else if !self.warnings.is_empty() {
+            Grade::Warn
+        } else {
+            Grade::Pass
+        }
+    }
+
+    /// A finding per line, the errors first, at most `max_lines` of them.
+    pub fn summary(&self, max_lines: usize) -> String {
+        let findings: Vec<String> = self
+            .errors
+            .iter()
+            .map(|error| format!("❌ {error}"))
+            .chain(self.warnings.iter().map(|warning| format!("⚠️ {warning}")))
+            .collect();
+        let mut lines: Vec<String> = findings.iter().take(max_lines).cloned().collect();
+        if findings.len() > max_lines {
+            lines.push(format!("And {} more.", findings.len() - max_lines));
+        }
+        lines.join("\n")
+    }
+}
+
+/// How many lines are shown on each side of the line a story can't be parsed at.
+const PARSE_ERROR_CONTEXT_LINES: usize = 2;
+/// How many characters of each of these lines are shown.
+const MAX_PARSE_ERROR_LINE_LENGTH: usize = 80;
+
+/// Tell where the story can't be parsed, with the lines around it in a code block. twee_v3 only
+/// gives the `rest` of the story it failed on.
+fn parse_error_message(story: &str, rest: &str) -> String {
+    // Anything else is a message, like for incomplete input.
+    if !story.ends_with(rest) {
+        return "The story can't be read, is it in the twee 3 format?".to_string();
+    }
+    let (line, column) = line_column(story, story.len() - rest.len());
+    format!(
+        "The story can't be read at line {line}, column {column}, is it in the twee 3 format?\n```\n{}\n```",
+        line_context(story, line, column)
+    )
+}
+
+/// The line and column of the byte offset in the text, both starting from 1. The column counts
+/// characters.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// The lines around the line, numbered, with a caret under the column.
+fn line_context(text: &str, line: usize, column: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let first = line.saturating_sub(PARSE_ERROR_CONTEXT_LINES).max(1);
+    let last = (line + PARSE_ERROR_CONTEXT_LINES).min(lines.len().max(line));
+    let width = last.to_string().len();
+
+    let mut context = vec![];
+    for number in first..=last {
+        // The line is missing when the text ends with a line break and the error is after it.
+        let content = lines.get(number - 1).copied().unwrap_or_default();
+        // Backticks would close the code block.
+        let content = truncate_chars(content, MAX_PARSE_ERROR_LINE_LENGTH).replace('`', "'");
+        context.push(format!("{number:>width$} | {content}"));
+        if number == line && column <= MAX_PARSE_ERROR_LINE_LENGTH + 1 {
+            context.push(format!("{:>width$} | {}^", "", " ".repeat(column - 1)));
+        }
+    }
+    context.join("\n")
+}
+
+/// Like [`validate_story`], for content that can't be trusted, like uploads: a panic of the parser
+/// on malformed input is caught and returned as an error instead of unwinding.
+pub fn parse_and_validate(content: &str) -> Result<StoryReport, String> {
+    catch_panic(|| validate_story(content))
+}
+
+/// Run `f`, returning the message of its panic, if it panics.
+fn catch_panic<T>(f: impl FnOnce() -> T + std::panic::UnwindSafe) -> Result<T, String> {
+    std::panic::catch_unwind(f).map_err(|panic| {
+        panic
+            .downcast_ref::<&str>()
+            .map(ToString::to_string)
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "The story couldn't be parsed".to_string())
+    })
+}
+
+/// Check everything that can go wrong with a story before it is played: that it has a title and
+/// a start, links to passages that exist and no more than Discord can show, has no passages
+/// sharing a title or that can't be reached, and can end.
+pub fn validate_story(story: &str) -> StoryReport {
+    let mut report = StoryReport {
+        errors: vec![],
+        warnings: vec![],
+    };
+    let parsed = match Story::try_from(story) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            report
+                .errors
+                .push(parse_error_message(story, &error.to_string()));
+            return report;
+        }
+    };
+    if parsed.title().is_none() {
+        report
+            .errors
+            .push("The story has no title, set it in a `StoryTitle` passage.".to_string());
+    }
+    if parsed.start().is_none() {
+        report.errors.push(
+            "The story has no start, name a passage `Start` or set it in the `StoryData`."
+                .to_string(),
+        );
+    }
+
+    let graph = StoryGraph::new(story);
+    let titles = &graph.nodes[..graph.passage_count];
+    for (index, title) in titles.iter().enumerate() {
+        let count = titles.iter().filter(|other| *other == title).count();
+        if count > 1 && !titles[..index].contains(title) {
+            report.warnings.push(format!(
+                "{count} passages are named `{title}`, only the first one can be played."
+            ));
+        }
+    }
+
+    for (index, title) in titles.iter().enumerate() {
+        let count = parsed
+            .get_passage(title)
+            .map_or(0, |passage| choices(&passage).len());
+        if count > MAX_CHOICES && !titles[..index].contains(title) {
+            report.errors.push(format!(
+                "`{title}` has {count} choices, but only {MAX_CHOICES} can be shown."
+            ));
+        }
+    }
+
+    for &(from, to) in &graph.edges {
+        if to >= graph.passage_count {
+            report.warnings.push(format!(
+                "`{}` links to `{}`, which doesn't exist.",
+                graph.nodes[from], graph.nodes[to]
+            ));
+        }
+    }
+
+    if let Some(start) = graph.start {
+        let mut reached = vec![false; graph.passage_count];
+        let mut queue = vec![start];
+        while let Some(from) = queue.pop() {
+            if std::mem::replace(&mut reached[from], true) {
+                continue;
+            }
+            queue.extend(
+                graph
+                    .edges
+                    .iter()
+                    .filter(|(edge_from, to)| *edge_from == from && *to < graph.passage_count)
+                    .map(|(_, to)| *to),
+            );
+        }
+        let displayed = displayed_passages(story);
+        for (index, title) in titles.iter().enumerate() {
+            // Passages sharing a title were reported already.
+            if !reached[index] && !titles[..index].contains(title) && !displayed.contains(title) {
+                report
+                    .warnings
+                    .push(format!("`{title}` can't be reached from the start."));
+            }
+        }
+    }
+
+    if ending_passages(story).is_empty() {
+        report
+            .warnings
+            .push("The story has no ending, every passage links to another one.".to_string());
+    }
+    report
+}
+
+/// The passages included with `(display:)` anywhere in the story.
+fn displayed_passages(story: &str) -> Vec<String> {
+    const DISPLAY: &str = "(display:";
+
+    let mut displayed = vec![];
+    let mut rest = story;
+    while let Some(start) = rest.find(DISPLAY) {
+        rest = &rest[start + DISPLAY.len()..];
+        let Some(end) = rest.find(')') else {
+            break;
+        };
+        let name = rest[..end].trim().trim_matches(|c| c == '"' || c == '\'');
+        displayed.push(name.to_string());
+        rest = &rest[end + 1..];
+    }
+    displayed
+}
+
+/// The start of the text, at most `max_chars` characters long. The text is cut on a character
+/// boundary, and not in the middle of a combining sequence either, like an accent following its
+/// letter, an emoji joined to others or the two letters of a flag, so it may be a bit shorter.
+pub fn truncate_chars(text: &str, max_chars: usize) -> &str {
+    let Some((mut end, _)) = text.char_indices().nth(max_chars) else {
+        return text;
+    };
+    while let Some(previous) = text[..end].chars().next_back() {
+        let next = text[end..].chars().next();
+        if !(previous == ZERO_WIDTH_JOINER
+            || next.is_some_and(extends_character)
+            || splits_flag(&text[..end], next))
+        {
+            break;
+        }
+        end -= previous.len_utf8();
+    }
+    &text[..end]
+}
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+
+/// Flags like 🇫🇷 are pairs of regional indicators, so cutting after an odd number of them
+/// separates the letters of the last flag.
+fn splits_flag(start: &str, next: Option<char>) -> bool {
+    next.is_some_and(is_regional_indicator)
+        && start
+            .chars()
+            .rev()
+            .take_while(|&c| is_regional_indicator(c))
+            .count()
+            % 2
+            == 1
+}
+
+/// Whether the character modifies the one before, so they must not be separated.
+fn extends_character(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}' // Combining diacritical marks
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}' // Including the keycap of 1️⃣
+        | '\u{FE00}'..='\u{FE0F}' // Variation selectors, to show a character as an emoji
+        | '\u{FE20}'..='\u{FE2F}'
+        | '\u{1F3FB}'..='\u{1F3FF}' // Skin tones
+        | '\u{E0020}'..='\u{E007F}' // Tags of subdivision flags
+        | ZERO_WIDTH_JOINER)
+}
+
+/// Split the emoji starting the label, a unicode one like `🗡️` or a custom one like
+/// `<:sword:600404340292059257>`, from the rest of the label.
+pub fn split_emoji(label: &str) -> (Option<&str>, &str) {
+    let end = if label.starts_with("<:") || label.starts_with("<a:") {
+        custom_emoji_length(label)
+    } else {
+        unicode_emoji_length(label)
+    };
+    match end {
+        Some(end) => (Some(&label[..end]), label[end..].trim_start()),
+        None => (None, label),
+    }
+}
+
+/// The length of the custom emoji starting the text, formatted as `<:name:id>`.
+fn custom_emoji_length(text: &str) -> Option<usize> {
+    let end = text.find('>')?;
+    let [_, name, id] = text[..end].split(':').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && id.parse::<u64>().is_ok();
+    valid.then_some(end + 1)
+}
+
+/// The length of the unicode emoji starting the text, with its modifiers and the emoji joined to
+/// it.
+fn unicode_emoji_length(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().peekable();
+    let (_, first) = chars.next()?;
+    let second = chars.peek().map(|(_, c)| *c);
+    // Flags are made of two letters, a single one isn't an emoji.
+    let is_flag = is_regional_indicator(first) && second.is_some_and(is_regional_indicator);
+    let is_emoji = is_flag
+        || is_emoji_presentation(first)
+        // Like ©️ or 1️⃣, shown as emoji with a variation selector.
+        || second == Some('\u{FE0F}');
+    if !is_emoji {
         return None;
     }
-    let story = story.unwrap();
-    return story.title().map(ToString::to_string);
+
+    let mut previous = first;
+    let mut end = first.len_utf8();
+    if is_flag {
+        chars.next();
+        end += second?.len_utf8();
+    }
+    for (index, c) in chars {
+        if !(previous == ZERO_WIDTH_JOINER || extends_character(c)) {
+            break;
+        }
+        previous = c;
+        end = index + c.len_utf8();
+    }
+    Some(end)
+}
+
+/// Whether the character is shown as an emoji without a variation selector, as Discord accepts
+/// them. Symbols like ✓ or ★ aren't emoji, and neither are the ones like ✔ only shown as an emoji
+/// with a selector.
+fn is_emoji_presentation(c: char) -> bool {
+    matches!(c,
+        '\u{231A}'..='\u{231B}' | '\u{23E9}'..='\u{23EC}' | '\u{23F0}' | '\u{23F3}'
+        | '\u{25FD}'..='\u{25FE}' | '\u{2614}'..='\u{2615}' | '\u{2648}'..='\u{2653}'
+        | '\u{267F}' | '\u{2693}' | '\u{26A1}' | '\u{26AA}'..='\u{26AB}'
+        | '\u{26BD}'..='\u{26BE}' | '\u{26C4}'..='\u{26C5}' | '\u{26CE}' | '\u{26D4}' | '\u{26EA}'
+        | '\u{26F2}'..='\u{26F3}' | '\u{26F5}' | '\u{26FA}' | '\u{26FD}' | '\u{2705}'
+        | '\u{270A}'..='\u{270B}' | '\u{2728}' | '\u{274C}' | '\u{274E}' | '\u{2753}'..='\u{2755}'
+        | '\u{2757}' | '\u{2795}'..='\u{2797}' | '\u{27B0}' | '\u{27BF}' | '\u{2B1B}'..='\u{2B1C}'
+        | '\u{2B50}' | '\u{2B55}'
+        | '\u{1F004}' | '\u{1F0CF}' | '\u{1F18E}' | '\u{1F191}'..='\u{1F19A}'
+        | '\u{1F201}' | '\u{1F21A}' | '\u{1F22F}' | '\u{1F232}'..='\u{1F236}'
+        | '\u{1F238}'..='\u{1F23A}' | '\u{1F250}'..='\u{1F251}'
+        | '\u{1F300}'..='\u{1F64F}' | '\u{1F680}'..='\u{1F6FF}' | '\u{1F7E0}'..='\u{1F7EB}'
+        | '\u{1F90C}'..='\u{1F9FF}' | '\u{1FA70}'..='\u{1FAFF}')
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c, '\u{1F1E6}'..='\u{1F1FF}')
+}
+
+/// The text, cut to at most `max_length` characters with an ellipsis when it is longer.
+pub fn truncate_label(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        return text.to_string();
+    }
+    format!("{}…", truncate_chars(text, max_length.saturating_sub(1)))
+}
+
+/// Make text coming from users safe to show in a message: on a single line, without backticks
+/// that would break out of a code span, and at most `max_length` characters long.
+pub fn sanitize_display(text: &str, max_length: usize) -> String {
+    let sanitized: String = text
+        .chars()
+        .map(|c| match c {
+            '`' => '\'',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+    let sanitized = sanitized.split_whitespace().collect::<Vec<_>>().join(" ");
+    truncate_label(&sanitized, max_length)
+}
+
+/// Replace the `(print: $variable)` macros of a passage text with the value of the variable.
+/// Unset variables are printed as an empty string.
+pub fn print_variables(text: &str, variables: &HashMap<String, String>) -> String {
+    const PRINT: &str = "(print:";
+
+    let mut printed = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(PRINT) {
+        let Some(end) = rest[start..].find(')') else {
+            break;
+        };
+        let name = rest[start + PRINT.len()..start + end].trim();
+        let Some(name) = name.strip_prefix('$') else {
+            // Not a variable, keep the macro as is.
+            printed.push_str(&rest[..start + end + 1]);
+            rest = &rest[start + end + 1..];
+            continue;
+        };
+
+        printed.push_str(&rest[..start]);
+        if let Some(value) = variables.get(name) {
+            printed.push_str(value);
+        }
+        rest = &rest[start + end + 1..];
+    }
+    printed.push_str(rest);
+    printed
+}
+
+/// Run the `(set:)`, `(if:)`, `(else-if:)` and `(else:)` macros of a passage text, in order,
+/// returning the text without them, and with only the hooks whose condition holds:
+/// * `(set: $variable to "value")` sets the variable, a bare value like `true` works too.
+/// * `(if: $variable)[text]` shows the text when the variable is set, but not to `false` or `0`.
+/// * `(if: $variable is "value")[text]` and `is not` compare the variable to the value.
+/// * `(if: not $variable)[text]` shows the text when the variable isn't set.
+/// * `(if: $variable >= 10)[text]`, and `<=`, `>` and `<`, compare the number of the variable, an
+///   unset variable counting as 0.
+///
+/// Malformed macros are kept as is, and a set only happens when its hook is shown.
+pub fn run_macros(text: &str, variables: &mut HashMap<String, String>) -> String {
+    const SET: &str = "(set:";
+    const IF: &str = "(if:";
+
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = [SET, IF].iter().filter_map(|name| rest.find(name)).min() {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(arguments) = rest.strip_prefix(SET) {
+            let Some(end) = macro_end(arguments) else {
+                break;
+            };
+            match arguments[..end].split_once(" to ") {
+                Some((name, value)) if name.trim().starts_with('$') => {
+                    let name = name.trim().trim_start_matches('$');
+                    variables.insert(name.to_string(), unquote(value.trim()).to_string());
+                }
+                _ => output.push_str(&rest[..SET.len() + end + 1]),
+            }
+            rest = &arguments[end + 1..];
+            continue;
+        }
+
+        // A chain of `(if:)`, `(else-if:)` and `(else:)` hooks, of which the first one that holds
+        // is shown.
+        let mut shown: Option<&str> = None;
+        let mut chain = rest;
+        let mut condition_prefix = IF;
+        loop {
+            let (holds, after_macro) = if let Some(arguments) = chain.strip_prefix(condition_prefix)
+            {
+                let Some(end) = macro_end(arguments) else {
+                    break;
+                };
+                (
+                    condition_holds(&arguments[..end], variables),
+                    &arguments[end + 1..],
+                )
+            } else if let Some(after_macro) = chain.strip_prefix("(else:)") {
+                (true, after_macro)
+            } else {
+                break;
+            };
+            let Some((hook, after_hook)) = split_hook(after_macro) else {
+                break;
+            };
+            if holds && shown.is_none() {
+                shown = Some(hook);
+            }
+            chain = after_hook;
+            condition_prefix = "(else-if:";
+
+            // Only whitespace may separate the hooks of a chain.
+            let next = chain.trim_start();
+            if !(next.starts_with("(else-if:") || next.starts_with("(else:)")) {
+                break;
+            }
+            chain = next;
+        }
+
+        if chain.len() == rest.len() {
+            // Not even the `(if:)` is well formed, keep it as text.
+            output.push_str(IF);
+            rest = &rest[IF.len()..];
+            continue;
+        }
+        if let Some(hook) = shown {
+            output.push_str(&run_macros(hook, variables));
+        }
+        rest = chain;
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Where the closing parenthesis of a macro is, ignoring the ones in quotes.
+fn macro_end(arguments: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, c) in arguments.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, ')') => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split the hook that starts the text, like `[text]`, from what follows it. Hooks can be nested.
+fn split_hook(text: &str) -> Option<(&str, &str)> {
+    let content = text.strip_prefix('[')?;
+    let mut depth = 0;
+    for (index, c) in content.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 0 => return Some((&content[..index], &content[index + 1..])),
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn condition_holds(condition: &str, variables: &HashMap<String, String>) -> bool {
+    let condition = condition.trim();
+    let value_of = |name: &str| variables.get(name.trim().trim_start_matches('$'));
+
+    if let Some((name, value)) = condition.split_once(" is not ") {
+        return value_of(name).map(String::as_str) != Some(unquote(value.trim()));
+    }
+    if let Some((name, value)) = condition.split_once(" is ") {
+        return value_of(name).map(String::as_str) == Some(unquote(value.trim()));
+    }
+    for operator in [">=", "<=", ">", "<"] {
+        let Some((name, value)) = condition.split_once(&format!(" {operator} ")) else {
+            continue;
+        };
+        let name = name.trim().trim_start_matches('$');
+        let (Some(left), Ok(right)) = (
+            number_value(variables, name),
+            unquote(value.trim()).parse::<f64>(),
+        ) else {
+            return false;
+        };
+        return match operator {
+            ">=" => left >= right,
+            "<=" => left <= right,
+            ">" => left > right,
+            _ => left < right,
+        };
+    }
+    let (negated, name) = match condition.strip_prefix("not ") {
+        Some(name) => (true, name),
+        None => (false, condition),
+    };
+    let truthy = value_of(name).is_some_and(|value| !["", "false", "0"].contains(&value.as_str()));
+    truthy != negated
+}
+
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+        .unwrap_or(value)
+}
+
+/// The JSON content of the `StoryData` passage, if any.
+fn story_data(story: &str) -> Option<serde_json::Value> {
+    let start = story.find(":: StoryData")?;
+    let content = &story[start..];
+    let content = &content[content.find('\n')? + 1..];
+    let end = content.find("\n::").unwrap_or(content.len());
+
+    serde_json::from_str(&content[..end]).ok()
+}
+
+/// Split the string at the first unescaped character among `delimiters`.
+fn split_unescaped<'a>(s: &'a str, delimiters: &[char]) -> (&'a str, &'a str) {
+    let mut escaped = false;
+    for (index, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if delimiters.contains(&c) {
+            return (&s[..index], &s[index..]);
+        }
+    }
+    (s, "")
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                unescaped.push(next);
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SeededRng;
+
+    #[test]
+    fn short_texts_are_not_truncated() {
+        assert_eq!(truncate_chars("Hello", 5), "Hello");
+        assert_eq!(truncate_chars("Hello", 10), "Hello");
+        assert_eq!(truncate_chars("", 0), "");
+    }
+
+    #[test]
+    fn texts_are_cut_on_characters() {
+        assert_eq!(truncate_chars("Hello", 3), "Hel");
+        assert_eq!(truncate_chars("héllo", 2), "hé");
+        assert_eq!(truncate_chars("日本語", 2), "日本");
+    }
+
+    #[test]
+    fn accents_stay_with_their_letter() {
+        // An e followed by a combining acute accent.
+        let text = "cafe\u{0301}s";
+        assert_eq!(truncate_chars(text, 4), "caf");
+        assert_eq!(truncate_chars(text, 5), "cafe\u{0301}");
+    }
+
+    #[test]
+    fn joined_emojis_are_not_split() {
+        // 👩‍👩‍👧: women and a girl, joined.
+        let family = "a\u{1F469}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        for max_chars in 1..6 {
+            assert_eq!(truncate_chars(family, max_chars), "a");
+        }
+        assert_eq!(truncate_chars(family, 6), family);
+    }
+
+    #[test]
+    fn skin_tones_stay_with_their_emoji() {
+        // 👋🏽
+        let text = "a\u{1F44B}\u{1F3FD}";
+        assert_eq!(truncate_chars(text, 2), "a");
+        assert_eq!(truncate_chars(text, 3), text);
+    }
+
+    #[test]
+    fn links_can_be_given_a_label() {
+        let right = Choice::new("Open the heavy wooden door->Cellar", "Open the door");
+        assert_eq!(
+            (right.text, right.target),
+            ("Open the heavy wooden door", "Cellar")
+        );
+        assert_eq!(right.label(), "Open the door");
+
+        let left = Choice::new("Cellar <- Open the heavy wooden door", "Open the door");
+        assert_eq!(
+            (left.text, left.target),
+            ("Open the heavy wooden door", "Cellar")
+        );
+        assert_eq!(left.label(), "Open the door");
+    }
+
+    #[test]
+    fn arrows_without_a_target_are_part_of_the_text() {
+        let back = Choice::new("<- Back", "Hall");
+        assert_eq!((back.text, back.target), ("<- Back", "Hall"));
+        assert_eq!(back.label(), "<- Back");
+
+        let next = Choice::new("Next ->", "Hall");
+        assert_eq!((next.text, next.target), ("Next ->", "Hall"));
+    }
+
+    #[test]
+    fn long_links_are_cut_at_a_word() {
+        let text = concat!(
+            "Walk all the way down the winding stairs, ",
+            "into the damp and silent cellar under the old house"
+        );
+        let label = Choice::new(text, "Cellar").label();
+
+        assert_eq!(
+            label,
+            "Walk all the way down the winding stairs, into the damp and silent cellar…"
+        );
+        assert!(label.chars().count() <= MAX_BUTTON_LABEL_LENGTH);
+    }
+
+    #[test]
+    fn labels_drop_the_markdown_of_links() {
+        let choice = Choice::new("Run  **away**\nfast", "Run");
+        assert_eq!(choice.label(), "Run away fast");
+    }
+
+    #[test]
+    fn backticks_and_control_characters_are_sanitized() {
+        assert_eq!(sanitize_display("my`story`\n.twee", 100), "my'story' .twee");
+        assert_eq!(
+            sanitize_display("```\u{0}escape\t\tme```", 100),
+            "''' escape me'''"
+        );
+    }
+
+    #[test]
+    fn dot_graphs_have_a_node_per_passage_and_an_edge_per_link() {
+        let story = concat!(
+            ":: StoryTitle\nStory\n\n:: StoryData\n{\"start\": \"Start\"}\n\n",
+            ":: Start\n[[Left]]\n[[Right]]\n[[Left]]\n\n",
+            ":: Left\n[[Start]]\n[[Cave]]\n\n",
+            ":: Right\nThe end\n"
+        );
+        let dot = story_to_dot(story);
+
+        let nodes = dot.lines().filter(|line| line.contains("[label=")).count();
+        let edges = dot.lines().filter(|line| line.contains(" -> ")).count();
+        // The missing `Cave` is drawn too, and `Left` is only linked once from `Start`.
+        assert_eq!(nodes, 4);
+        assert_eq!(edges, 4);
+        assert!(dot.contains("n0 [label=\"Start\" style=bold];"));
+        assert!(dot.contains("n3 [label=\"Cave\" style=dashed];"));
+        assert!(dot.contains("    n1 -> n3;\n"));
+    }
+
+    #[test]
+    fn long_titles_are_truncated_to_fit_in_options() {
+        let title = "A story with a very long title, ".repeat(7);
+        let title = &title[..200];
+        let label = truncate_label(title, MAX_OPTION_LABEL_LENGTH);
+
+        assert_eq!(label.chars().count(), MAX_OPTION_LABEL_LENGTH);
+        assert!(label.ends_with('…'));
+        assert!(title.starts_with(label.trim_end_matches('…')));
+        assert_eq!(
+            truncate_label("Short title", MAX_OPTION_LABEL_LENGTH),
+            "Short title"
+        );
+    }
+
+    #[test]
+    fn long_filenames_are_truncated() {
+        let filename = format!("{}.twee", "a".repeat(300));
+        let sanitized = sanitize_display(&filename, 64);
+
+        assert_eq!(sanitized.chars().count(), 64);
+        assert_eq!(sanitized, format!("{}…", "a".repeat(63)));
+    }
+
+    #[test]
+    fn emoji_starting_labels_are_split() {
+        assert_eq!(split_emoji("🗡️ Fight"), (Some("🗡️"), "Fight"));
+        assert_eq!(split_emoji("⭐Wish"), (Some("⭐"), "Wish"));
+        assert_eq!(split_emoji("🇫🇷 Paris"), (Some("🇫🇷"), "Paris"));
+        assert_eq!(split_emoji("1️⃣ One"), (Some("1️⃣"), "One"));
+        assert_eq!(
+            split_emoji("<:sword:600404340292059257> Fight"),
+            (Some("<:sword:600404340292059257>"), "Fight")
+        );
+    }
+
+    #[test]
+    fn symbols_stay_in_labels() {
+        assert_eq!(split_emoji("✓ Done"), (None, "✓ Done"));
+        assert_eq!(split_emoji("★ Star"), (None, "★ Star"));
+        assert_eq!(split_emoji("→ Next"), (None, "→ Next"));
+        assert_eq!(split_emoji("✔ Check"), (None, "✔ Check"));
+        // A single letter of a flag.
+        assert_eq!(split_emoji("🇫 F"), (None, "🇫 F"));
+        assert_eq!(split_emoji("<:sword> Fight"), (None, "<:sword> Fight"));
+        assert_eq!(split_emoji("Go north"), (None, "Go north"));
+    }
+
+    #[test]
+    fn keycaps_are_not_split() {
+        // 1️⃣: a digit, the emoji variation selector and the keycap.
+        let text = "a1\u{FE0F}\u{20E3}";
+        assert_eq!(truncate_chars(text, 2), "a");
+        assert_eq!(truncate_chars(text, 3), "a");
+        assert_eq!(truncate_chars(text, 4), text);
+    }
+
+    #[test]
+    fn flags_are_not_split() {
+        // 🇫🇷🇯🇵
+        let flags = "\u{1F1EB}\u{1F1F7}\u{1F1EF}\u{1F1F5}";
+        assert_eq!(truncate_chars(flags, 1), "");
+        assert_eq!(truncate_chars(flags, 2), "\u{1F1EB}\u{1F1F7}");
+        assert_eq!(truncate_chars(flags, 3), "\u{1F1EB}\u{1F1F7}");
+        // 🏴󠁧󠁢󠁳󠁣󠁴󠁿: the flag of Scotland, a black flag followed by tags.
+        let scotland = "a\u{1F3F4}\u{E0067}\u{E0062}\u{E0073}\u{E0063}\u{E0074}\u{E007F}";
+        assert_eq!(truncate_chars(scotland, 4), "a");
+        assert_eq!(truncate_chars(scotland, 8), scotland);
+    }
+
+    #[test]
+    fn parse_errors_tell_the_line_and_column() {
+        let report = validate_story(":: Start\nHello\n:: \n");
+        assert_eq!(
+            report.errors,
+            vec![
+                "The story can't be read at line 3, column 4, is it in the twee 3 format?\n```\n1 | :: Start\n2 | Hello\n3 | :: \n  |    ^\n```"
+            ]
+        );
+
+        let report = validate_story("Hello\n:: Start\nX");
+        assert!(report.errors[0].contains("at line 1, column 1,"));
+    }
+
+    /// A story whose start links to `count` passages.
+    fn story_with_choices(count: usize) -> String {
+        let links: String = (0..count)
+            .map(|index| format!("[[Room {index}]]\n"))
+            .collect();
+        let rooms: String = (0..count)
+            .map(|index| format!(":: Room {index}\nThe end\n\n"))
+            .collect();
+        format!(
+            ":: StoryTitle\nRooms\n\n:: StoryData\n{{\"start\": \"Start\"}}\n\n:: Start\n{links}\n{rooms}"
+        )
+    }
+
+    #[test]
+    fn passages_with_too_many_choices_fail() {
+        let report = validate_story(&story_with_choices(MAX_CHOICES + 1));
+        assert_eq!(
+            report.errors,
+            ["`Start` has 26 choices, but only 25 can be shown."]
+        );
+        assert!(report.grade() == Grade::Fail);
+    }
+
+    #[test]
+    fn passages_with_as_many_choices_as_shown_pass() {
+        let report = validate_story(&story_with_choices(MAX_CHOICES));
+        assert!(report.errors.is_empty());
+        assert!(report.warnings.is_empty());
+    }
+
+    const VALID_STORY: &str = ":: StoryTitle\nCave\n\n:: StoryData\n{\"start\": \"Start\"}\n\n:: Start\n(display: \"Torch\")\n[[Enter->Cave]]\n\n:: Torch\nA torch.\n\n:: Cave\nThe end\n";
+
+    #[test]
+    fn valid_stories_pass() {
+        let report = validate_story(VALID_STORY);
+        assert!(report.errors.is_empty());
+        assert!(report.warnings.is_empty());
+        assert!(report.grade() == Grade::Pass);
+        assert_eq!(report.summary(5), "");
+    }
+
+    #[test]
+    fn stories_without_title_or_start_fail() {
+        let report = validate_story(":: Begin\nThe end\n");
+        assert_eq!(
+            report.errors,
+            [
+                "The story has no title, set it in a `StoryTitle` passage.",
+                "The story has no start, name a passage `Start` or set it in the `StoryData`.",
+            ]
+        );
+        assert!(report.grade() == Grade::Fail);
+    }
+
+    #[test]
+    fn passages_sharing_a_title_are_reported_once() {
+        let story = VALID_STORY.replace(
+            ":: Torch\n",
+            ":: Cave\nAgain\n\n:: Cave\nAnd again\n\n:: Torch\n",
+        );
+        let report = validate_story(&story);
+        assert_eq!(
+            report.warnings,
+            ["3 passages are named `Cave`, only the first one can be played."]
+        );
+        assert!(report.grade() == Grade::Warn);
+    }
+
+    #[test]
+    fn links_to_missing_passages_are_reported() {
+        let story = VALID_STORY.replace("[[Enter->Cave]]", "[[Enter->Cave]]\n[[Flee->Forest]]");
+        let report = validate_story(&story);
+        assert_eq!(
+            report.warnings,
+            ["`Start` links to `Forest`, which doesn't exist."]
+        );
+    }
+
+    #[test]
+    fn passages_out_of_reach_are_reported() {
+        let story = format!("{VALID_STORY}\n:: Secret\nHidden\n");
+        let report = validate_story(&story);
+        assert_eq!(
+            report.warnings,
+            ["`Secret` can't be reached from the start."]
+        );
+    }
+
+    #[test]
+    fn stories_without_ending_are_reported() {
+        let story = VALID_STORY
+            .replace("The end", "[[Start]]")
+            .replace("A torch.", "[[Cave]]");
+        let report = validate_story(&story);
+        assert_eq!(
+            report.warnings,
+            ["The story has no ending, every passage links to another one."]
+        );
+        assert_eq!(
+            report.summary(5),
+            "⚠️ The story has no ending, every passage links to another one."
+        );
+    }
+
+    #[test]
+    fn stories_diff_their_passages_and_links() {
+        let old = ":: Start\n[[Cave]]\n[[Forest]]\n\n:: Cave\nDark\n\n:: Forest\nTrees\n";
+        let new = ":: Start\n[[Cave]]\n[[Lake]]\n\n:: Cave\nDark\n\n:: Lake\nWet\n";
+
+        let diff = diff_stories(old, new);
+        assert_eq!(diff.added, ["Lake"]);
+        assert_eq!(diff.removed, ["Forest"]);
+        assert_eq!(diff.relinked.len(), 1);
+        assert_eq!(diff.relinked[0].passage, "Start");
+        assert_eq!(diff.relinked[0].added_links, ["Lake"]);
+        assert_eq!(diff.relinked[0].removed_links, ["Forest"]);
+        assert_eq!(
+            diff.summary(2),
+            "➕ `Lake` was added.\n➖ `Forest` was removed.\nAnd 1 more."
+        );
+        assert_eq!(
+            diff.summary(3).lines().last(),
+            Some("🔀 `Start` now links to `Lake` and no longer links to `Forest`.")
+        );
+    }
+
+    #[test]
+    fn unchanged_stories_have_an_empty_diff() {
+        let story = ":: Start\n[[Cave]]\n\n:: Cave\nDark\n";
+        let reworded = ":: Start\nGo in the [[Cave]]\n\n:: Cave\nVery dark\n";
+
+        assert!(diff_stories(story, story).is_empty());
+        assert!(diff_stories(story, reworded).is_empty());
+    }
+
+    #[test]
+    fn incoming_links_come_from_every_passage_leading_there() {
+        let story = ":: Start\n[[Enter->Cave]]\n[[Forest]]\n\n:: Forest\n[[Back to the cave->Cave]]\n[[Start]]\n\n:: Cave\nDark\n";
+
+        assert_eq!(
+            incoming_links(story, "Cave"),
+            [
+                ("Start".to_string(), "Enter".to_string()),
+                ("Forest".to_string(), "Back to the cave".to_string()),
+            ]
+        );
+        assert_eq!(
+            incoming_links(story, "Start"),
+            [("Forest".to_string(), "Start".to_string())]
+        );
+        assert!(incoming_links(story, "Nowhere").is_empty());
+        assert_eq!(
+            outgoing_links(story, "Forest"),
+            Some(vec![
+                ("Back to the cave".to_string(), "Cave".to_string()),
+                ("Start".to_string(), "Start".to_string()),
+            ])
+        );
+    }
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn stats(story_id: i64, stars: f64, played_at: Option<i64>) -> StoryStats {
+        StoryStats {
+            story_id,
+            rating: Some(stars),
+            played_at,
+        }
+    }
+
+    #[test]
+    fn highly_rated_unplayed_stories_are_recommended_first() {
+        let now = 100 * DAY;
+        let stories = [stats(1, 1.0, Some(now - DAY)), stats(2, 5.0, None)];
+        let rng = SeededRng::new(7);
+
+        let picks: Vec<i64> = (0..100)
+            .filter_map(|_| recommend(&stories, now, &rng))
+            .collect();
+        assert_eq!(picks.len(), 100);
+        // Weighted 500 against 13.
+        assert!(picks.iter().filter(|&&story_id| story_id == 2).count() > 90);
+    }
+
+    #[test]
+    fn stories_played_right_now_are_only_recommended_when_alone() {
+        let now = 100 * DAY;
+        let stories = [stats(1, 5.0, Some(now)), stats(2, 1.0, Some(now - DAY))];
+        let rng = SeededRng::new(7);
+
+        for _ in 0..20 {
+            assert_eq!(recommend(&stories, now, &rng), Some(2));
+        }
+        assert_eq!(recommend(&stories[..1], now, &rng), Some(1));
+        assert_eq!(recommend(&[], now, &rng), None);
+    }
+
+    #[test]
+    fn authors_are_read_from_the_story_data_first() {
+        let story = ":: StoryData\n{\"start\": \"Start\", \"author\": \" Ada \"}\n\n:: Author\nGrace\n\n:: Start\nHi\n";
+        assert_eq!(story_author(story).as_deref(), Some("Ada"));
+    }
+
+    #[test]
+    fn authors_are_read_from_the_author_passage() {
+        let story = ":: StoryData\n{\"start\": \"Start\", \"author\": \"\"}\n\n:: Author\n\n  Grace  \nHopper\n\n:: Start\nHi\n";
+        assert_eq!(story_author(story).as_deref(), Some("Grace"));
+    }
+
+    #[test]
+    fn stories_can_have_no_author() {
+        assert_eq!(story_author(":: Start\nHi\n"), None);
+        assert_eq!(story_author(":: Author\n\n:: Start\nHi\n"), None);
+    }
+
+    fn path(titles: &[&str]) -> Vec<String> {
+        titles.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn paths_following_links_can_be_replayed() {
+        let story = story(":: Start\n[[Cave]]\n\n:: Cave\n[[Lake]]\n\n:: Lake\nWet\n");
+
+        assert_eq!(
+            path_mismatch(&story, &path(&["Start", "Cave", "Lake"])),
+            None
+        );
+        assert_eq!(path_mismatch(&story, &path(&["Cave"])), None);
+        assert_eq!(path_mismatch(&story, &[]), None);
+    }
+
+    #[test]
+    fn paths_through_removed_passages_or_links_mismatch() {
+        let story = story(":: Start\n[[Cave]]\n\n:: Cave\n[[Lake]]\n\n:: Lake\nWet\n");
+
+        assert_eq!(
+            path_mismatch(&story, &path(&["Start", "Forest", "Lake"])).as_deref(),
+            Some("There is no passage named `Forest` anymore.")
+        );
+        assert_eq!(
+            path_mismatch(&story, &path(&["Start", "Lake"])).as_deref(),
+            Some("`Start` doesn't link to `Lake`.")
+        );
+    }
+
+    #[test]
+    fn parse_errors_without_position_are_a_message() {
+        assert_eq!(
+            parse_error_message("story", "Parsing requires more data"),
+            "The story can't be read, is it in the twee 3 format?"
+        );
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let text = "héllo\nwörld";
+        assert_eq!(line_column(text, 0), (1, 1));
+        assert_eq!(line_column(text, text.find('l').unwrap()), (1, 3));
+        assert_eq!(line_column(text, text.find('r').unwrap()), (2, 3));
+        assert_eq!(line_column(text, text.len()), (2, 6));
+        assert_eq!(line_context(text, 2, 3), "1 | héllo\n2 | wörld\n  |   ^");
+    }
+
+    #[test]
+    fn context_is_limited_to_the_lines_around() {
+        let text: Vec<String> = (1..=12).map(|number| format!("line {number}")).collect();
+        let text = text.join("\n");
+        assert_eq!(
+            line_context(&text, 9, 2),
+            " 7 | line 7\n 8 | line 8\n 9 | line 9\n   |  ^\n10 | line 10\n11 | line 11"
+        );
+        assert_eq!(
+            line_context(&text, 1, 1),
+            "1 | line 1\n  | ^\n2 | line 2\n3 | line 3"
+        );
+    }
+
+    #[test]
+    fn long_lines_are_truncated() {
+        let long = "é".repeat(MAX_PARSE_ERROR_LINE_LENGTH + 10);
+        let shown = "é".repeat(MAX_PARSE_ERROR_LINE_LENGTH);
+
+        // The column right after the truncated line still gets a caret.
+        let caret = format!("  | {}^", " ".repeat(MAX_PARSE_ERROR_LINE_LENGTH));
+        assert_eq!(
+            line_context(&long, 1, MAX_PARSE_ERROR_LINE_LENGTH + 1),
+            format!("1 | {shown}\n{caret}")
+        );
+        // Further, it would point at something not shown.
+        assert_eq!(
+            line_context(&long, 1, MAX_PARSE_ERROR_LINE_LENGTH + 2),
+            format!("1 | {shown}")
+        );
+    }
+
+    #[test]
+    fn backticks_do_not_close_the_context() {
+        assert_eq!(line_context("a `b`", 1, 3), "1 | a 'b'\n  |   ^");
+    }
+
+    #[test]
+    fn panics_are_caught_with_their_message() {
+        assert_eq!(catch_panic(|| 42), Ok(42));
+        assert_eq!(
+            catch_panic(|| panic!("static message")),
+            Err::<(), _>("static message".to_string())
+        );
+        let line = 7;
+        assert_eq!(
+            catch_panic(|| panic!("formatted at line {line}")),
+            Err::<(), _>("formatted at line 7".to_string())
+        );
+        assert_eq!(
+            catch_panic(|| std::panic::panic_any(42)),
+            Err::<(), _>("The story couldn't be parsed".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_stories_are_reported() {
+        let report = parse_and_validate("Hello\n:: Start\nX").unwrap();
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].starts_with("The story can't be read at line 1, column 1,"));
+
+        let report = parse_and_validate(
+            ":: StoryTitle\nStory\n\n:: StoryData\n{\"start\": \"Start\"}\n\n:: Start\nHello\n",
+        )
+        .unwrap();
+        assert_eq!(report.errors, Vec::<String>::new());
+    }
+
+    fn story(passages: &str) -> Story<String> {
+        let content =
+            format!(":: StoryTitle\nStory\n\n:: StoryData\n{{\"start\": \"Start\"}}\n\n{passages}");
+        Story::try_from(content).unwrap()
+    }
+
+    fn variables(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn set_variables_are_printed() {
+        let variables = variables(&[("name", "Ada"), ("gold", "12")]);
+        assert_eq!(
+            print_variables("(print: $name) has (print:$gold) gold.", &variables),
+            "Ada has 12 gold."
+        );
+    }
+
+    #[test]
+    fn unset_variables_are_printed_empty() {
+        let variables = variables(&[("name", "Ada")]);
+        assert_eq!(
+            print_variables("You have (print: $gold) gold.", &variables),
+            "You have  gold."
+        );
+        // Only variables are printed.
+        assert_eq!(
+            print_variables("(print: \"hello\") (print: $name", &variables),
+            "(print: \"hello\") (print: $name"
+        );
+    }
+
+    /// The ending passage of the story, that the choices before lead to.
+    const ENDING: &str =
+        "The dragon wakes up.\n(if: $sword)[You slay it.](else-if: $gold >= 10)[You pay it off.](else:)[It eats you.]";
+
+    #[test]
+    fn endings_depend_on_the_variables() {
+        let mut with_sword = variables(&[("sword", "true")]);
+        assert_eq!(
+            run_macros(ENDING, &mut with_sword),
+            "The dragon wakes up.\nYou slay it."
+        );
+
+        let mut with_gold = variables(&[("gold", "12")]);
+        assert_eq!(
+            run_macros(ENDING, &mut with_gold),
+            "The dragon wakes up.\nYou pay it off."
+        );
+
+        let mut empty_handed = variables(&[("sword", "false")]);
+        assert_eq!(
+            run_macros(ENDING, &mut empty_handed),
+            "The dragon wakes up.\nIt eats you."
+        );
+    }
+
+    #[test]
+    fn sets_are_seen_by_the_conditions_after_them() {
+        let mut variables = HashMap::new();
+        let text =
+            "(set: $sword to true)(if: $sword is \"true\")[Sharp.](if: not $shield)[ Exposed.]";
+
+        assert_eq!(run_macros(text, &mut variables), "Sharp. Exposed.");
+        assert_eq!(variables["sword"], "true");
+    }
+
+    #[test]
+    fn effects_set_and_change_variables() {
+        let mut variables = variables(&[("gold", "10")]);
+        apply_effects(
+            "$gold -= 4, $sword to true, $name to \"Ann, the brave\", $xp += 2.5",
+            &mut variables,
+        );
+
+        assert_eq!(variables["gold"], "6");
+        assert_eq!(variables["sword"], "true");
+        assert_eq!(variables["name"], "Ann, the brave");
+        assert_eq!(variables["xp"], "2.5");
+    }
+
+    #[test]
+    fn malformed_effects_are_skipped() {
+        let mut variables = variables(&[("gold", "10"), ("name", "Ann")]);
+        apply_effects(
+            "$gold -= lots, gold to 3, $name += 1, nonsense",
+            &mut variables,
+        );
+
+        assert_eq!(
+            variables,
+            self::variables(&[("gold", "10"), ("name", "Ann")])
+        );
+    }
+
+    #[test]
+    fn choices_are_available_while_their_requirement_holds() {
+        let story = story(
+            ":: Start\n(requires: $gold >= 10)(effects: $gold -= 10)[[Buy the sword->Shop]]\n[[Leave]]\n",
+        );
+        let passage = story.get_passage("Start").unwrap();
+        let choices = choices(&passage);
+        let mut variables = variables(&[("gold", "15")]);
+        assert!(choices[0].available(&variables));
+        assert!(choices[1].available(&variables));
+
+        apply_effects(choices[0].effects.unwrap(), &mut variables);
+
+        assert_eq!(variables["gold"], "5");
+        assert!(!choices[0].available(&variables));
+        assert!(choices[1].available(&variables));
+    }
+
+    #[test]
+    fn choices_without_requirement_are_always_available() {
+        let story = story(":: Start\n[[Leave]]\n");
+        let passage = story.get_passage("Start").unwrap();
+
+        assert!(choices(&passage)[0].available(&HashMap::new()));
+    }
+
+    #[test]
+    fn passages_only_have_their_own_warnings() {
+        let story = ":: StoryData\n{\"start\": \"Start\", \"warnings\": [\"spiders\", 3]}\n\n:: Start [cw:dark]\nHi\n\n:: Cave [cw:bats cw:dark checkpoint]\nDark\n";
+
+        assert_eq!(content_warnings(story, "Start"), ["dark"]);
+        assert_eq!(content_warnings(story, "Cave"), ["bats", "dark"]);
+        assert!(content_warnings(story, "Nowhere").is_empty());
+        assert_eq!(story_warnings(story), ["spiders"]);
+        assert!(story_warnings(":: Start\nHi\n").is_empty());
+    }
+
+    #[test]
+    fn every_passage_is_a_save_point_without_checkpoints() {
+        let story = ":: Start\n[[Cave]]\n\n:: Cave [dark]\nDark\n";
+
+        assert!(is_save_point(story, "Start"));
+        assert!(is_save_point(story, "Cave"));
+    }
+
+    #[test]
+    fn only_checkpoints_are_save_points_when_the_story_has_some() {
+        let story = ":: Start\n[[Cave]]\n\n:: Cave [dark checkpoint]\n[[Lake]]\n\n:: Lake\nWet\n";
+
+        assert!(!is_save_point(story, "Start"));
+        assert!(is_save_point(story, "Cave"));
+        assert!(!is_save_point(story, "Lake"));
+        assert!(!is_save_point(story, "Nowhere"));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn any_text_is_validated_without_panicking(content in "\\PC*") {
+            parse_and_validate(&content).unwrap();
+        }
+
+        #[test]
+        fn any_twee_like_text_is_validated_without_panicking(
+            content in "(::[ a-zA-Z\\[\\]{}\":,]{0,12}\n([^\n]|\\[\\[|\\]\\]|->|<-|\\(set:){0,24}\n){0,6}"
+        ) {
+            parse_and_validate(&content).unwrap();
+        }
+    }
 }